@@ -2,6 +2,9 @@ extern crate cc;
 
 fn main() {
     println!("cargo:rerun-if-changed=src/cpp/bridge.cpp");
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() != Ok("windows") {
+        return;
+    }
     cc::Build::new()
         .cpp(true)
         .warnings(true)
@@ -0,0 +1,379 @@
+//! `AiVoice`を持ち、ジョブを1つずつ実行するアクター。
+//!
+//! HTTPハンドラは`ACTOR.run`でジョブを積み、結果を待つ。
+//! 話者一覧などの読み取りだけで済むものは、ジョブを積まずに`HostInfo`から返す。
+
+use crate::aivoice::{
+    self, AiVoice, AiVoiceOptions, MasterControl, Speaker, SpeakerChanges, ATTACH_SESSION_TIMEOUT,
+};
+use crate::error::{Error, Result};
+use crate::icon_manager;
+use crate::settings_modifier::ExportFormat;
+
+use indexmap::IndexMap;
+use once_cell::sync::{Lazy, OnceCell};
+use serde::Serialize;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    oneshot,
+};
+use tracing::{error, info, warn};
+
+pub const DEFAULT_QUEUE_SIZE: usize = 16;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+type JobFn = Box<dyn for<'a> FnOnce(&'a mut AiVoice, Cancellation) -> BoxFuture<'a, ()> + Send>;
+
+struct Job {
+    run: JobFn,
+    cancellation: Cancellation,
+    enqueued_at: Instant,
+}
+
+/// ジョブの受け口。`serve`がパニックしても作り直した`serve`で受け続けられるように共有する。
+type JobReceiver = Arc<tokio::sync::Mutex<mpsc::Receiver<Job>>>;
+
+/// ジョブを積んだリクエストが破棄されたかどうか。
+#[derive(Debug, Clone, Default)]
+pub struct Cancellation(Arc<AtomicBool>);
+
+impl Cancellation {
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// キャンセルされていれば`Error::Cancelled`を返す。
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(Error::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// 結果を受け取る前に破棄されたら`Cancellation`を立てる。
+struct CancelOnDrop(Option<Cancellation>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if let Some(cancellation) = self.0.take() {
+            cancellation.0.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// ジョブを積まずに返せるホストの情報。ジョブが終わるたびに更新する。
+#[derive(Debug, Clone, Default)]
+pub struct HostInfo {
+    pub host_name: String,
+    pub version: Option<String>,
+    pub speakers: IndexMap<String, Speaker>,
+    pub voice_fusion: bool,
+    pub export_format: ExportFormat,
+    pub native_sampling_rate: bool,
+    /// `AiVoice::last_master_control`。まだ読んでいなければ`None`。
+    pub master_control: Option<MasterControl>,
+}
+
+impl HostInfo {
+    pub fn is_morphable(&self, base: &Speaker, target: &Speaker) -> bool {
+        aivoice::is_morphable(self.voice_fusion, base, target)
+    }
+}
+
+/// `/queue`で公開する待ち行列の状態。
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct QueueStatus {
+    pub capacity: usize,
+    /// 実行を待っているジョブの数。実行中のジョブは含まない。
+    pub depth: usize,
+    /// ジョブを実行中かどうか。
+    pub busy: bool,
+    /// 直前に実行したジョブが待った時間（ミリ秒）。
+    pub last_wait_ms: u64,
+    /// 実行したジョブが待った時間の平均（ミリ秒）。
+    pub average_wait_ms: u64,
+    /// 実行したジョブの数。
+    pub completed: u64,
+    /// 実行する前にリクエストが破棄されたジョブの数。
+    pub cancelled: u64,
+    /// 待ち行列がいっぱいで受け付けなかったジョブの数。
+    pub rejected: u64,
+    #[serde(skip)]
+    total_wait: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct Actor {
+    sender: mpsc::Sender<Job>,
+    status: Arc<Mutex<QueueStatus>>,
+    info: Arc<Mutex<HostInfo>>,
+}
+
+impl Actor {
+    /// `f`をアクターで実行し、結果を待つ。
+    ///
+    /// 返されたFutureが結果を受け取る前に破棄されると`Cancellation`が立つ。
+    /// まだ実行されていないジョブは実行せずに捨てる。
+    pub async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: for<'a> FnOnce(&'a mut AiVoice, Cancellation) -> BoxFuture<'a, Result<T>>
+            + Send
+            + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let cancellation = Cancellation::default();
+        let job = Job {
+            run: Box::new(move |aivoice, cancellation| {
+                Box::pin(async move {
+                    let _ = sender.send(f(aivoice, cancellation).await);
+                })
+            }),
+            cancellation: cancellation.clone(),
+            enqueued_at: Instant::now(),
+        };
+
+        // 積んだ直後に取り出されても数が合うように、先に数えておく
+        self.status.lock().unwrap().depth += 1;
+        if let Err(e) = self.sender.try_send(job) {
+            let mut status = self.status.lock().unwrap();
+            status.depth -= 1;
+            return match e {
+                TrySendError::Full(_) => {
+                    status.rejected += 1;
+                    Err(Error::QueueFull)
+                }
+                TrySendError::Closed(_) => Err(Error::ActorUnavailable),
+            };
+        }
+
+        let mut guard = CancelOnDrop(Some(cancellation));
+        // 結果を返さずにジョブが捨てられるのは、アクターがパニックしたか止まった場合
+        let result = receiver.await.map_err(|_| Error::ActorUnavailable)?;
+        guard.0 = None;
+        result
+    }
+
+    pub fn status(&self) -> QueueStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub fn info(&self) -> HostInfo {
+        self.info.lock().unwrap().clone()
+    }
+
+    /// まだホストを起動していなければ起動してから`info`を返す。
+    pub async fn started_info(&self) -> Result<HostInfo> {
+        let info = self.info();
+        if !info.speakers.is_empty() {
+            return Ok(info);
+        }
+        // `serve`が`info`を更新するのは結果を返した後なので、ジョブの中で読んだ情報を返す
+        self.run(|aivoice, _| {
+            Box::pin(async move {
+                aivoice.ensure_started().await?;
+                Ok(host_info(aivoice))
+            })
+        })
+        .await
+    }
+}
+
+/// ジョブを受け取って実行し続ける。`idle_timeout`の間ジョブが無ければホストを終了させる。
+///
+/// `rescan_interval`ごとに話者一覧を読み直す。ホストを起動していない間は読み直さない。
+/// ホストの再起動に失敗していれば、`AiVoice::recovery_retry_at`に再起動を試す。
+/// アタッチしたA.I.Voiceで合成が`ATTACH_SESSION_TIMEOUT`の間途切れたら、エディタの状態を元に戻す。
+async fn serve(
+    mut aivoice: AiVoice,
+    receiver: JobReceiver,
+    status: Arc<Mutex<QueueStatus>>,
+    info: Arc<Mutex<HostInfo>>,
+    idle_timeout: Option<Duration>,
+    rescan_interval: Option<Duration>,
+) {
+    let mut receiver = receiver.lock().await;
+    let mut rescan = rescan_interval
+        .map(|interval| tokio::time::interval_at(tokio::time::Instant::now() + interval, interval));
+    loop {
+        let idle_remaining = idle_timeout
+            .zip(aivoice.idle_for())
+            .map(|(timeout, idle_for)| timeout.saturating_sub(idle_for));
+        let retry_at = aivoice.recovery_retry_at();
+        let session_remaining = aivoice
+            .attach_session_idle_for()
+            .map(|idle_for| ATTACH_SESSION_TIMEOUT.saturating_sub(idle_for));
+        let job = tokio::select! {
+            job = receiver.recv() => match job {
+                Some(job) => job,
+                None => break,
+            },
+            _ = tokio::time::sleep(idle_remaining.unwrap_or_default()), if idle_remaining.is_some() => {
+                info!("A.I.Voice has been idle for {:?}", idle_timeout.unwrap());
+                if let Err(e) = aivoice.stop().await {
+                    warn!("Failed to stop idle A.I.Voice: {}", e);
+                }
+                continue;
+            }
+            _ = tokio::time::sleep(session_remaining.unwrap_or_default()), if session_remaining.is_some() => {
+                if let Err(e) = aivoice.end_attach_session().await {
+                    warn!("Failed to restore A.I.Voice editor state: {}", e);
+                }
+                continue;
+            }
+            _ = tokio::time::sleep_until(retry_at.unwrap_or_else(tokio::time::Instant::now)), if retry_at.is_some() => {
+                // 待つのはジョブの外なので、その間に来たジョブは`Error::Recovering`ですぐに返る
+                let _ = aivoice.recover().await;
+                publish_info(&aivoice, &info);
+                continue;
+            }
+            _ = async { rescan.as_mut().unwrap().tick().await }, if rescan.is_some() => {
+                if aivoice.is_running() {
+                    if let Err(e) = rescan_speakers(&mut aivoice, false).await {
+                        warn!("Failed to rescan speakers: {}", e);
+                    }
+                    publish_info(&aivoice, &info);
+                }
+                continue;
+            }
+        };
+
+        let waited = job.enqueued_at.elapsed();
+        {
+            let mut status = status.lock().unwrap();
+            status.depth -= 1;
+            if job.cancellation.is_cancelled() {
+                status.cancelled += 1;
+                info!("Skipping a job cancelled after {:?} in the queue", waited);
+                continue;
+            }
+            status.busy = true;
+            status.completed += 1;
+            status.total_wait += waited;
+            status.last_wait_ms = waited.as_millis() as u64;
+            status.average_wait_ms =
+                (status.total_wait.as_millis() / status.completed as u128) as u64;
+        }
+
+        (job.run)(&mut aivoice, job.cancellation).await;
+
+        status.lock().unwrap().busy = false;
+        publish_info(&aivoice, &info);
+    }
+}
+
+/// `serve`を実行し続ける。ジョブのパニックで`serve`が終わった場合は、`AiVoice`を作り直して続ける。
+async fn supervise(
+    mut aivoice: AiVoice,
+    options: AiVoiceOptions,
+    receiver: JobReceiver,
+    status: Arc<Mutex<QueueStatus>>,
+    info: Arc<Mutex<HostInfo>>,
+) {
+    loop {
+        let task = tokio::spawn(serve(
+            aivoice,
+            receiver.clone(),
+            status.clone(),
+            info.clone(),
+            options.idle_timeout,
+            options.rescan_interval,
+        ));
+        match task.await {
+            Ok(()) => {
+                info!("Actor stopped: no more jobs can be sent");
+                return;
+            }
+            Err(e) => error!("Actor stopped unexpectedly, recreating A.I.Voice: {}", e),
+        }
+
+        status.lock().unwrap().busy = false;
+        aivoice = match AiVoice::create(&options).await {
+            Ok(aivoice) => aivoice,
+            Err(e) => {
+                error!("Failed to recreate A.I.Voice, stopping the actor: {}", e);
+                return;
+            }
+        };
+        publish_info(&aivoice, &info);
+    }
+}
+
+fn publish_info(aivoice: &AiVoice, info: &Mutex<HostInfo>) {
+    *info.lock().unwrap() = host_info(aivoice);
+}
+
+/// ジョブの中で`aivoice`から読んだ`HostInfo`。
+pub fn host_info(aivoice: &AiVoice) -> HostInfo {
+    HostInfo {
+        host_name: aivoice.host().name().to_string(),
+        version: aivoice.host_version().clone(),
+        speakers: aivoice.speakers().clone(),
+        voice_fusion: *aivoice.voice_fusion(),
+        export_format: aivoice.export_format(),
+        native_sampling_rate: *aivoice.native_sampling_rate(),
+        master_control: aivoice.last_master_control().cloned(),
+    }
+}
+
+/// 話者一覧を読み直し、変わっていれば画像も読み直す。`start`が`true`ならホストを起動してから読み直す。
+pub async fn rescan_speakers(aivoice: &mut AiVoice, start: bool) -> Result<SpeakerChanges> {
+    let changes = if start {
+        aivoice.rescan_speakers().await?
+    } else {
+        aivoice.reload_speakers().await?
+    };
+    if !changes.added.is_empty() || !changes.removed.is_empty() {
+        icon_manager::reload_icons(aivoice).await?;
+    }
+    Ok(changes)
+}
+
+static INSTANCE: OnceCell<Actor> = OnceCell::new();
+
+/// ホストを作り、アクターを起動する。`ACTOR`を初めて使う前に呼ぶ。
+pub async fn init(options: AiVoiceOptions) -> Result<()> {
+    let aivoice = AiVoice::create(&options).await?;
+
+    let (sender, receiver) = mpsc::channel(options.queue_size.max(1));
+    let status = Arc::new(Mutex::new(QueueStatus {
+        capacity: options.queue_size.max(1),
+        ..Default::default()
+    }));
+    let info = Arc::new(Mutex::new(HostInfo {
+        host_name: aivoice.host().name().to_string(),
+        voice_fusion: *aivoice.voice_fusion(),
+        ..Default::default()
+    }));
+    tokio::spawn(supervise(
+        aivoice,
+        options,
+        Arc::new(tokio::sync::Mutex::new(receiver)),
+        status.clone(),
+        info.clone(),
+    ));
+
+    INSTANCE
+        .set(Actor {
+            sender,
+            status,
+            info,
+        })
+        .expect("ACTOR is already initialized");
+    Ok(())
+}
+
+pub static ACTOR: Lazy<Actor> =
+    Lazy::new(|| INSTANCE.get().expect("ACTOR is not initialized").clone());
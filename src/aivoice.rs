@@ -1,163 +1,724 @@
-use crate::bridge::Host;
-pub use crate::bridge::{HostStatus, MergedVoiceContainer, TextEditMode, VoicePreset};
+use crate::backend::{self, temporary_phrase_dict_path, Backend, BackendOptions};
+pub use crate::bridge::{
+    HostStatus, MasterControl, MergedVoiceContainer, TextEditMode, VoicePreset, VoicePresetStyle,
+};
 use crate::error::{Error, Result};
-use crate::settings_modifier::SettingsModifier;
+use crate::host_thread::HostThread;
+use crate::phrase_dictionary_journal;
+use crate::phrase_pool::PhrasePool;
+use crate::registry::Registry;
+use crate::settings_modifier::{
+    EditorSettings, ExportFormat, SettingsModifier, EXPORT_SAMPLING_RATES,
+};
+use crate::supervisor::{HostState, Supervisor, SUPERVISOR};
+use crate::watchdog::{Watchdog, WatchdogOptions};
 
 use derive_getters::Getters;
-use fxhash::FxHasher;
 use indexmap::IndexMap;
-use num_derive::FromPrimitive;
-use once_cell::sync::Lazy;
+use serde::Serialize;
 use std::{
-    hash::{Hash as _, Hasher as _},
-    path::PathBuf,
-    str::FromStr,
+    path::{Path, PathBuf},
     sync::Arc,
 };
-use strum::{Display, EnumString};
-use tasklist::{kill as taskkill, tasklist};
-use tokio::{io::AsyncWriteExt as _, sync::Mutex};
-use tracing::info;
+use tokio::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
 #[derive(Debug, Getters)]
-pub struct AiVoice {
-    host: Host,
+pub struct AiVoice<B: Backend + ?Sized = dyn Backend> {
+    host: Arc<B>,
+    host_thread: HostThread,
+    /// `setup`の時点のA.I.Voiceのバージョン。
+    host_version: Option<String>,
     settings_modifier: Option<SettingsModifier>,
     speakers: IndexMap<String, Speaker>,
+    #[getter(skip)]
+    watchdog: Watchdog,
+    #[getter(skip)]
+    editor_settings: EditorSettings,
+    #[getter(skip)]
+    restart_editor: bool,
+    /// リクエストされたサンプリング周波数で直接書き出せるように、A.I.Voiceを起動し直すかどうか。
+    native_sampling_rate: bool,
+    #[getter(skip)]
+    sampling_rate_streak: SamplingRateStreak,
+    #[getter(skip)]
+    registry_path: PathBuf,
+    /// 別の話者のスタイルとボイスフュージョンでモーフィングできるかどうか。
+    voice_fusion: bool,
+    #[getter(skip)]
+    user_presets: bool,
+    #[getter(skip)]
+    phrase_pool: PhrasePool,
+    /// `setup`が済んでいて、ホストを使える状態かどうか。
+    #[getter(skip)]
+    running: bool,
+    /// 起動中のA.I.Voiceにアタッチせず、自分で起動したかどうか。
+    #[getter(skip)]
+    launched_editor: bool,
+    #[getter(skip)]
+    last_used: Instant,
+    /// 再起動に失敗して、次の再起動を待っている間だけ`Some`。
+    #[getter(skip)]
+    recovery: Option<Recovery>,
+    /// ホストの状態を公開する先。普通は`SUPERVISOR`。
+    #[getter(skip)]
+    supervisor: Arc<Supervisor>,
+    /// 起動中のA.I.Voiceにアタッチしている場合、最初の合成の前に保存したエディタの状態。
+    #[getter(skip)]
+    attach_session: Option<EditorSnapshot>,
+    /// 最後に読み書きしたマスターコントロール。ホストを使わずに合成結果のキャッシュを引くのに使う。
+    #[getter(skip)]
+    last_master_control: Option<MasterControl>,
+}
+
+/// アタッチしている場合、この間合成が無ければエディタの状態とフレーズ辞書をユーザーのものに戻す。
+pub const ATTACH_SESSION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 書き出す周波数を変えるのは、同じ周波数のリクエストがこの回数続いた場合だけ。
+pub const SAMPLING_RATE_STREAK: usize = 3;
+
+/// 直前のリクエストと同じ周波数が続いた回数。
+///
+/// 周波数が交互に変わる場合にA.I.Voiceの再起動を繰り返さないように、続いた場合だけ書き出す周波数を変える。
+#[derive(Debug, Clone, Copy, Default)]
+struct SamplingRateStreak {
+    sampling_rate: u32,
+    count: usize,
+}
+
+impl SamplingRateStreak {
+    /// `sampling_rate`のリクエストを数え、`SAMPLING_RATE_STREAK`回続いていれば`true`。
+    fn observe(&mut self, sampling_rate: u32) -> bool {
+        if self.sampling_rate == sampling_rate {
+            self.count += 1;
+        } else {
+            *self = Self {
+                sampling_rate,
+                count: 1,
+            };
+        }
+        self.count >= SAMPLING_RATE_STREAK
+    }
+}
+
+/// 再起動の試行回数と、次に試す時刻。
+#[derive(Debug, Clone, Copy)]
+struct Recovery {
+    attempt: u32,
+    retry_at: Instant,
+}
+
+/// 合成を始める前に保存し、合成が終わったら戻すエディタの状態。
+#[derive(Debug, Clone)]
+struct EditorSnapshot {
+    text: String,
+    preset_name: String,
 }
 
 #[derive(Debug, Clone, Getters)]
 pub struct Speaker {
+    /// 話者IDのファイルと`AiVoice::speakers`でのキー。ボイスはボイス名、ユーザーのボイスプリセットは`preset/`とプリセット名。
+    key: String,
     display_name: String,
+    /// ボイス名。
     internal_name: String,
-    id: u32,
+    /// 先頭は常にノーマル。
     styles: Vec<Style>,
+    /// ユーザーのボイスプリセットの場合、そのプリセット。合成時の基準にする。
+    preset: Option<VoicePreset>,
+    /// `/speakers`に表示しない。
+    hidden: bool,
+    /// `/speakers`での並び順。
+    order: Option<i32>,
+}
+
+/// 話者のスタイル。
+///
+/// `name`はボイスプリセットのスタイル名（`J`など）で、ノーマルは`N`。
+/// A.I.Voiceのボイスによってスタイルは違うので、ボイスプリセットから読み取る。
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+pub struct Style {
+    name: String,
+    display_name: String,
+    id: u32,
 }
 
-#[derive(Debug, Copy, Clone, EnumString, Display, FromPrimitive, PartialEq, Eq, Hash)]
-#[repr(u8)]
-pub enum Style {
-    #[strum(serialize = "N")]
-    Normal,
-    #[strum(serialize = "J")]
-    Joy,
-    #[strum(serialize = "A")]
-    Anger,
-    #[strum(serialize = "S")]
-    Sorrow,
+pub const NORMAL_STYLE: &str = "N";
+
+/// スタイル名の既定の表示名。知らないスタイルはスタイル名をそのまま表示する。
+fn default_style_display_name(name: &str) -> &str {
+    match name {
+        NORMAL_STYLE => "ノーマル",
+        "J" => "喜び",
+        "A" => "怒り",
+        "S" => "悲しみ",
+        _ => name,
+    }
 }
 
 impl Style {
-    pub fn to_japanese(self) -> &'static str {
-        match self {
-            Self::Normal => "ノーマル",
-            Self::Joy => "喜び",
-            Self::Anger => "怒り",
-            Self::Sorrow => "悲しみ",
-        }
+    pub fn is_normal(&self) -> bool {
+        self.name == NORMAL_STYLE
     }
 }
 
 impl Speaker {
-    pub fn new(preset: VoicePreset) -> Self {
-        let internal_name = preset.voice_name;
-        let display_name = preset.preset_name;
-        let mut hasher = FxHasher::default();
-        internal_name.hash(&mut hasher);
-        let id = ((hasher.finish()) % u16::MAX as u64) as u32;
-        let mut styles: Vec<_> = preset
-            .styles
-            .iter()
-            .map(|x| Style::from_str(&x.name).unwrap())
+    /// `registry`に登録されたIDと表示の設定を使う。まだ登録されていないスタイルにはIDを割り当てる。
+    pub fn new(preset: VoicePreset, registry: &mut Registry) -> Result<Self> {
+        let mut style_names = vec![NORMAL_STYLE.to_string()];
+        for style in preset.styles {
+            if style.name.is_empty() || style_names.contains(&style.name) {
+                warn!(
+                    "Ignoring style {:?} of {}: empty or duplicated",
+                    style.name, preset.voice_name
+                );
+                continue;
+            }
+            style_names.push(style.name);
+        }
+
+        Self::build(
+            preset.voice_name.clone(),
+            preset.preset_name,
+            preset.voice_name,
+            style_names,
+            None,
+            registry,
+        )
+    }
+
+    /// ユーザーのボイスプリセットを話者にする。スタイルは`voice`と同じで、ノーマルはプリセットの感情パラメータを使う。
+    pub fn from_user_preset(
+        preset: VoicePreset,
+        voice: &Speaker,
+        registry: &mut Registry,
+    ) -> Result<Self> {
+        Self::build(
+            format!("preset/{}", preset.preset_name),
+            preset.preset_name.clone(),
+            voice.internal_name.clone(),
+            voice
+                .styles
+                .iter()
+                .map(|style| style.name.clone())
+                .collect(),
+            Some(preset),
+            registry,
+        )
+    }
+
+    fn build(
+        key: String,
+        display_name: String,
+        internal_name: String,
+        style_names: Vec<String>,
+        preset: Option<VoicePreset>,
+        registry: &mut Registry,
+    ) -> Result<Self> {
+        let entry = registry.assign(&key, &style_names)?;
+        let styles = style_names
+            .into_iter()
+            .map(|name| Style {
+                display_name: entry
+                    .style_names
+                    .get(&name)
+                    .cloned()
+                    .unwrap_or_else(|| default_style_display_name(&name).to_string()),
+                id: entry.styles[&name],
+                name,
+            })
             .collect();
-        styles.insert(0, Style::Normal);
-        Self {
-            display_name,
+        Ok(Self {
+            key,
+            display_name: entry.name.unwrap_or(display_name),
             internal_name,
-            id,
             styles,
-        }
+            preset,
+            hidden: entry.hidden,
+            order: entry.order,
+        })
+    }
+
+    /// `style`で合成するときの感情パラメータ。
+    ///
+    /// ノーマルはすべて0にした状態で、ユーザーのボイスプリセットの場合はプリセットの値を使う。
+    pub fn style_weights(&self, style: &Style) -> Vec<VoicePresetStyle> {
+        self.styles
+            .iter()
+            .filter(|x| !x.is_normal())
+            .map(|x| VoicePresetStyle {
+                name: x.name.clone(),
+                value: if style.is_normal() {
+                    self.preset
+                        .iter()
+                        .flat_map(|preset| &preset.styles)
+                        .find(|preset_style| preset_style.name == x.name)
+                        .map_or(0.0, |preset_style| preset_style.value)
+                } else if x == style {
+                    1.0
+                } else {
+                    0.0
+                },
+            })
+            .collect()
     }
 
     pub fn uuid(&self) -> Uuid {
-        Uuid::new_v5(&Uuid::NAMESPACE_OID, self.internal_name.as_bytes())
+        Uuid::new_v5(&Uuid::NAMESPACE_OID, self.key.as_bytes())
     }
 }
 
+/// スタイルIDから話者とスタイルを探す。
+pub fn find_style(
+    speakers: &IndexMap<String, Speaker>,
+    style_id: u32,
+) -> Option<(&Speaker, &Style)> {
+    speakers.values().find_map(|speaker| {
+        speaker
+            .styles
+            .iter()
+            .find(|style| style.id == style_id)
+            .map(|style| (speaker, style))
+    })
+}
+
+/// `base`のスタイルを`target`のスタイルとモーフィングできるかどうか。
+///
+/// ボイスフュージョンを使わない場合（`SELF_ONLY`）は、VOICEVOXと同じく`speaker_uuid`が同じ話者同士だけ。
+/// ユーザーのボイスプリセットは、元のボイスと声が同じでも別の話者として扱う。
+pub fn is_morphable(voice_fusion: bool, base: &Speaker, target: &Speaker) -> bool {
+    voice_fusion || base.uuid() == target.uuid()
+}
+
+/// `sampling_rate`で出力するときにA.I.Voiceが書き出す形式。
+///
+/// `native_sampling_rate`の場合、A.I.Voiceが書き出せる周波数ならその周波数で書き出す。
+pub fn export_format_for(
+    current: ExportFormat,
+    native_sampling_rate: bool,
+    sampling_rate: u32,
+) -> ExportFormat {
+    if native_sampling_rate && EXPORT_SAMPLING_RATES.contains(&sampling_rate) {
+        ExportFormat {
+            sampling_rate,
+            ..current
+        }
+    } else {
+        current
+    }
+}
+
+/// `AiVoice::reload_speakers`で増減した話者のキー。
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SpeakerChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+fn add_speaker(speakers: &mut IndexMap<String, Speaker>, speaker: Speaker) {
+    info!(
+        "  {} ({}, {})",
+        speaker.display_name(),
+        speaker
+            .styles
+            .iter()
+            .map(|style| format!("{}: {}", style.name, style.id))
+            .collect::<Vec<_>>()
+            .join(", "),
+        speaker.uuid().hyphenated()
+    );
+    speakers.insert(speaker.key.clone(), speaker);
+}
+
 #[derive(Debug, Clone, Getters)]
 pub struct Phrase {
     uuid: Uuid,
     pronunciation: String,
+    /// フレーズ辞書を使えない場合にエディタに設定する、読みのカタカナ。
+    text: String,
 }
 
 impl Phrase {
-    pub fn new(pronunciation: String) -> Self {
+    /// 同じ読みなら同じ`uuid`になる。`uuid`をエディタのテキストにし、フレーズ辞書で読みを指定する。
+    pub fn new(pronunciation: String, text: String) -> Self {
         Self {
-            uuid: Uuid::new_v4(),
+            uuid: Uuid::new_v5(&Uuid::NAMESPACE_OID, pronunciation.as_bytes()),
             pronunciation,
+            text,
         }
     }
 }
 
-impl AiVoice {
-    pub fn new() -> Self {
+impl<B: Backend + ?Sized + 'static> AiVoice<B> {
+    /// `host`は`host_thread`で作ったものを渡す。
+    pub fn new(host: Box<B>, host_thread: HostThread, options: &AiVoiceOptions) -> Self {
+        let native_sampling_rate = options.native_sampling_rate
+            && options.restart_editor
+            && host.editor_settings_path().is_some();
+        if options.native_sampling_rate && !native_sampling_rate {
+            warn!("native_sampling_rate requires --restart-editor and a local A.I.Voice, ignoring");
+        }
         Self {
-            host: Host::new(),
+            host: Arc::from(host),
+            host_thread,
+            host_version: None,
             settings_modifier: None,
             speakers: IndexMap::new(),
+            watchdog: Watchdog::new(options.watchdog.clone()),
+            editor_settings: options.editor.clone(),
+            restart_editor: options.restart_editor,
+            native_sampling_rate,
+            sampling_rate_streak: SamplingRateStreak::default(),
+            registry_path: options
+                .registry_path
+                .clone()
+                .unwrap_or_else(Registry::default_path),
+            voice_fusion: options.voice_fusion,
+            user_presets: options.user_presets,
+            phrase_pool: PhrasePool::new(options.phrase_pool_size),
+            running: false,
+            launched_editor: false,
+            last_used: Instant::now(),
+            recovery: None,
+            supervisor: SUPERVISOR.clone(),
+            attach_session: None,
+            last_master_control: None,
         }
     }
 
-    pub fn temporary_phrase_dict_path() -> PathBuf {
-        process_path::get_executable_path()
-            .unwrap()
-            .parent()
-            .unwrap()
-            .join("temporary_phrase_dict.pdic")
+    /// `SUPERVISOR`の代わりに`supervisor`に状態を公開する。
+    #[cfg(test)]
+    pub fn with_supervisor(mut self, supervisor: Arc<Supervisor>) -> Self {
+        self.supervisor = supervisor;
+        self
     }
 
-    pub async fn setup(&mut self) -> Result<()> {
-        let mut tasks = unsafe { tasklist().into_iter() };
-        if let Some((_, aivoice_process_id)) =
-            tasks.find(|(task_name, _)| task_name.to_lowercase() == "aivoiceeditor.exe")
-        {
-            info!("A.I.Voice process id: {}", aivoice_process_id);
+    /// `f`をホストのスレッドで実行し、`timeout`以内に終わらなければ`Error::Timeout`を返す。
+    ///
+    /// タイムアウトしても呼び出しは止められないので、ホストが応答するまで後の呼び出しは待たされる。
+    async fn run_with_deadline<T, F>(&self, api: &str, timeout: Duration, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&B) -> Result<T> + Send + 'static,
+    {
+        let host = self.host.clone();
+        match tokio::time::timeout(timeout, self.host_thread.run(move || f(&host))).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(Error::ApiFailed(format!("{}: panicked", api))),
+            Err(_) => Err(Error::Timeout(api.to_string())),
+        }
+    }
 
-            if !unsafe { taskkill(aivoice_process_id) } {
-                return Err(Error::TerminateHostFailed);
+    /// `run_with_deadline`に加えて、タイムアウトが続いた場合はホストを終了させる。
+    async fn call_with_timeout<T, F>(&mut self, api: &str, timeout: Duration, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&B) -> Result<T> + Send + 'static,
+    {
+        match self.run_with_deadline(api, timeout, f).await {
+            Err(Error::Timeout(api)) => Err(self.hung(api).await),
+            result => {
+                self.watchdog.record_success();
+                result
+            }
+        }
+    }
+
+    async fn call<T, F>(&mut self, api: &str, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&B) -> Result<T> + Send + 'static,
+    {
+        let timeout = self.watchdog.options().call_timeout;
+        self.call_with_timeout(api, timeout, f).await
+    }
+
+    /// タイムアウトを記録し、上限に達していればホストを終了させる。
+    ///
+    /// 終了したホストは次の呼び出しで`reconnect_if_required`が再起動する。
+    async fn hung(&mut self, api: String) -> Error {
+        if self.watchdog.record_hang(&api) {
+            error!("A.I.Voice seems to be hung, terminating it");
+            // ホストのスレッドはハングした呼び出しで塞がっているので、プロセスの終了は別のスレッドで行う
+            let host = self.host.clone();
+            let timeout = self.watchdog.options().call_timeout;
+            let terminate = tokio::task::spawn_blocking(move || host.terminate_host());
+            match tokio::time::timeout(timeout, terminate).await {
+                Ok(Ok(Ok(()) | Err(Error::ProcessNotFound))) => {}
+                Ok(Ok(Err(e))) => error!("Failed to terminate A.I.Voice: {}", e),
+                Ok(Err(e)) => error!("Failed to terminate A.I.Voice: {}", e),
+                Err(_) => error!("Timed out terminating A.I.Voice"),
             }
-            info!("Terminated A.I.Voice");
-        } else {
-            info!("A.I.Voice is not running");
         }
+        Error::Timeout(api)
+    }
 
-        self.write_temporary_phrase_dict(None).await?;
+    async fn status(&mut self) -> Result<HostStatus> {
+        self.call("Status", |host| Ok(host.status())).await
+    }
 
-        let mut settings_modifier = SettingsModifier::new();
+    /// A.I.Voiceを終了させ、設定ファイルを書き換える。
+    ///
+    /// `--restart-editor`が指定されていない場合は何もせず、起動中のA.I.Voiceをそのまま使う。
+    pub async fn prepare_editor(&mut self) -> Result<()> {
+        let Some(settings_path) = self.host.editor_settings_path() else {
+            return Ok(());
+        };
+        if !self.restart_editor {
+            info!("Attaching to A.I.Voice without restarting it");
+            return Ok(());
+        }
 
-        settings_modifier
-            .modify(&Self::temporary_phrase_dict_path())
+        match self
+            .call("TerminateHost", |host| host.terminate_host())
+            .await
+        {
+            Ok(()) => {}
+            Err(Error::ProcessNotFound) => info!("A.I.Voice is not running"),
+            Err(e) => return Err(e),
+        }
+
+        // 書き換えるまではユーザーのフレーズ辞書を指しているので、先に設定ファイルを書き換える
+        let editor_settings = self.editor_settings.clone();
+        self.settings_modifier
+            .get_or_insert_with(|| SettingsModifier::new(editor_settings, settings_path))
+            .modify(&temporary_phrase_dict_path())
             .await?;
-        self.settings_modifier = Some(settings_modifier);
+
+        self.clear_phrase_dictionary().await?;
 
         tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
 
+        Ok(())
+    }
+
+    pub async fn setup(&mut self) -> Result<()> {
+        self.supervisor.set_state(HostState::Starting);
+
+        self.launch().await?;
+
+        self.reload_speakers().await?;
+
+        self.ensure_voicevox_preset().await?;
+
+        self.host_version = Some(self.call("Version", |host| host.version()).await?);
+        self.running = true;
+        self.supervisor.set_state(HostState::Ready);
+
+        Ok(())
+    }
+
+    /// ホストを起動していなければ`setup`する。最後に使われた時刻を更新する。
+    pub async fn ensure_started(&mut self) -> Result<()> {
+        self.last_used = Instant::now();
+        if self.recovery.is_some() {
+            return Err(Error::Recovering);
+        }
+        if self.running {
+            return Ok(());
+        }
+
+        info!("Starting A.I.Voice on demand");
+        let result = self.setup().await;
+        if let Err(e) = &result {
+            error!("Failed to start A.I.Voice: {}", e);
+            self.supervisor.set_state(HostState::Failed {
+                error: e.to_string(),
+            });
+        }
+        result
+    }
+
+    /// 今の設定でA.I.Voiceが書き出す形式。
+    pub fn export_format(&self) -> ExportFormat {
+        self.editor_settings.export
+    }
+
+    /// `sampling_rate`で直接書き出せるように、必要ならA.I.Voiceを止めて書き出す形式を変える。
+    ///
+    /// 同じ周波数のリクエストが`SAMPLING_RATE_STREAK`回続くまでは今の形式のまま書き出し、呼び出し側で変換する。
+    /// 止めたA.I.Voiceは次の呼び出しで新しい設定で起動し直す。
+    pub async fn prefer_sampling_rate(&mut self, sampling_rate: u32) -> Result<()> {
+        let stable = self.sampling_rate_streak.observe(sampling_rate);
+        let export = export_format_for(
+            self.editor_settings.export,
+            self.native_sampling_rate,
+            sampling_rate,
+        );
+        if export == self.editor_settings.export {
+            return Ok(());
+        }
+        if !stable {
+            debug!(
+                "Resampling to {} Hz until the sampling rate is stable",
+                sampling_rate
+            );
+            return Ok(());
+        }
+
+        info!(
+            "Restarting A.I.Voice to export at {} Hz",
+            export.sampling_rate
+        );
+        self.stop().await?;
+        self.editor_settings.export = export;
+        Ok(())
+    }
+
+    /// 最後に使われてからの時間。ホストを起動していない場合は`None`。
+    pub fn idle_for(&self) -> Option<Duration> {
+        self.running.then(|| self.last_used.elapsed())
+    }
+
+    /// ホストを終了させ、書き換えた設定を元に戻す。次に必要になったときに`ensure_started`で起動し直す。
+    pub async fn stop(&mut self) -> Result<()> {
+        if !self.running {
+            return Ok(());
+        }
+
+        info!("Stopping A.I.Voice");
+        self.shutdown().await?;
+        self.settings_modifier = None;
+        self.running = false;
+        self.launched_editor = false;
+        self.supervisor.set_state(HostState::Standby);
+
+        Ok(())
+    }
+
+    /// 設定を書き換えてからホストを起動し、接続する。
+    async fn launch(&mut self) -> Result<()> {
+        if self.host.editor_settings_path().is_some() {
+            self.prepare_editor().await?;
+        } else if self.restart_editor {
+            self.clear_phrase_dictionary().await?;
+        }
+
         self.start_and_connect().await?;
 
-        self.host.set_text_edit_mode(TextEditMode::Text)?;
+        self.call("TextEditMode=", |host| {
+            host.set_text_edit_mode(TextEditMode::Text)
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    /// 話者一覧を読み直す。すべて読み込めてから入れ替えるので、失敗した場合は元の一覧のまま。
+    pub async fn reload_speakers(&mut self) -> Result<SpeakerChanges> {
+        let voice_names = self.call("VoiceNames", |host| host.speakers()).await?;
 
-        let speakers = self.host.speakers()?;
+        // 手で編集されていても反映されるように、読み直すたびに読み込む
+        let mut registry = Registry::load(&self.registry_path)?;
+        info!("Speakers:");
+        let mut speakers = IndexMap::new();
+        let mut default_preset_names = Vec::new();
+        for voice_name in voice_names {
+            let preset = self
+                .call("GetVoicePreset", move |host| {
+                    host.get_voice_preset(&voice_name)
+                })
+                .await?;
+            default_preset_names.push(preset.preset_name.clone());
+            let speaker = Speaker::new(preset, &mut registry)?;
+            add_speaker(&mut speakers, speaker);
+        }
+        if self.user_presets {
+            self.add_user_presets(&mut speakers, &default_preset_names, &mut registry)
+                .await?;
+        }
 
-        for speaker in speakers.iter() {
-            let preset = self.host.get_voice_preset(speaker)?;
-            let speaker = Speaker::new(preset.clone());
-            self.speakers.insert(speaker.internal_name.clone(), speaker);
+        let changes = SpeakerChanges {
+            added: speakers
+                .keys()
+                .filter(|key| !self.speakers.contains_key(*key))
+                .cloned()
+                .collect(),
+            removed: self
+                .speakers
+                .keys()
+                .filter(|key| !speakers.contains_key(*key))
+                .cloned()
+                .collect(),
+        };
+        if !changes.added.is_empty() || !changes.removed.is_empty() {
+            info!(
+                "Speakers changed: added {:?}, removed {:?}",
+                changes.added, changes.removed
+            );
         }
+        self.speakers = speakers;
+        Ok(changes)
+    }
+
+    /// 話者一覧を読み直す。ホストを起動していなければ起動する。
+    pub async fn rescan_speakers(&mut self) -> Result<SpeakerChanges> {
+        if self.running {
+            self.last_used = Instant::now();
+            return self.reload_speakers().await;
+        }
+        let previous: Vec<String> = self.speakers.keys().cloned().collect();
+        self.ensure_started().await?;
+        Ok(SpeakerChanges {
+            added: self
+                .speakers
+                .keys()
+                .filter(|key| !previous.contains(key))
+                .cloned()
+                .collect(),
+            removed: previous
+                .into_iter()
+                .filter(|key| !self.speakers.contains_key(key))
+                .collect(),
+        })
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// ユーザーが作ったボイスプリセットを話者として追加する。
+    async fn add_user_presets(
+        &mut self,
+        speakers: &mut IndexMap<String, Speaker>,
+        default_preset_names: &[String],
+        registry: &mut Registry,
+    ) -> Result<()> {
+        let preset_names = self
+            .call("VoicePresetNames", |host| host.voice_preset_names())
+            .await?;
+        for preset_name in preset_names {
+            if preset_name == "AIVoiceVox"
+                || default_preset_names.contains(&preset_name)
+                || speakers.contains_key(&preset_name)
+            {
+                continue;
+            }
+            let preset = self
+                .call("GetVoicePreset", move |host| {
+                    host.get_voice_preset(&preset_name)
+                })
+                .await?;
+            let Some(voice) = speakers.get(&preset.voice_name) else {
+                warn!(
+                    "Ignoring voice preset {}: voice {} is not available",
+                    preset.preset_name, preset.voice_name
+                );
+                continue;
+            };
+            let speaker = Speaker::from_user_preset(preset, voice, registry)?;
+            add_speaker(speakers, speaker);
+        }
+        Ok(())
+    }
 
-        let voice_preset_names = self.host.voice_preset_names()?;
+    async fn ensure_voicevox_preset(&mut self) -> Result<()> {
+        let voice_preset_names = self
+            .call("VoicePresetNames", |host| host.voice_preset_names())
+            .await?;
         if !voice_preset_names.iter().any(|x| x == "AIVoiceVox") {
-            self.host.add_voice_preset(VoicePreset {
+            let preset = VoicePreset {
                 preset_name: "AIVoiceVox".to_string(),
                 voice_name: self.speakers.values().next().unwrap().internal_name.clone(),
                 volume: 1.0,
@@ -171,21 +732,26 @@ impl AiVoice {
                     base_pitch_voice_name: "".to_string(),
                     merged_voices: vec![],
                 },
-            })?;
+            };
+            self.call("AddVoicePreset", move |host| host.add_voice_preset(&preset))
+                .await?;
         }
 
         Ok(())
     }
 
-    pub async fn start_and_connect(&self) -> Result<()> {
+    pub async fn start_and_connect(&mut self) -> Result<()> {
         info!("Connecting to A.I.Voice...");
 
-        let status = self.host.status();
+        let status = self.status().await?;
 
         match status {
             HostStatus::NotRunning => {
                 info!("Starting A.I.Voice...");
-                self.host.start()?;
+                let timeout = self.watchdog.options().start_timeout;
+                self.call_with_timeout("StartHost", timeout, |host| host.start())
+                    .await?;
+                self.launched_editor = true;
             }
             HostStatus::Idle => {
                 info!("A.I.Voice is already running");
@@ -194,124 +760,643 @@ impl AiVoice {
             _ => {}
         }
 
-        loop {
-            info!("Host status: {:?}", self.host.status());
+        let start_timeout = self.watchdog.options().start_timeout;
+        tokio::time::timeout(start_timeout, async {
+            loop {
+                let status = self.status().await?;
+                info!("Host status: {:?}", status);
+
+                match status {
+                    HostStatus::NotConnected => return Ok(()),
+                    HostStatus::NotRunning | HostStatus::Error => {
+                        return Err(Error::StartHostFailed)
+                    }
+                    _ => {}
+                }
 
-            if self.host.status() == HostStatus::NotConnected {
-                break;
+                tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
             }
+        })
+        .await
+        .map_err(|_| Error::StartHostFailed)??;
 
-            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-        }
         info!("Connecting to A.I.Voice...");
-        self.host.connect()?;
+        self.call("Connect", |host| host.connect()).await?;
         info!("Connected to A.I.Voice");
 
         Ok(())
     }
 
-    pub async fn version(&self) -> Result<String> {
-        self.host.reconnect_if_required().await?;
-        self.host.version()
+    /// ホストが落ちているかどうか。
+    pub async fn is_crashed(&mut self) -> bool {
+        matches!(
+            self.status().await,
+            Ok(HostStatus::NotRunning | HostStatus::Error)
+        )
     }
 
-    pub async fn write_temporary_phrase_dict(&self, phrase: Option<&Phrase>) -> Result<()> {
-        info!(
-            "Writing temporary phrase dictionary to {}",
-            &Self::temporary_phrase_dict_path().display()
-        );
-        let mut temporary_phrase_dict =
-            tokio::fs::File::create(&Self::temporary_phrase_dict_path())
-                .await
-                .map_err(Error::WriteDictionaryFailed)?;
+    /// 落ちたホストを1回だけ再起動してみる。
+    ///
+    /// 設定ファイルの書き換えと「AIVoiceVox」プリセットの追加をやり直す。
+    /// 失敗した場合は`supervisor`のポリシーに従って次に試す時刻を決める。待つのはアクターで、
+    /// その間に来たジョブは`Error::Recovering`ですぐに失敗させる。
+    pub async fn recover(&mut self) -> Result<()> {
+        let policy = self.supervisor.policy().clone();
+        let attempt = match self.recovery {
+            Some(recovery) => recovery.attempt,
+            None => {
+                self.supervisor.crashed();
+                0
+            }
+        };
+        self.supervisor.set_state(HostState::Recovering { attempt });
+        warn!("Restarting A.I.Voice (attempt {})", attempt + 1);
+
+        match self.restart().await {
+            Ok(()) => {
+                info!("A.I.Voice restarted");
+                self.recovery = None;
+                self.supervisor.recovered();
+                Ok(())
+            }
+            Err(e) if attempt + 1 >= policy.max_attempts => {
+                error!("Gave up restarting A.I.Voice: {}", e);
+                self.recovery = None;
+                self.supervisor.set_state(HostState::Failed {
+                    error: e.to_string(),
+                });
+                Err(Error::HostUnavailable)
+            }
+            Err(e) => {
+                let delay = policy.delay(attempt);
+                warn!(
+                    "Failed to restart A.I.Voice: {}, retrying in {:?}",
+                    e, delay
+                );
+                self.recovery = Some(Recovery {
+                    attempt: attempt + 1,
+                    retry_at: Instant::now() + delay,
+                });
+                Err(Error::Recovering)
+            }
+        }
+    }
+
+    /// 再起動に失敗していれば、次に再起動を試す時刻。
+    pub fn recovery_retry_at(&self) -> Option<Instant> {
+        self.recovery.map(|recovery| recovery.retry_at)
+    }
+
+    async fn restart(&mut self) -> Result<()> {
+        self.launch().await?;
+        self.ensure_voicevox_preset().await?;
+
+        Ok(())
+    }
+
+    pub async fn reconnect_if_required(&mut self) -> Result<()> {
+        self.ensure_started().await?;
+
+        match self.status().await? {
+            HostStatus::NotRunning | HostStatus::Error => {
+                warn!("A.I.Voice is not running, probably crashed");
+                self.recover().await
+            }
+            HostStatus::Idle => {
+                info!("A.I.Voice is already running and idle");
+                Ok(())
+            }
+            HostStatus::Busy => {
+                info!("A.I.Voice is already running and busy");
+                Ok(())
+            }
+            HostStatus::NotConnected => {
+                info!("Connecting to A.I.Voice...");
+                self.call("Connect", |host| host.connect()).await?;
+                info!("Connected to A.I.Voice");
+                Ok(())
+            }
+        }
+    }
+
+    /// このマシンに登録されているホスト名。
+    pub async fn available_host_names(&mut self) -> Result<Vec<String>> {
+        self.call("GetAvailableHostNames", |host| host.available_host_names())
+            .await
+    }
+
+    pub async fn version(&mut self) -> Result<String> {
+        self.reconnect_if_required().await?;
+        self.call("Version", |host| host.version()).await
+    }
+
+    /// 一時フレーズ辞書を空にする。
+    pub async fn clear_phrase_dictionary(&mut self) -> Result<()> {
+        self.phrase_pool.clear();
+        self.write_temporary_phrase_dict().await
+    }
+
+    /// `phrase`を一時フレーズ辞書に入れ、エディタに設定するテキストを返す。既に入っていれば辞書は読み込み直さない。
+    ///
+    /// アタッチしたA.I.Voiceでフレーズ辞書が無効になっている場合は、辞書を使わずに読みのカタカナを返す。
+    pub async fn use_phrase(&mut self, phrase: &Phrase) -> Result<String> {
+        // 起動し直すと辞書が空になるので、先に起動しておく
+        self.reconnect_if_required().await?;
+        let text = phrase.uuid.hyphenated().to_string();
+        if !self.phrase_pool.acquire(phrase) {
+            debug!(
+                "Phrase is already in the dictionary: {}",
+                phrase.pronunciation
+            );
+            return Ok(text);
+        }
+        match self.write_temporary_phrase_dict().await {
+            Ok(()) => {}
+            Err(Error::PhraseDictionaryDisabled) if !self.restart_editor => {
+                warn!(
+                    "Phrase dictionary is disabled in A.I.Voice, synthesizing the reading instead"
+                );
+                self.phrase_pool.clear();
+                return Ok(phrase.text.clone());
+            }
+            Err(e) => return Err(e),
+        }
+        self.reload_phrase_dictionary().await?;
+        self.phrase_pool.mark_synced();
+        Ok(text)
+    }
+
+    async fn write_temporary_phrase_dict(&mut self) -> Result<()> {
         let now = chrono::Local::now();
-        let text = format!(
+        let phrases: Vec<Phrase> = self.phrase_pool.phrases().cloned().collect();
+        let mut contents = format!(
             r#"# ComponentName="AITalk" ComponentVersion="6.0.0.0" UpdateDateTime="{}" Type="Phrase" Version="3.3" Language="Japanese" Count="{}"{}"#,
             now.format("%Y/%m/%d %H:%M:%S.%f"),
-            if phrase.is_some() { 1 } else { 0 },
+            phrases.len(),
             "\n"
-        );
-        temporary_phrase_dict
-            .write_all(text.as_bytes())
-            .await
-            .map_err(Error::WriteDictionaryFailed)?;
+        )
+        .into_bytes();
 
-        if let Some(phrase) = phrase {
+        for (i, phrase) in phrases.iter().enumerate() {
             let text = format!(
-                r#"num:0{}{}{}$2_2{}$2_2{}"#,
+                r#"num:{}{}{}{}$2_2{}$2_2{}"#,
+                i,
                 "\n",
                 phrase.uuid.hyphenated(),
                 "\n",
                 phrase.pronunciation,
                 "\n"
             );
-            let (bytes, _, _) = encoding_rs::SHIFT_JIS.encode(&text);
-            temporary_phrase_dict
-                .write_all(&bytes)
-                .await
-                .map_err(Error::WriteDictionaryFailed)?;
+            // フレーズ辞書はShift_JISでしか読めない
+            let (bytes, _, unmappable) = encoding_rs::SHIFT_JIS.encode(&text);
+            if unmappable {
+                warn!(
+                    "Pronunciation contains characters outside Shift_JIS: {}",
+                    phrase.pronunciation
+                );
+            }
+            contents.extend_from_slice(&bytes);
         }
-        Ok(())
+
+        self.call("WritePhraseDictionary", move |host| {
+            host.write_phrase_dictionary(&contents)
+        })
+        .await
     }
 
-    pub async fn shutdown(&self) -> Result<()> {
-        if self.host.status() == HostStatus::Idle {
-            let mut tasks = unsafe { tasklist().into_iter() };
-            if let Some((_, aivoice_process_id)) =
-                tasks.find(|(task_name, _)| task_name.to_lowercase() == "aivoiceeditor.exe")
-            {
-                info!("A.I.Voice process id: {}", aivoice_process_id);
+    /// ホストを終了させ、書き換えた設定とフレーズ辞書を元に戻す。アタッチしたA.I.Voiceは終了させない。
+    pub async fn shutdown(&mut self) -> Result<()> {
+        if let Err(e) = self.end_attach_session().await {
+            warn!("Failed to restore A.I.Voice editor state: {}", e);
+        }
+        self.supervisor.set_state(HostState::Stopped);
 
-                if !unsafe { taskkill(aivoice_process_id) } {
-                    return Err(Error::TerminateHostFailed);
-                }
-                info!("Terminated A.I.Voice");
+        let timeout = self.watchdog.options().call_timeout;
+        // `end_attach_session`が途中で失敗した場合や、ワーカーが書き換えた場合も戻す
+        if let Err(e) = self
+            .run_with_deadline("RestorePhraseDictionary", timeout, |host| {
+                host.restore_phrase_dictionary()
+            })
+            .await
+        {
+            error!("Failed to restore the phrase dictionary: {}", e);
+        }
+        let status = self
+            .run_with_deadline("Status", timeout, |host| Ok(host.status()))
+            .await;
+        // アタッチしたA.I.Voiceはユーザーのものなので終了させない
+        let owns_editor = self.restart_editor || self.launched_editor;
+        if let (true, Ok(HostStatus::Idle)) = (owns_editor, status) {
+            match self
+                .run_with_deadline("TerminateHost", timeout, |host| host.terminate_host())
+                .await
+            {
+                Ok(()) | Err(Error::ProcessNotFound) => {}
+                Err(e) => return Err(e),
             }
         }
-        self.settings_modifier
-            .as_ref()
-            .unwrap()
-            .restore_settings()
-            .await
-            .map_err(|_| Error::TerminateHostFailed)?;
+        if let Some(settings_modifier) = self.settings_modifier.as_ref() {
+            settings_modifier.restore_settings().await?;
+        }
 
         Ok(())
     }
 
-    pub async fn reload_phrase_dictionary(&self) -> Result<()> {
-        self.host.reconnect_if_required().await?;
-        self.host.reload_phrase_dictionary()?;
+    pub async fn reload_phrase_dictionary(&mut self) -> Result<()> {
+        self.reconnect_if_required().await?;
+        self.call("ReloadPhraseDictionary", |host| {
+            host.reload_phrase_dictionary()
+        })
+        .await?;
 
         Ok(())
     }
 
-    pub async fn set_text(&self, text: &str) -> Result<()> {
-        self.host.reconnect_if_required().await?;
-        self.host.set_text(text)?;
+    pub async fn set_text(&mut self, text: &str) -> Result<()> {
+        self.reconnect_if_required().await?;
+        let text = text.to_string();
+        self.call("Text=", move |host| host.set_text(&text)).await?;
 
         Ok(())
     }
 
-    pub async fn set_voice_preset(&self, preset: &VoicePreset) -> Result<()> {
-        self.host.reconnect_if_required().await?;
-        self.host.set_voice_preset(preset)?;
+    pub async fn set_voice_preset(&mut self, preset: &VoicePreset) -> Result<()> {
+        self.reconnect_if_required().await?;
+        let preset = preset.clone();
+        self.call("SetVoicePreset", move |host| host.set_voice_preset(&preset))
+            .await?;
 
         Ok(())
     }
 
-    pub async fn set_current_voice_preset_name(&self, name: &str) -> Result<()> {
-        self.host.reconnect_if_required().await?;
-        self.host.set_current_voice_preset_name(name)?;
+    pub async fn set_current_voice_preset_name(&mut self, name: &str) -> Result<()> {
+        self.reconnect_if_required().await?;
+        let name = name.to_string();
+        self.call("CurrentVoicePresetName=", move |host| {
+            host.set_current_voice_preset_name(&name)
+        })
+        .await?;
 
         Ok(())
     }
 
-    pub async fn save_audio_to_file(&self, path: &str) -> Result<()> {
-        self.host.reconnect_if_required().await?;
-        self.host.save_audio_to_file(path)?;
+    pub async fn save_audio_to_file(&mut self, path: &str) -> Result<()> {
+        self.reconnect_if_required().await?;
+        let timeout = self.watchdog.options().synthesis_timeout;
+        let path = path.to_string();
+        self.call_with_timeout("SaveAudioToFile", timeout, move |host| {
+            host.save_audio_to_file(&path)
+        })
+        .await?;
 
         Ok(())
     }
+
+    pub async fn master_control(&mut self) -> Result<MasterControl> {
+        self.reconnect_if_required().await?;
+        let master_control = self
+            .call("MasterControl", |host| host.get_master_control())
+            .await?;
+        self.last_master_control = Some(master_control.clone());
+
+        Ok(master_control)
+    }
+
+    pub async fn set_master_control(&mut self, master_control: &MasterControl) -> Result<()> {
+        self.reconnect_if_required().await?;
+        let new_master_control = master_control.clone();
+        self.call("MasterControl=", move |host| {
+            host.set_master_control(&new_master_control)
+        })
+        .await?;
+        self.last_master_control = Some(master_control.clone());
+
+        Ok(())
+    }
+
+    /// 最後に`master_control`で読んだか`set_master_control`で書いた値。A.I.Voiceの画面で変えた値は、次に読むまで反映されない。
+    pub fn last_master_control(&self) -> Option<&MasterControl> {
+        self.last_master_control.as_ref()
+    }
+
+    /// 起動中のA.I.Voiceにアタッチしている場合、ユーザーが編集中のテキストとボイスプリセットを保存する。
+    ///
+    /// 合成が続いている間は保存したままにし、`end_attach_session`でまとめて元に戻す。
+    /// A.I.Voiceを再起動して使っている場合や、既に保存している場合は何もしない。
+    pub async fn begin_attach_session(&mut self) -> Result<()> {
+        if self.restart_editor || self.attach_session.is_some() {
+            return Ok(());
+        }
+        self.reconnect_if_required().await?;
+
+        let text = self.call("Text", |host| host.get_text()).await?;
+        let preset_name = self
+            .call("CurrentVoicePresetName", |host| {
+                host.get_current_voice_preset_name()
+            })
+            .await?;
+        debug!("Saved A.I.Voice editor state");
+        self.attach_session = Some(EditorSnapshot { text, preset_name });
+
+        Ok(())
+    }
+
+    /// `begin_attach_session`の後、最後に使われてからの時間。保存していない場合は`None`。
+    pub fn attach_session_idle_for(&self) -> Option<Duration> {
+        self.attach_session
+            .as_ref()
+            .map(|_| self.last_used.elapsed())
+    }
+
+    /// フレーズ辞書と、`begin_attach_session`で保存したエディタの状態を元に戻す。
+    pub async fn end_attach_session(&mut self) -> Result<()> {
+        let Some(EditorSnapshot { text, preset_name }) = self.attach_session.take() else {
+            return Ok(());
+        };
+
+        info!("Restoring A.I.Voice editor state");
+        self.phrase_pool.clear();
+        // 辞書はホストの状態に関係なく戻せるので、再接続より先に戻す
+        self.call("RestorePhraseDictionary", |host| {
+            host.restore_phrase_dictionary()
+        })
+        .await?;
+        self.reload_phrase_dictionary().await?;
+        if !preset_name.is_empty() {
+            self.set_current_voice_preset_name(&preset_name).await?;
+        }
+        self.set_text(&text).await?;
+
+        Ok(())
+    }
+
+    /// `save_audio_to_file`で書き出したファイルに中身が書き込まれるまで待つ。
+    pub async fn wait_for_audio(&mut self, path: &Path) -> Result<()> {
+        let timeout = self.watchdog.options().synthesis_timeout;
+        let wait = async {
+            loop {
+                let metadata = tokio::fs::metadata(path)
+                    .await
+                    .map_err(|e| Error::SynthesisFailed(e.into()))?;
+                if metadata.len() > 0 {
+                    return Ok(());
+                }
+                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+            }
+        };
+        match tokio::time::timeout(timeout, wait).await {
+            Ok(result) => {
+                self.watchdog.record_success();
+                result
+            }
+            Err(_) => Err(self.hung("SaveAudioToFile".to_string()).await),
+        }
+    }
 }
 
-pub static AIVOICE: Lazy<Arc<Mutex<AiVoice>>> = Lazy::new(|| Arc::new(Mutex::new(AiVoice::new())));
+/// `AiVoice`と`actor::ACTOR`の設定。
+#[derive(Debug, Clone, Default)]
+pub struct AiVoiceOptions {
+    pub backend: BackendOptions,
+    pub watchdog: WatchdogOptions,
+    pub editor: EditorSettings,
+    /// 起動中のA.I.Voiceを終了させ、設定ファイルを書き換えてから起動し直す。
+    ///
+    /// `false`の場合は起動中のA.I.Voiceにそのまま接続し、合成が`ATTACH_SESSION_TIMEOUT`の間途切れたら
+    /// エディタの状態とフレーズ辞書を元に戻す。
+    pub restart_editor: bool,
+    /// この時間使われなかったらホストを終了させる。`None`の場合は終了させない。
+    pub idle_timeout: Option<Duration>,
+    /// 実行を待てるジョブの数。これを超えたリクエストはすぐに失敗させる。
+    pub queue_size: usize,
+    /// 話者とスタイルのIDを保存するファイル。`None`の場合は実行ファイルと同じフォルダに置く。
+    pub registry_path: Option<PathBuf>,
+    /// 別の話者とのモーフィングをボイスフュージョンで行う。
+    pub voice_fusion: bool,
+    /// ユーザーのボイスプリセットも話者にする。
+    pub user_presets: bool,
+    /// この間隔で話者一覧を読み直す。`None`の場合は`/rescan_speakers`が呼ばれたときだけ読み直す。
+    pub rescan_interval: Option<Duration>,
+    /// 一時フレーズ辞書に残しておくフレーズの数。
+    pub phrase_pool_size: usize,
+    /// リクエストされたサンプリング周波数をA.I.Voiceが書き出せる場合は、その周波数で書き出す。
+    /// `restart_editor`の場合だけ有効。
+    pub native_sampling_rate: bool,
+}
+
+impl AiVoice {
+    /// ホストのスレッドを起動し、そのスレッドでホストを作る。
+    pub async fn create(options: &AiVoiceOptions) -> Result<Self> {
+        let host_thread = HostThread::spawn();
+        let backend = options.backend.clone();
+        let save_audio_timeout = options.watchdog.synthesis_timeout;
+        let host = host_thread
+            .run(move || backend::create(&backend, save_audio_timeout))
+            .await
+            .map_err(|_| Error::InitializeFailed)??;
+        phrase_dictionary_journal::install_panic_hook();
+        // 前回のプロセスがユーザーのフレーズ辞書を戻さずに終了していたら、先に戻しておく
+        if let Err(e) = phrase_dictionary_journal::restore() {
+            error!("Failed to restore the phrase dictionary: {}", e);
+        }
+        if let Some(settings_path) = host.editor_settings_path() {
+            SettingsModifier::install_panic_hook(settings_path.clone());
+            // 前回のプロセスが設定ファイルを戻さずに終了していたら、A.I.Voiceを使う前に戻しておく
+            if let Err(e) = SettingsModifier::restore(&settings_path) {
+                error!("Failed to restore A.I.Voice settings: {}", e);
+            }
+        }
+        Ok(Self::new(host, host_thread, options))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::FakeHost;
+    use crate::supervisor::BackoffPolicy;
+    use axum::response::IntoResponse;
+
+    fn attached(registry: &Path) -> AiVoice<FakeHost> {
+        let options = AiVoiceOptions {
+            registry_path: Some(registry.join("speakers.json")),
+            phrase_pool_size: 2,
+            ..Default::default()
+        };
+        AiVoice::new(Box::default(), HostThread::spawn(), &options)
+    }
+
+    #[tokio::test]
+    async fn reloads_phrase_dictionary_once_per_attach_session() {
+        let registry = tempfile::tempdir().unwrap();
+        let mut aivoice = attached(registry.path());
+        aivoice.ensure_started().await.unwrap();
+        aivoice.set_text("ユーザーのテキスト").await.unwrap();
+        let phrase = Phrase::new("$2_2テ^スト".to_string(), "テスト".to_string());
+
+        aivoice.begin_attach_session().await.unwrap();
+        for _ in 0..3 {
+            aivoice.begin_attach_session().await.unwrap();
+            let text = aivoice.use_phrase(&phrase).await.unwrap();
+            assert_eq!(text, phrase.uuid().hyphenated().to_string());
+            aivoice.set_text(&text).await.unwrap();
+        }
+        assert_eq!(aivoice.host().phrase_dictionary_reloads(), 1);
+        assert!(aivoice.attach_session_idle_for().is_some());
+
+        aivoice.end_attach_session().await.unwrap();
+        assert_eq!(aivoice.host().phrase_dictionary_reloads(), 2);
+        assert_eq!(aivoice.host().text(), "ユーザーのテキスト");
+        assert!(aivoice.attach_session_idle_for().is_none());
+
+        // 戻した後はユーザーの辞書なので、同じフレーズでも書き込み直す
+        aivoice.begin_attach_session().await.unwrap();
+        aivoice.use_phrase(&phrase).await.unwrap();
+        assert_eq!(aivoice.host().phrase_dictionary_reloads(), 3);
+    }
+
+    #[tokio::test]
+    async fn backs_off_while_restart_fails() {
+        let registry = tempfile::tempdir().unwrap();
+        let supervisor = Arc::new(Supervisor::new(BackoffPolicy {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(2),
+            max_attempts: 3,
+        }));
+        let mut aivoice = attached(registry.path()).with_supervisor(supervisor.clone());
+        aivoice.ensure_started().await.unwrap();
+        assert_eq!(supervisor.state(), HostState::Ready);
+
+        aivoice.host().push_status(HostStatus::Error);
+        assert!(aivoice.is_crashed().await);
+        aivoice.host().push_status(HostStatus::NotRunning);
+        assert!(aivoice.is_crashed().await);
+        assert!(!aivoice.is_crashed().await);
+
+        aivoice.host().terminate_host().unwrap();
+        aivoice.host().fail_starts(2);
+        assert!(matches!(
+            aivoice.reconnect_if_required().await,
+            Err(Error::Recovering)
+        ));
+        assert_eq!(supervisor.state(), HostState::Recovering { attempt: 0 });
+        assert!(supervisor.status().last_crash.is_some());
+        assert!(aivoice.recovery_retry_at().is_some());
+        assert!(matches!(
+            aivoice.ensure_started().await,
+            Err(Error::Recovering)
+        ));
+
+        assert!(matches!(aivoice.recover().await, Err(Error::Recovering)));
+        assert_eq!(supervisor.state(), HostState::Recovering { attempt: 1 });
+
+        aivoice.recover().await.unwrap();
+        assert!(aivoice.recovery_retry_at().is_none());
+        assert_eq!(supervisor.state(), HostState::Ready);
+        assert_eq!(supervisor.status().restarts, 1);
+        aivoice.ensure_started().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let registry = tempfile::tempdir().unwrap();
+        let supervisor = Arc::new(Supervisor::new(BackoffPolicy {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(2),
+            max_attempts: 2,
+        }));
+        let mut aivoice = attached(registry.path()).with_supervisor(supervisor.clone());
+        aivoice.ensure_started().await.unwrap();
+
+        aivoice.host().terminate_host().unwrap();
+        aivoice.host().fail_starts(2);
+        assert!(matches!(aivoice.recover().await, Err(Error::Recovering)));
+        assert!(matches!(
+            aivoice.recover().await,
+            Err(Error::HostUnavailable)
+        ));
+        assert!(matches!(supervisor.state(), HostState::Failed { .. }));
+        assert!(aivoice.recovery_retry_at().is_none());
+        assert_eq!(supervisor.status().restarts, 0);
+    }
+
+    #[tokio::test]
+    async fn terminates_host_after_repeated_timeouts() {
+        let registry = tempfile::tempdir().unwrap();
+        let options = AiVoiceOptions {
+            registry_path: Some(registry.path().join("speakers.json")),
+            watchdog: WatchdogOptions {
+                call_timeout: Duration::from_millis(50),
+                hang_limit: 2,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut aivoice: AiVoice<FakeHost> =
+            AiVoice::new(Box::default(), HostThread::spawn(), &options);
+        aivoice.ensure_started().await.unwrap();
+
+        aivoice
+            .host()
+            .hang_on("MasterControl", Duration::from_millis(500));
+        let error = aivoice.master_control().await.unwrap_err();
+        assert!(matches!(&error, Error::Timeout(api) if api == "MasterControl"));
+        assert_eq!(
+            error.into_response().status(),
+            axum::http::StatusCode::GATEWAY_TIMEOUT
+        );
+        assert_eq!(aivoice.host().status(), HostStatus::Idle);
+
+        // ホストのスレッドはまだ塞がっているので、次の呼び出しもタイムアウトしてホストを終了させる
+        let error = aivoice.master_control().await.unwrap_err();
+        assert!(matches!(&error, Error::Timeout(_)));
+        assert_eq!(aivoice.host().status(), HostStatus::NotRunning);
+    }
+
+    #[tokio::test]
+    async fn applies_registry_overrides() {
+        let registry = tempfile::tempdir().unwrap();
+        fs_err::write(
+            registry.path().join("speakers.json"),
+            serde_json::json!({
+                "speakers": {
+                    "fake_voice": {
+                        "name": "フェイク",
+                        "hidden": true,
+                        "order": 3,
+                        "styles": { "N": 1 },
+                        "style_names": { "N": "ふつう" },
+                    }
+                }
+            })
+            .to_string(),
+        )
+        .unwrap();
+        let mut aivoice = attached(registry.path());
+        aivoice.ensure_started().await.unwrap();
+
+        let speaker = &aivoice.speakers()["fake_voice"];
+        assert_eq!(speaker.display_name(), "フェイク");
+        assert!(*speaker.hidden());
+        assert_eq!(*speaker.order(), Some(3));
+        let normal = &speaker.styles()[0];
+        assert_eq!(*normal.id(), 1);
+        assert_eq!(normal.display_name(), "ふつう");
+        assert!(!*aivoice.speakers()["fake_voice_emo"].hidden());
+    }
+
+    #[test]
+    fn switches_sampling_rate_only_when_stable() {
+        let mut streak = SamplingRateStreak::default();
+        for _ in 0..4 {
+            assert!(!streak.observe(24000));
+            assert!(!streak.observe(48000));
+        }
+
+        assert!(!streak.observe(24000));
+        assert!(!streak.observe(24000));
+        assert!(streak.observe(24000));
+        assert!(streak.observe(24000));
+        assert!(!streak.observe(48000));
+    }
+}
@@ -0,0 +1,381 @@
+use super::Backend;
+use crate::bridge::{
+    HostStatus, MasterControl, MergedVoiceContainer, TextEditMode, VoicePreset, VoicePresetStyle,
+};
+use crate::error::{Error, Result};
+
+use indexmap::IndexMap;
+use std::{
+    collections::{HashMap, VecDeque},
+    f64::consts::PI,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+const SAMPLE_RATE: u32 = 48000;
+
+/// A.I.Voiceの代わりに使うホスト。
+///
+/// 合成結果は設定されたテキストとボイスプリセットだけから決まるサイン波のWAVになる。
+/// 複製したものは状態を共有するので、`AiVoice`に渡した後もテストから落としたり呼び出しを数えたりできる。
+#[derive(Debug, Clone)]
+pub struct FakeHost {
+    state: Arc<Mutex<FakeState>>,
+}
+
+#[derive(Debug)]
+struct FakeState {
+    status: HostStatus,
+    scripted_statuses: VecDeque<HostStatus>,
+    voices: Vec<String>,
+    presets: IndexMap<String, VoicePreset>,
+    current_preset_name: Option<String>,
+    master_control: MasterControl,
+    text: String,
+    phrase_dictionary_reloads: usize,
+    /// 呼ばれたら落ちるAPI。1回落ちるたびに1つ消す。
+    crash_on: Vec<String>,
+    /// 呼ばれたら止まるAPIと止まる時間。
+    hang_on: Vec<(String, Duration)>,
+    failing_starts: usize,
+    calls: HashMap<String, usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FakeVoice {
+    pub voice_name: String,
+    pub display_name: String,
+    pub styles: Vec<String>,
+}
+
+impl FakeVoice {
+    pub fn new(voice_name: &str, display_name: &str, styles: &[&str]) -> Self {
+        Self {
+            voice_name: voice_name.to_string(),
+            display_name: display_name.to_string(),
+            styles: styles.iter().map(|x| x.to_string()).collect(),
+        }
+    }
+}
+
+impl Default for FakeHost {
+    fn default() -> Self {
+        let host = Self::new(vec![
+            FakeVoice::new("fake_voice", "フェイク", &[]),
+            FakeVoice::new("fake_voice_emo", "フェイク（感情）", &["J", "A", "S"]),
+            FakeVoice::new("fake_voice_ex", "フェイク（拡張）", &["J", "Whisper"]),
+        ]);
+        // ユーザーが作ったボイスプリセット
+        {
+            let mut state = host.state.lock().unwrap();
+            let mut preset = state.presets["fake_voice_emo"].clone();
+            preset.preset_name = "フェイク（早口）".to_string();
+            preset.speed = 1.5;
+            preset.styles[0].value = 0.5;
+            state.presets.insert(preset.preset_name.clone(), preset);
+        }
+        host
+    }
+}
+
+impl FakeHost {
+    pub fn new(voices: Vec<FakeVoice>) -> Self {
+        let presets = voices
+            .iter()
+            .map(|voice| {
+                (
+                    voice.voice_name.clone(),
+                    VoicePreset {
+                        preset_name: voice.display_name.clone(),
+                        voice_name: voice.voice_name.clone(),
+                        volume: 1.0,
+                        speed: 1.0,
+                        pitch: 1.0,
+                        pitch_range: 1.0,
+                        middle_pause: 150,
+                        long_pause: 370,
+                        styles: voice
+                            .styles
+                            .iter()
+                            .map(|name| VoicePresetStyle {
+                                name: name.clone(),
+                                value: 0.0,
+                            })
+                            .collect(),
+                        merged_voice_container: MergedVoiceContainer {
+                            base_pitch_voice_name: "".to_string(),
+                            merged_voices: vec![],
+                        },
+                    },
+                )
+            })
+            .collect();
+        Self {
+            state: Mutex::new(FakeState {
+                status: HostStatus::NotRunning,
+                scripted_statuses: VecDeque::new(),
+                voices: voices.into_iter().map(|x| x.voice_name).collect(),
+                presets,
+                current_preset_name: None,
+                master_control: MasterControl::default(),
+                text: String::new(),
+                phrase_dictionary_reloads: 0,
+                crash_on: Vec::new(),
+                hang_on: Vec::new(),
+                failing_starts: 0,
+                calls: HashMap::new(),
+            })
+            .into(),
+        }
+    }
+
+    /// 次の`status`呼び出しで返すステータスを積む。積まれている間は実際の状態より優先される。
+    pub fn push_status(&self, status: HostStatus) {
+        self.state
+            .lock()
+            .unwrap()
+            .scripted_statuses
+            .push_back(status);
+    }
+
+    pub fn text(&self) -> String {
+        self.state.lock().unwrap().text.clone()
+    }
+
+    pub fn phrase_dictionary_reloads(&self) -> usize {
+        self.state.lock().unwrap().phrase_dictionary_reloads
+    }
+
+    /// 次に`api`を呼んだときに、A.I.Voiceが落ちたことにする。呼び出しは失敗し、ステータスは`NotRunning`になる。
+    pub fn crash_on(&self, api: &str) {
+        self.state.lock().unwrap().crash_on.push(api.to_string());
+    }
+
+    /// 次に`api`を呼んだときに、応答する前に`duration`だけ止まる。
+    pub fn hang_on(&self, api: &str, duration: Duration) {
+        self.state
+            .lock()
+            .unwrap()
+            .hang_on
+            .push((api.to_string(), duration));
+    }
+
+    /// 次の`count`回の`start`を失敗させる。
+    pub fn fail_starts(&self, count: usize) {
+        self.state.lock().unwrap().failing_starts = count;
+    }
+
+    /// 接続が必要な`api`が呼ばれた回数。
+    pub fn calls(&self, api: &str) -> usize {
+        self.state
+            .lock()
+            .unwrap()
+            .calls
+            .get(api)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// 接続が必要な`api`の呼び出しを数え、仕込まれたハングや異常終了を起こしてから状態を返す。
+    fn connected(&self, api: &str) -> Result<MutexGuard<'_, FakeState>> {
+        let mut state = self.state.lock().unwrap();
+        *state.calls.entry(api.to_string()).or_default() += 1;
+        if let Some(index) = state.hang_on.iter().position(|(x, _)| x == api) {
+            let (_, duration) = state.hang_on.remove(index);
+            drop(state);
+            std::thread::sleep(duration);
+            state = self.state.lock().unwrap();
+        }
+        if let Some(index) = state.crash_on.iter().position(|x| x == api) {
+            state.crash_on.remove(index);
+            state.status = HostStatus::NotRunning;
+            return Err(Error::ApiFailed(api.to_string()));
+        }
+        match state.status {
+            HostStatus::Idle | HostStatus::Busy => Ok(state),
+            _ => Err(Error::ApiFailed(api.to_string())),
+        }
+    }
+}
+
+impl Backend for FakeHost {
+    fn name(&self) -> &str {
+        "Fake"
+    }
+
+    fn status(&self) -> HostStatus {
+        let mut state = self.state.lock().unwrap();
+        state.scripted_statuses.pop_front().unwrap_or(state.status)
+    }
+
+    fn start(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.failing_starts > 0 {
+            state.failing_starts -= 1;
+            return Err(Error::StartHostFailed);
+        }
+        if state.status != HostStatus::NotRunning {
+            return Err(Error::StartHostFailed);
+        }
+        state.status = HostStatus::NotConnected;
+        Ok(())
+    }
+
+    fn connect(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.status == HostStatus::NotRunning {
+            return Err(Error::ConnectFailed);
+        }
+        state.status = HostStatus::Idle;
+        Ok(())
+    }
+
+    fn version(&self) -> Result<String> {
+        let _state = self
+            .connected("Version")
+            .map_err(|_| Error::VersionFailed)?;
+        Ok("0.0.0.0".to_string())
+    }
+
+    fn speakers(&self) -> Result<Vec<String>> {
+        let state = self
+            .connected("VoiceNames")
+            .map_err(|_| Error::SpeakersFailed)?;
+        Ok(state.voices.clone())
+    }
+
+    fn voice_preset_names(&self) -> Result<Vec<String>> {
+        let state = self.connected("VoicePresetNames")?;
+        Ok(state.presets.keys().cloned().collect())
+    }
+
+    fn add_voice_preset(&self, preset: &VoicePreset) -> Result<()> {
+        let mut state = self.connected("AddVoicePreset")?;
+        if state.presets.contains_key(&preset.preset_name) {
+            return Err(Error::ApiFailed("AddVoicePreset".to_string()));
+        }
+        state
+            .presets
+            .insert(preset.preset_name.clone(), preset.clone());
+        Ok(())
+    }
+
+    fn get_voice_preset(&self, preset_name: &str) -> Result<VoicePreset> {
+        let state = self.connected("GetVoicePreset")?;
+        state
+            .presets
+            .get(preset_name)
+            .cloned()
+            .ok_or_else(|| Error::ApiFailed("GetVoicePreset".to_string()))
+    }
+
+    fn set_voice_preset(&self, preset: &VoicePreset) -> Result<()> {
+        let mut state = self.connected("SetVoicePreset")?;
+        let Some(current) = state.presets.get_mut(&preset.preset_name) else {
+            return Err(Error::ApiFailed("SetVoicePreset".to_string()));
+        };
+        *current = preset.clone();
+        Ok(())
+    }
+
+    fn set_current_voice_preset_name(&self, preset_name: &str) -> Result<()> {
+        let mut state = self.connected("CurrentVoicePresetName=")?;
+        if !state.presets.contains_key(preset_name) {
+            return Err(Error::ApiFailed("CurrentVoicePresetName=".to_string()));
+        }
+        state.current_preset_name = Some(preset_name.to_string());
+        Ok(())
+    }
+
+    fn get_current_voice_preset_name(&self) -> Result<String> {
+        let state = self.connected("CurrentVoicePresetName")?;
+        Ok(state.current_preset_name.clone().unwrap_or_default())
+    }
+
+    fn set_text_edit_mode(&self, _mode: TextEditMode) -> Result<()> {
+        let _state = self.connected("TextEditMode=")?;
+        Ok(())
+    }
+
+    fn terminate_host(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.status == HostStatus::NotRunning {
+            return Err(Error::ProcessNotFound);
+        }
+        state.status = HostStatus::NotRunning;
+        Ok(())
+    }
+
+    fn reload_phrase_dictionary(&self) -> Result<()> {
+        let mut state = self.connected("ReloadPhraseDictionary")?;
+        state.phrase_dictionary_reloads += 1;
+        Ok(())
+    }
+
+    fn set_text(&self, text: &str) -> Result<()> {
+        let mut state = self.connected("Text=")?;
+        state.text = text.to_string();
+        Ok(())
+    }
+
+    fn get_text(&self) -> Result<String> {
+        let state = self.connected("Text")?;
+        Ok(state.text.clone())
+    }
+
+    fn save_audio_to_file(&self, path: &str) -> Result<()> {
+        let state = self.connected("SaveAudioToFile")?;
+        let preset = state
+            .current_preset_name
+            .as_ref()
+            .and_then(|name| state.presets.get(name))
+            .ok_or_else(|| Error::ApiFailed("SaveAudioToFile".to_string()))?;
+
+        let wav = render_wav(&state.text, preset, &state.master_control);
+        std::fs::write(path, wav).map_err(|e| Error::SynthesisFailed(e.into()))?;
+        Ok(())
+    }
+
+    fn get_master_control(&self) -> Result<MasterControl> {
+        let state = self.connected("MasterControl")?;
+        Ok(state.master_control.clone())
+    }
+
+    fn set_master_control(&self, master_control: &MasterControl) -> Result<()> {
+        let mut state = self.connected("MasterControl=")?;
+        state.master_control = master_control.clone();
+        Ok(())
+    }
+}
+
+/// テキストとプリセットから16bit/48kHzのモノラルWAVを作る。
+///
+/// 長さはテキストの文字数と話速、周波数は高さ、振幅は音量で決まる。
+/// マスターコントロールの値はプリセットの値に掛け合わせる。
+pub fn render_wav(text: &str, preset: &VoicePreset, master_control: &MasterControl) -> Vec<u8> {
+    let speed = preset.speed * master_control.speed;
+    let seconds = (text.chars().count().max(1) as f64 * 0.05 / speed.max(0.1)).max(0.1);
+    let frequency = 220.0 * preset.pitch * master_control.pitch;
+    let amplitude = (0.5 * preset.volume * master_control.volume).clamp(0.0, 1.0);
+    let samples = (SAMPLE_RATE as f64 * seconds) as u32;
+
+    let mut wav = Vec::with_capacity(44 + samples as usize * 2);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + samples * 2).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&(samples * 2).to_le_bytes());
+    for i in 0..samples {
+        let t = i as f64 / SAMPLE_RATE as f64;
+        let sample = (amplitude * (2.0 * PI * frequency * t).sin() * i16::MAX as f64) as i16;
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    wav
+}
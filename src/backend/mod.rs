@@ -0,0 +1,209 @@
+pub mod fake;
+pub mod protocol;
+pub mod record;
+pub mod remote;
+pub mod replay;
+
+#[cfg(windows)]
+use crate::bridge::Host;
+use crate::bridge::{HostStatus, MasterControl, TextEditMode, VoicePreset};
+use crate::error::{Error, Result};
+use crate::phrase_dictionary_journal;
+
+use clap::ValueEnum;
+use std::{
+    fmt::Debug,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use tracing::info;
+
+/// 音声合成を行うホスト（A.I.Voice）の操作。
+///
+/// `bridge::Host`がA.I.Voice Editor APIを呼び出す実装で、
+/// `fake::FakeHost`はA.I.Voiceなしで動く実装。
+pub trait Backend: Debug + Send + Sync {
+    fn name(&self) -> &str;
+
+    /// このマシンに登録されているホスト名。
+    fn available_host_names(&self) -> Result<Vec<String>> {
+        Ok(vec![self.name().to_string()])
+    }
+
+    fn status(&self) -> HostStatus;
+    fn start(&self) -> Result<()>;
+    fn connect(&self) -> Result<()>;
+    fn version(&self) -> Result<String>;
+    fn speakers(&self) -> Result<Vec<String>>;
+    fn voice_preset_names(&self) -> Result<Vec<String>>;
+    fn add_voice_preset(&self, preset: &VoicePreset) -> Result<()>;
+    fn get_voice_preset(&self, preset_name: &str) -> Result<VoicePreset>;
+    fn set_voice_preset(&self, preset: &VoicePreset) -> Result<()>;
+    fn set_current_voice_preset_name(&self, preset_name: &str) -> Result<()>;
+    fn get_current_voice_preset_name(&self) -> Result<String>;
+    fn set_text_edit_mode(&self, mode: TextEditMode) -> Result<()>;
+    fn terminate_host(&self) -> Result<()>;
+    fn reload_phrase_dictionary(&self) -> Result<()>;
+    fn set_text(&self, text: &str) -> Result<()>;
+    fn get_text(&self) -> Result<String>;
+    fn save_audio_to_file(&self, path: &str) -> Result<()>;
+    fn get_master_control(&self) -> Result<MasterControl>;
+    fn set_master_control(&self, master_control: &MasterControl) -> Result<()>;
+
+    /// ホストが読み込むフレーズ辞書のパス。
+    fn phrase_dictionary_path(&self) -> Result<PathBuf> {
+        Ok(temporary_phrase_dict_path())
+    }
+
+    /// ホストが読み込むフレーズ辞書を書き込む。
+    ///
+    /// 一時フレーズ辞書でない場合はユーザーの辞書なので、`phrase_dictionary_journal`でバックアップしてから書き込む。
+    fn write_phrase_dictionary(&self, contents: &[u8]) -> Result<()> {
+        let path = self.phrase_dictionary_path()?;
+        if path != temporary_phrase_dict_path() {
+            return phrase_dictionary_journal::write(&path, contents);
+        }
+        info!("Writing phrase dictionary to {}", path.display());
+        std::fs::write(path, contents).map_err(Error::WriteDictionaryFailed)
+    }
+
+    /// `write_phrase_dictionary`で書き換えたユーザーのフレーズ辞書を元に戻す。
+    fn restore_phrase_dictionary(&self) -> Result<()> {
+        phrase_dictionary_journal::restore().map(|_| ())
+    }
+
+    /// ボイスライブラリのディレクトリ。アイコンと立ち絵の読み込みに使う。
+    fn voice_directory(&self) -> Option<PathBuf> {
+        None
+    }
+
+    /// 書き換えるA.I.Voiceの設定ファイル。書き換える必要がない場合は`None`。
+    fn editor_settings_path(&self) -> Option<PathBuf> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BackendKind {
+    /// A.I.Voice Editor APIを使う。
+    Aivoice,
+    /// A.I.Voiceを使わず、決まった波形を返す。
+    Fake,
+    /// 別プロセスのワーカーに接続する。
+    Remote,
+    /// `--record`で記録したファイルを再生する。
+    Replay,
+}
+
+impl Default for BackendKind {
+    fn default() -> Self {
+        if cfg!(windows) {
+            Self::Aivoice
+        } else {
+            Self::Fake
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BackendOptions {
+    pub kind: BackendKind,
+    /// `Remote`の接続先。`host:port`でTCP接続する。
+    pub worker_address: Option<String>,
+    /// `Remote`で起動するワーカーのプログラム。標準入出力で通信する。
+    pub worker_command: Option<PathBuf>,
+    /// `worker_command`に渡す引数。
+    pub worker_args: Vec<String>,
+    /// 使うホスト名。`Remote`ではワーカー側で指定する。
+    pub host_name: Option<String>,
+    /// 指定されている場合、ホストの呼び出しをこのファイルに記録する。
+    pub record: Option<PathBuf>,
+    /// `Replay`で再生するファイル。
+    pub replay_file: Option<PathBuf>,
+    /// A.I.Voiceの設定ファイル。`None`なら`settings_modifier::find_settings_path`で探す。
+    pub editor_settings: Option<PathBuf>,
+}
+
+/// `save_audio_timeout`は記録するときに書き出された音声を待つ時間で、ウォッチドッグの`synthesis_timeout`を渡す。
+pub fn create(options: &BackendOptions, save_audio_timeout: Duration) -> Result<Box<dyn Backend>> {
+    let host: Box<dyn Backend> = match options.kind {
+        #[cfg(windows)]
+        BackendKind::Aivoice => Box::new(Host::new(
+            options.host_name.as_deref(),
+            options.editor_settings.as_deref(),
+        )?),
+        #[cfg(not(windows))]
+        BackendKind::Aivoice => return Err(Error::BackendUnavailable),
+        BackendKind::Fake => {
+            let host = fake::FakeHost::default();
+            select_host_name(options.host_name.as_deref(), &host.available_host_names()?)?;
+            Box::new(host)
+        }
+        BackendKind::Remote => match (&options.worker_address, &options.worker_command) {
+            (Some(address), _) => Box::new(remote::RemoteHost::connect(address)?),
+            (None, Some(command)) => {
+                Box::new(remote::RemoteHost::spawn(command, &options.worker_args)?)
+            }
+            (None, None) => {
+                return Err(Error::WorkerFailed(anyhow::anyhow!(
+                    "--worker-address か --worker-command を指定してください"
+                )))
+            }
+        },
+        BackendKind::Replay => match &options.replay_file {
+            Some(path) => Box::new(replay::ReplayHost::open(path)?),
+            None => {
+                return Err(Error::RecordFailed(anyhow::anyhow!(
+                    "--replay-file を指定してください"
+                )))
+            }
+        },
+    };
+
+    match &options.record {
+        Some(path) => Ok(Box::new(record::RecordingHost::new(
+            host,
+            path,
+            save_audio_timeout,
+        )?)),
+        None => Ok(host),
+    }
+}
+
+/// `available`から`requested`のホストを選ぶ。`requested`が`None`なら最初のホストを使う。
+pub fn select_host_name(requested: Option<&str>, available: &[String]) -> Result<String> {
+    match requested {
+        Some(requested) => available
+            .iter()
+            .find(|name| *name == requested)
+            .cloned()
+            .ok_or_else(|| Error::HostNotFound {
+                requested: requested.to_string(),
+                available: available.to_vec(),
+            }),
+        None => available.first().cloned().ok_or(Error::NoHostAvailable),
+    }
+}
+
+/// `save_audio_to_file`で書き出された音声を読む。ファイルに中身が書き込まれるまで`timeout`まで待つ。
+pub fn read_saved_audio(path: &Path, timeout: Duration) -> Result<Vec<u8>> {
+    let started_at = Instant::now();
+    loop {
+        let audio = std::fs::read(path).map_err(|e| Error::SynthesisFailed(e.into()))?;
+        if !audio.is_empty() {
+            return Ok(audio);
+        }
+        if started_at.elapsed() > timeout {
+            return Err(Error::Timeout("SaveAudioToFile".to_string()));
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+pub fn temporary_phrase_dict_path() -> PathBuf {
+    process_path::get_executable_path()
+        .unwrap()
+        .parent()
+        .unwrap()
+        .join("temporary_phrase_dict.pdic")
+}
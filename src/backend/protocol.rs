@@ -0,0 +1,248 @@
+//! ワーカーとの通信に使うJSON-RPC 2.0のメッセージ。
+//!
+//! 1行に1メッセージのJSONを書き、TCPか標準入出力で送る。
+
+use super::{read_saved_audio, Backend};
+use crate::bridge::{BridgeError, HostStatus, MasterControl, TextEditMode, VoicePreset};
+use crate::error::Error;
+
+use anyhow::anyhow;
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+pub const JSONRPC_VERSION: &str = "2.0";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Request {
+    pub jsonrpc: String,
+    pub id: u64,
+    #[serde(flatten)]
+    pub call: Call,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum Call {
+    Name,
+    AvailableHostNames,
+    Status,
+    Start,
+    Connect,
+    Version,
+    Speakers,
+    VoicePresetNames,
+    AddVoicePreset { preset: VoicePreset },
+    GetVoicePreset { preset_name: String },
+    SetVoicePreset { preset: VoicePreset },
+    SetCurrentVoicePresetName { preset_name: String },
+    GetCurrentVoicePresetName,
+    SetTextEditMode { mode: TextEditMode },
+    TerminateHost,
+    WritePhraseDictionary { contents: String },
+    RestorePhraseDictionary,
+    ReloadPhraseDictionary,
+    SetText { text: String },
+    GetText,
+    SaveAudio,
+    GetMasterControl,
+    SetMasterControl { master_control: MasterControl },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Response {
+    pub jsonrpc: String,
+    pub id: Option<u64>,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Result(Reply),
+    Error(RpcError),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reply {
+    Done,
+    Status(HostStatus),
+    Text(String),
+    Names(Vec<String>),
+    VoicePreset(VoicePreset),
+    MasterControl(MasterControl),
+    /// Base64でエンコードしたWAV。
+    Audio(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i32,
+    pub message: String,
+    pub data: RpcErrorKind,
+}
+
+/// `Error`のうち、呼び出し側が区別する必要のあるもの。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RpcErrorKind {
+    ParseError,
+    InitializeFailed,
+    StartHostFailed,
+    ConnectFailed,
+    VersionFailed,
+    SpeakersFailed,
+    ProcessNotFound,
+    ApiFailed,
+    TerminateHostFailed,
+    Timeout,
+    BridgeFailed(BridgeError),
+    Other,
+}
+
+pub const PARSE_ERROR: i32 = -32700;
+pub const SERVER_ERROR: i32 = -32000;
+
+impl Request {
+    pub fn new(id: u64, call: Call) -> Self {
+        Self {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            id,
+            call,
+        }
+    }
+}
+
+impl Response {
+    pub fn new(id: Option<u64>, outcome: Outcome) -> Self {
+        Self {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            id,
+            outcome,
+        }
+    }
+}
+
+impl RpcError {
+    pub fn parse_error(e: serde_json::Error) -> Self {
+        Self {
+            code: PARSE_ERROR,
+            message: e.to_string(),
+            data: RpcErrorKind::ParseError,
+        }
+    }
+}
+
+impl From<&Error> for RpcError {
+    fn from(e: &Error) -> Self {
+        let (kind, message) = match e {
+            Error::InitializeFailed => (RpcErrorKind::InitializeFailed, e.to_string()),
+            Error::StartHostFailed => (RpcErrorKind::StartHostFailed, e.to_string()),
+            Error::ConnectFailed => (RpcErrorKind::ConnectFailed, e.to_string()),
+            Error::VersionFailed => (RpcErrorKind::VersionFailed, e.to_string()),
+            Error::SpeakersFailed => (RpcErrorKind::SpeakersFailed, e.to_string()),
+            Error::ProcessNotFound => (RpcErrorKind::ProcessNotFound, e.to_string()),
+            Error::ApiFailed(api) => (RpcErrorKind::ApiFailed, api.clone()),
+            Error::TerminateHostFailed => (RpcErrorKind::TerminateHostFailed, e.to_string()),
+            Error::Timeout(api) => (RpcErrorKind::Timeout, api.clone()),
+            Error::BridgeFailed(bridge) => {
+                (RpcErrorKind::BridgeFailed(bridge.clone()), e.to_string())
+            }
+            _ => (RpcErrorKind::Other, error_chain(e)),
+        };
+        Self {
+            code: SERVER_ERROR,
+            message,
+            data: kind,
+        }
+    }
+}
+
+impl From<RpcError> for Error {
+    fn from(e: RpcError) -> Self {
+        match e.data {
+            RpcErrorKind::InitializeFailed => Error::InitializeFailed,
+            RpcErrorKind::StartHostFailed => Error::StartHostFailed,
+            RpcErrorKind::ConnectFailed => Error::ConnectFailed,
+            RpcErrorKind::VersionFailed => Error::VersionFailed,
+            RpcErrorKind::SpeakersFailed => Error::SpeakersFailed,
+            RpcErrorKind::ProcessNotFound => Error::ProcessNotFound,
+            RpcErrorKind::ApiFailed => Error::ApiFailed(e.message),
+            RpcErrorKind::TerminateHostFailed => Error::TerminateHostFailed,
+            RpcErrorKind::Timeout => Error::Timeout(e.message),
+            RpcErrorKind::BridgeFailed(bridge) => Error::BridgeFailed(bridge),
+            RpcErrorKind::ParseError | RpcErrorKind::Other => {
+                Error::WorkerFailed(anyhow!("{} ({})", e.message, e.code))
+            }
+        }
+    }
+}
+
+fn error_chain(e: &dyn std::error::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(e) = source {
+        message.push_str(&format!(": {}", e));
+        source = e.source();
+    }
+    message
+}
+
+/// `call`を`host`に対して実行する。ワーカー側で使う。
+///
+/// `SaveAudio`では書き出された音声を`save_audio_timeout`まで待つ。
+pub fn dispatch(
+    host: &dyn Backend,
+    call: Call,
+    save_audio_timeout: Duration,
+) -> crate::error::Result<Reply> {
+    let reply = match call {
+        Call::Name => Reply::Text(host.name().to_string()),
+        Call::AvailableHostNames => Reply::Names(host.available_host_names()?),
+        Call::Status => Reply::Status(host.status()),
+        Call::Start => host.start().map(|_| Reply::Done)?,
+        Call::Connect => host.connect().map(|_| Reply::Done)?,
+        Call::Version => Reply::Text(host.version()?),
+        Call::Speakers => Reply::Names(host.speakers()?),
+        Call::VoicePresetNames => Reply::Names(host.voice_preset_names()?),
+        Call::AddVoicePreset { preset } => host.add_voice_preset(&preset).map(|_| Reply::Done)?,
+        Call::GetVoicePreset { preset_name } => {
+            Reply::VoicePreset(host.get_voice_preset(&preset_name)?)
+        }
+        Call::SetVoicePreset { preset } => host.set_voice_preset(&preset).map(|_| Reply::Done)?,
+        Call::SetCurrentVoicePresetName { preset_name } => host
+            .set_current_voice_preset_name(&preset_name)
+            .map(|_| Reply::Done)?,
+        Call::SetTextEditMode { mode } => host.set_text_edit_mode(mode).map(|_| Reply::Done)?,
+        Call::TerminateHost => host.terminate_host().map(|_| Reply::Done)?,
+        Call::WritePhraseDictionary { contents } => {
+            let contents = base64::engine::general_purpose::STANDARD
+                .decode(contents)
+                .map_err(|e| Error::WorkerFailed(e.into()))?;
+            host.write_phrase_dictionary(&contents)
+                .map(|_| Reply::Done)?
+        }
+        Call::RestorePhraseDictionary => host.restore_phrase_dictionary().map(|_| Reply::Done)?,
+        Call::ReloadPhraseDictionary => host.reload_phrase_dictionary().map(|_| Reply::Done)?,
+        Call::SetText { text } => host.set_text(&text).map(|_| Reply::Done)?,
+        Call::GetText => Reply::Text(host.get_text()?),
+        Call::GetCurrentVoicePresetName => Reply::Text(host.get_current_voice_preset_name()?),
+        Call::GetMasterControl => Reply::MasterControl(host.get_master_control()?),
+        Call::SetMasterControl { master_control } => host
+            .set_master_control(&master_control)
+            .map(|_| Reply::Done)?,
+        Call::SaveAudio => {
+            let temp_audio_file = tempfile::Builder::new()
+                .suffix(".wav")
+                .tempfile()
+                .map_err(|e| Error::SynthesisFailed(e.into()))?
+                .into_temp_path();
+            host.save_audio_to_file(temp_audio_file.to_str().unwrap())?;
+            let audio = read_saved_audio(&temp_audio_file, save_audio_timeout)?;
+            Reply::Audio(base64::engine::general_purpose::STANDARD.encode(audio))
+        }
+    };
+    Ok(reply)
+}
@@ -0,0 +1,319 @@
+//! ホストの呼び出しと結果を記録する。
+//!
+//! 1行に1回の呼び出しを、ワーカーとのやり取りと同じ形のJSONで書く。
+//! 記録したファイルは`replay::ReplayHost`で再生できる。
+
+use super::protocol::{Call, Outcome, Reply, RpcError};
+use super::{read_saved_audio, Backend};
+use crate::bridge::{HostStatus, MasterControl, TextEditMode, VoicePreset};
+use crate::error::{Error, Result};
+
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+use tracing::{info, warn};
+
+/// 記録の1行。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    #[serde(flatten)]
+    pub call: Call,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+/// `inner`への呼び出しをすべて記録するホスト。
+#[derive(Debug)]
+pub struct RecordingHost {
+    inner: Box<dyn Backend>,
+    writer: Mutex<BufWriter<File>>,
+    save_audio_timeout: Duration,
+}
+
+impl RecordingHost {
+    pub fn new(inner: Box<dyn Backend>, path: &Path, save_audio_timeout: Duration) -> Result<Self> {
+        info!("Recording host calls to {}", path.display());
+        let file = File::create(path).map_err(|e| Error::RecordFailed(e.into()))?;
+        let host = Self {
+            inner,
+            writer: Mutex::new(BufWriter::new(file)),
+            save_audio_timeout,
+        };
+        host.record(Call::Name, Ok(host.inner.name().to_string()), |name| {
+            Reply::Text(name.clone())
+        })?;
+        Ok(host)
+    }
+
+    /// `result`を記録してそのまま返す。記録に失敗しても呼び出し自体は失敗させない。
+    fn record<T>(
+        &self,
+        call: Call,
+        result: Result<T>,
+        reply: impl FnOnce(&T) -> Reply,
+    ) -> Result<T> {
+        let entry = Entry {
+            call,
+            outcome: match &result {
+                Ok(value) => Outcome::Result(reply(value)),
+                Err(e) => Outcome::Error(RpcError::from(e)),
+            },
+        };
+        let written = serde_json::to_string(&entry)
+            .map_err(anyhow::Error::from)
+            .and_then(|line| {
+                let mut writer = self.writer.lock().unwrap();
+                writeln!(writer, "{}", line)?;
+                writer.flush()?;
+                Ok(())
+            });
+        if let Err(e) = written {
+            warn!("Failed to record host call: {}", e);
+        }
+        result
+    }
+
+    fn record_done(&self, call: Call, result: Result<()>) -> Result<()> {
+        self.record(call, result, |_| Reply::Done)
+    }
+}
+
+impl Backend for RecordingHost {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn available_host_names(&self) -> Result<Vec<String>> {
+        self.record(
+            Call::AvailableHostNames,
+            self.inner.available_host_names(),
+            |names| Reply::Names(names.clone()),
+        )
+    }
+
+    fn status(&self) -> HostStatus {
+        self.record(Call::Status, Ok(self.inner.status()), |status| {
+            Reply::Status(*status)
+        })
+        .unwrap_or(HostStatus::Error)
+    }
+
+    fn start(&self) -> Result<()> {
+        self.record_done(Call::Start, self.inner.start())
+    }
+
+    fn connect(&self) -> Result<()> {
+        self.record_done(Call::Connect, self.inner.connect())
+    }
+
+    fn version(&self) -> Result<String> {
+        self.record(Call::Version, self.inner.version(), |version| {
+            Reply::Text(version.clone())
+        })
+    }
+
+    fn speakers(&self) -> Result<Vec<String>> {
+        self.record(Call::Speakers, self.inner.speakers(), |names| {
+            Reply::Names(names.clone())
+        })
+    }
+
+    fn voice_preset_names(&self) -> Result<Vec<String>> {
+        self.record(
+            Call::VoicePresetNames,
+            self.inner.voice_preset_names(),
+            |names| Reply::Names(names.clone()),
+        )
+    }
+
+    fn add_voice_preset(&self, preset: &VoicePreset) -> Result<()> {
+        self.record_done(
+            Call::AddVoicePreset {
+                preset: preset.clone(),
+            },
+            self.inner.add_voice_preset(preset),
+        )
+    }
+
+    fn get_voice_preset(&self, preset_name: &str) -> Result<VoicePreset> {
+        self.record(
+            Call::GetVoicePreset {
+                preset_name: preset_name.to_string(),
+            },
+            self.inner.get_voice_preset(preset_name),
+            |preset| Reply::VoicePreset(preset.clone()),
+        )
+    }
+
+    fn set_voice_preset(&self, preset: &VoicePreset) -> Result<()> {
+        self.record_done(
+            Call::SetVoicePreset {
+                preset: preset.clone(),
+            },
+            self.inner.set_voice_preset(preset),
+        )
+    }
+
+    fn set_current_voice_preset_name(&self, preset_name: &str) -> Result<()> {
+        self.record_done(
+            Call::SetCurrentVoicePresetName {
+                preset_name: preset_name.to_string(),
+            },
+            self.inner.set_current_voice_preset_name(preset_name),
+        )
+    }
+
+    fn get_current_voice_preset_name(&self) -> Result<String> {
+        self.record(
+            Call::GetCurrentVoicePresetName,
+            self.inner.get_current_voice_preset_name(),
+            |preset_name| Reply::Text(preset_name.clone()),
+        )
+    }
+
+    fn set_text_edit_mode(&self, mode: TextEditMode) -> Result<()> {
+        self.record_done(
+            Call::SetTextEditMode { mode },
+            self.inner.set_text_edit_mode(mode),
+        )
+    }
+
+    fn terminate_host(&self) -> Result<()> {
+        self.record_done(Call::TerminateHost, self.inner.terminate_host())
+    }
+
+    fn reload_phrase_dictionary(&self) -> Result<()> {
+        self.record_done(
+            Call::ReloadPhraseDictionary,
+            self.inner.reload_phrase_dictionary(),
+        )
+    }
+
+    fn set_text(&self, text: &str) -> Result<()> {
+        self.record_done(
+            Call::SetText {
+                text: text.to_string(),
+            },
+            self.inner.set_text(text),
+        )
+    }
+
+    fn get_text(&self) -> Result<String> {
+        self.record(Call::GetText, self.inner.get_text(), |text| {
+            Reply::Text(text.clone())
+        })
+    }
+
+    fn save_audio_to_file(&self, path: &str) -> Result<()> {
+        let result = self
+            .inner
+            .save_audio_to_file(path)
+            .and_then(|_| read_saved_audio(Path::new(path), self.save_audio_timeout));
+        self.record(Call::SaveAudio, result, |audio| {
+            Reply::Audio(base64::engine::general_purpose::STANDARD.encode(audio))
+        })
+        .map(|_| ())
+    }
+
+    fn get_master_control(&self) -> Result<MasterControl> {
+        self.record(
+            Call::GetMasterControl,
+            self.inner.get_master_control(),
+            |master_control| Reply::MasterControl(master_control.clone()),
+        )
+    }
+
+    fn set_master_control(&self, master_control: &MasterControl) -> Result<()> {
+        self.record_done(
+            Call::SetMasterControl {
+                master_control: master_control.clone(),
+            },
+            self.inner.set_master_control(master_control),
+        )
+    }
+
+    fn write_phrase_dictionary(&self, contents: &[u8]) -> Result<()> {
+        self.record_done(
+            Call::WritePhraseDictionary {
+                contents: base64::engine::general_purpose::STANDARD.encode(contents),
+            },
+            self.inner.write_phrase_dictionary(contents),
+        )
+    }
+
+    fn restore_phrase_dictionary(&self) -> Result<()> {
+        self.record_done(
+            Call::RestorePhraseDictionary,
+            self.inner.restore_phrase_dictionary(),
+        )
+    }
+
+    fn phrase_dictionary_path(&self) -> Result<PathBuf> {
+        self.inner.phrase_dictionary_path()
+    }
+
+    fn voice_directory(&self) -> Option<PathBuf> {
+        self.inner.voice_directory()
+    }
+
+    fn editor_settings_path(&self) -> Option<PathBuf> {
+        self.inner.editor_settings_path()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{fake::FakeHost, replay::ReplayHost};
+    use std::io::BufRead;
+
+    #[test]
+    fn records_results_and_errors() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let host = RecordingHost::new(
+            Box::<FakeHost>::default(),
+            file.path(),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        assert!(matches!(host.version(), Err(Error::VersionFailed)));
+        host.start().unwrap();
+        host.connect().unwrap();
+        let speakers = host.speakers().unwrap();
+        assert!(matches!(
+            host.get_voice_preset("存在しないプリセット"),
+            Err(Error::ApiFailed(api)) if api == "GetVoicePreset"
+        ));
+
+        let entries: Vec<Entry> = std::io::BufReader::new(File::open(file.path()).unwrap())
+            .lines()
+            .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+            .collect();
+        assert!(matches!(
+            &entries[0],
+            Entry { call: Call::Name, outcome: Outcome::Result(Reply::Text(name)) } if name == "Fake"
+        ));
+        assert_eq!(entries.len(), 6);
+
+        // 再生すると、記録した結果と同じエラーになる
+        let replay = ReplayHost::open(file.path()).unwrap();
+        replay.start().unwrap();
+        replay.connect().unwrap();
+        assert_eq!(replay.speakers().unwrap(), speakers);
+        assert!(matches!(replay.version(), Err(Error::VersionFailed)));
+        assert!(matches!(
+            replay.get_voice_preset("存在しないプリセット"),
+            Err(Error::ApiFailed(api)) if api == "GetVoicePreset"
+        ));
+        assert!(matches!(
+            replay.get_voice_preset("記録していないプリセット"),
+            Err(Error::ReplayMissing(_))
+        ));
+    }
+}
@@ -0,0 +1,603 @@
+use super::protocol::{Call, Outcome, Reply, Request, Response};
+use super::Backend;
+use crate::bridge::{HostStatus, MasterControl, TextEditMode, VoicePreset};
+use crate::error::{Error, Result};
+
+use anyhow::anyhow;
+use base64::Engine as _;
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Write},
+    net::{Shutdown, TcpStream},
+    path::Path,
+    process::{Child, Command, Stdio},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+use tracing::{info, warn};
+
+/// 別プロセスのワーカー（`aivoice-vox worker`）を通してA.I.Voiceを操作するホスト。
+///
+/// 応答は読み取り用のスレッドで受け取り、リクエストのIDで呼び出し元に返す。
+/// ハングした呼び出しが応答を待っている間も、別のスレッドから`terminate_host`を呼べる。
+#[derive(Debug)]
+pub struct RemoteHost {
+    name: String,
+    connection: Connection,
+}
+
+struct Connection {
+    writer: Mutex<Writer>,
+    pending: Arc<Mutex<Pending>>,
+    /// TCPで接続している場合のソケット。閉じるときに読み取り用のスレッドを止めるのに使う。
+    stream: Option<TcpStream>,
+    child: Option<Child>,
+}
+
+struct Writer {
+    writer: Box<dyn Write + Send>,
+    next_id: u64,
+}
+
+/// 応答を待っているリクエスト。通信が壊れたら`closed`に理由を入れ、以降の呼び出しはすぐに失敗させる。
+#[derive(Default)]
+struct Pending {
+    senders: HashMap<u64, mpsc::Sender<Result<Reply>>>,
+    closed: Option<String>,
+}
+
+impl Pending {
+    fn close(&mut self, reason: String) {
+        for (_, sender) in self.senders.drain() {
+            let _ = sender.send(Err(Error::WorkerFailed(anyhow!(reason.clone()))));
+        }
+        self.closed = Some(reason);
+    }
+}
+
+impl std::fmt::Debug for Connection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Connection")
+            .field("stream", &self.stream)
+            .field("child", &self.child)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if let Some(stream) = self.stream.as_ref() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        if let Some(child) = self.child.as_mut() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+impl Connection {
+    fn new(
+        reader: impl BufRead + Send + 'static,
+        writer: impl Write + Send + 'static,
+        stream: Option<TcpStream>,
+        child: Option<Child>,
+    ) -> Self {
+        let pending = Arc::new(Mutex::new(Pending::default()));
+        {
+            let pending = pending.clone();
+            thread::spawn(move || read_responses(reader, &pending));
+        }
+        Self {
+            writer: Mutex::new(Writer {
+                writer: Box::new(writer),
+                next_id: 0,
+            }),
+            pending,
+            stream,
+            child,
+        }
+    }
+}
+
+/// ワーカーからの応答を読み、待っている呼び出し元に返す。通信が終わるか壊れたら、待っている呼び出しをすべて失敗させる。
+fn read_responses(mut reader: impl BufRead, pending: &Mutex<Pending>) {
+    let reason = loop {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) => break "Worker closed the connection".to_string(),
+            Ok(_) => {}
+            Err(e) => break format!("Failed to read from worker: {}", e),
+        }
+        // 標準出力はプロトコル専用なので、読めない行があったら通信が壊れている
+        let response: Response = match serde_json::from_str(&line) {
+            Ok(response) => response,
+            Err(e) => break format!("Malformed line from worker: {:?}: {}", line.trim_end(), e),
+        };
+        let sender = response
+            .id
+            .and_then(|id| pending.lock().unwrap().senders.remove(&id));
+        let Some(sender) = sender else {
+            warn!("Ignoring response with unexpected id: {:?}", response.id);
+            continue;
+        };
+        let _ = sender.send(match response.outcome {
+            Outcome::Result(reply) => Ok(reply),
+            Outcome::Error(e) => Err(e.into()),
+        });
+    };
+    pending.lock().unwrap().close(reason);
+}
+
+impl RemoteHost {
+    /// `address`（`host:port`）で待ち受けているワーカーに接続する。
+    pub fn connect(address: &str) -> Result<Self> {
+        info!("Connecting to worker at {}", address);
+        let stream = TcpStream::connect(address).map_err(|e| Error::WorkerFailed(e.into()))?;
+        let clone = || {
+            stream
+                .try_clone()
+                .map_err(|e| Error::WorkerFailed(e.into()))
+        };
+        Self::new(Connection::new(
+            BufReader::new(clone()?),
+            clone()?,
+            Some(stream),
+            None,
+        ))
+    }
+
+    /// `program`を`args`を付けてワーカーとして起動し、標準入出力で通信する。
+    ///
+    /// パスに空白が含まれていてもよいように、プログラムと引数は分けて受け取る。
+    pub fn spawn(program: &Path, args: &[String]) -> Result<Self> {
+        info!("Spawning worker: {} {:?}", program.display(), args);
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| Error::WorkerFailed(e.into()))?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        Self::new(Connection::new(
+            BufReader::new(stdout),
+            stdin,
+            None,
+            Some(child),
+        ))
+    }
+
+    fn new(connection: Connection) -> Result<Self> {
+        let mut host = Self {
+            name: String::new(),
+            connection,
+        };
+        let Reply::Text(name) = host.call(Call::Name)? else {
+            return Err(unexpected_reply("name"));
+        };
+        info!("Worker hostname: {}", name);
+        host.name = name;
+        Ok(host)
+    }
+
+    /// `call`を送って応答を待つ。送るときだけ書き込みをロックするので、応答を待っている間も別の呼び出しを送れる。
+    fn call(&self, call: Call) -> Result<Reply> {
+        let (sender, receiver) = mpsc::channel();
+        {
+            let mut writer = self.connection.writer.lock().unwrap();
+            writer.next_id += 1;
+            let id = writer.next_id;
+            let mut request = serde_json::to_string(&Request::new(id, call))
+                .map_err(|e| Error::WorkerFailed(e.into()))?;
+            request.push('\n');
+            {
+                let mut pending = self.connection.pending.lock().unwrap();
+                if let Some(reason) = &pending.closed {
+                    return Err(Error::WorkerFailed(anyhow!(reason.clone())));
+                }
+                pending.senders.insert(id, sender);
+            }
+
+            if let Err(e) = writer
+                .writer
+                .write_all(request.as_bytes())
+                .and_then(|_| writer.writer.flush())
+            {
+                self.connection.pending.lock().unwrap().senders.remove(&id);
+                return Err(Error::WorkerFailed(e.into()));
+            }
+        }
+
+        receiver
+            .recv()
+            .unwrap_or_else(|_| Err(Error::WorkerFailed(anyhow!("Worker closed the connection"))))
+    }
+
+    fn call_done(&self, call: Call, method: &str) -> Result<()> {
+        match self.call(call)? {
+            Reply::Done => Ok(()),
+            _ => Err(unexpected_reply(method)),
+        }
+    }
+
+    fn call_names(&self, call: Call, method: &str) -> Result<Vec<String>> {
+        match self.call(call)? {
+            Reply::Names(names) => Ok(names),
+            _ => Err(unexpected_reply(method)),
+        }
+    }
+}
+
+fn unexpected_reply(method: &str) -> Error {
+    Error::WorkerFailed(anyhow!("Unexpected reply for {}", method))
+}
+
+impl Backend for RemoteHost {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn available_host_names(&self) -> Result<Vec<String>> {
+        self.call_names(Call::AvailableHostNames, "available_host_names")
+    }
+
+    fn status(&self) -> HostStatus {
+        match self.call(Call::Status) {
+            Ok(Reply::Status(status)) => status,
+            Ok(_) => HostStatus::Error,
+            Err(e) => {
+                warn!("Failed to get status from worker: {}", e);
+                HostStatus::Error
+            }
+        }
+    }
+
+    fn start(&self) -> Result<()> {
+        self.call_done(Call::Start, "start")
+    }
+
+    fn connect(&self) -> Result<()> {
+        self.call_done(Call::Connect, "connect")
+    }
+
+    fn version(&self) -> Result<String> {
+        match self.call(Call::Version)? {
+            Reply::Text(version) => Ok(version),
+            _ => Err(unexpected_reply("version")),
+        }
+    }
+
+    fn speakers(&self) -> Result<Vec<String>> {
+        self.call_names(Call::Speakers, "speakers")
+    }
+
+    fn voice_preset_names(&self) -> Result<Vec<String>> {
+        self.call_names(Call::VoicePresetNames, "voice_preset_names")
+    }
+
+    fn add_voice_preset(&self, preset: &VoicePreset) -> Result<()> {
+        self.call_done(
+            Call::AddVoicePreset {
+                preset: preset.clone(),
+            },
+            "add_voice_preset",
+        )
+    }
+
+    fn get_voice_preset(&self, preset_name: &str) -> Result<VoicePreset> {
+        match self.call(Call::GetVoicePreset {
+            preset_name: preset_name.to_string(),
+        })? {
+            Reply::VoicePreset(preset) => Ok(preset),
+            _ => Err(unexpected_reply("get_voice_preset")),
+        }
+    }
+
+    fn set_voice_preset(&self, preset: &VoicePreset) -> Result<()> {
+        self.call_done(
+            Call::SetVoicePreset {
+                preset: preset.clone(),
+            },
+            "set_voice_preset",
+        )
+    }
+
+    fn set_current_voice_preset_name(&self, preset_name: &str) -> Result<()> {
+        self.call_done(
+            Call::SetCurrentVoicePresetName {
+                preset_name: preset_name.to_string(),
+            },
+            "set_current_voice_preset_name",
+        )
+    }
+
+    fn get_current_voice_preset_name(&self) -> Result<String> {
+        match self.call(Call::GetCurrentVoicePresetName)? {
+            Reply::Text(preset_name) => Ok(preset_name),
+            _ => Err(unexpected_reply("get_current_voice_preset_name")),
+        }
+    }
+
+    fn set_text_edit_mode(&self, mode: TextEditMode) -> Result<()> {
+        self.call_done(Call::SetTextEditMode { mode }, "set_text_edit_mode")
+    }
+
+    fn terminate_host(&self) -> Result<()> {
+        self.call_done(Call::TerminateHost, "terminate_host")
+    }
+
+    fn reload_phrase_dictionary(&self) -> Result<()> {
+        self.call_done(Call::ReloadPhraseDictionary, "reload_phrase_dictionary")
+    }
+
+    fn set_text(&self, text: &str) -> Result<()> {
+        self.call_done(
+            Call::SetText {
+                text: text.to_string(),
+            },
+            "set_text",
+        )
+    }
+
+    fn get_text(&self) -> Result<String> {
+        match self.call(Call::GetText)? {
+            Reply::Text(text) => Ok(text),
+            _ => Err(unexpected_reply("get_text")),
+        }
+    }
+
+    fn save_audio_to_file(&self, path: &str) -> Result<()> {
+        let Reply::Audio(audio) = self.call(Call::SaveAudio)? else {
+            return Err(unexpected_reply("save_audio"));
+        };
+        let audio = base64::engine::general_purpose::STANDARD
+            .decode(audio)
+            .map_err(|e| Error::WorkerFailed(e.into()))?;
+        std::fs::write(path, audio).map_err(|e| Error::SynthesisFailed(e.into()))
+    }
+
+    fn get_master_control(&self) -> Result<MasterControl> {
+        match self.call(Call::GetMasterControl)? {
+            Reply::MasterControl(master_control) => Ok(master_control),
+            _ => Err(unexpected_reply("get_master_control")),
+        }
+    }
+
+    fn set_master_control(&self, master_control: &MasterControl) -> Result<()> {
+        self.call_done(
+            Call::SetMasterControl {
+                master_control: master_control.clone(),
+            },
+            "set_master_control",
+        )
+    }
+
+    fn write_phrase_dictionary(&self, contents: &[u8]) -> Result<()> {
+        self.call_done(
+            Call::WritePhraseDictionary {
+                contents: base64::engine::general_purpose::STANDARD.encode(contents),
+            },
+            "write_phrase_dictionary",
+        )
+    }
+
+    fn restore_phrase_dictionary(&self) -> Result<()> {
+        self.call_done(Call::RestorePhraseDictionary, "restore_phrase_dictionary")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::{render_wav, FakeHost};
+    use crate::backend::protocol;
+    use crate::worker;
+    use std::time::Duration;
+    use std::{
+        io::Read,
+        net::TcpListener,
+        sync::{Arc, Condvar},
+        thread,
+    };
+
+    /// `f`を別のスレッドで実行し、`timeout`までに返らなければ失敗させる。
+    fn within<T: Send + 'static>(timeout: Duration, f: impl FnOnce() -> T + Send + 'static) -> T {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = sender.send(f());
+        });
+        receiver.recv_timeout(timeout).expect("call did not return")
+    }
+
+    /// `FakeHost`を`worker::serve`で公開し、`RemoteHost`で接続する。
+    fn connect_to_fake() -> (RemoteHost, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let worker = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let reader = BufReader::new(stream.try_clone().unwrap());
+            let host = FakeHost::default();
+            let dispatch = |call| protocol::dispatch(&host, call, Duration::from_secs(5));
+            worker::serve(&dispatch, reader, stream).unwrap();
+        });
+        (RemoteHost::connect(&address).unwrap(), worker)
+    }
+
+    #[test]
+    fn round_trip() {
+        let (host, worker) = connect_to_fake();
+        let fake = FakeHost::default();
+        assert_eq!(host.name(), fake.name());
+        assert_eq!(host.status(), HostStatus::NotRunning);
+
+        host.start().unwrap();
+        host.connect().unwrap();
+        assert_eq!(host.status(), HostStatus::Idle);
+        fake.start().unwrap();
+        fake.connect().unwrap();
+        assert_eq!(host.version().unwrap(), fake.version().unwrap());
+        assert_eq!(host.speakers().unwrap(), fake.speakers().unwrap());
+        assert_eq!(
+            host.voice_preset_names().unwrap(),
+            fake.voice_preset_names().unwrap()
+        );
+
+        let preset_name = "フェイク（早口）";
+        let mut preset = host.get_voice_preset(preset_name).unwrap();
+        preset.speed = 2.0;
+        host.set_voice_preset(&preset).unwrap();
+        assert_eq!(host.get_voice_preset(preset_name).unwrap().speed, 2.0);
+        host.set_current_voice_preset_name(preset_name).unwrap();
+        assert_eq!(host.get_current_voice_preset_name().unwrap(), preset_name);
+
+        host.set_text("テスト").unwrap();
+        assert_eq!(host.get_text().unwrap(), "テスト");
+
+        let mut master_control = host.get_master_control().unwrap();
+        master_control.volume = 0.5;
+        host.set_master_control(&master_control).unwrap();
+        assert_eq!(host.get_master_control().unwrap(), master_control);
+
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("audio.wav");
+        host.save_audio_to_file(path.to_str().unwrap()).unwrap();
+        let audio = std::fs::read(&path).unwrap();
+        assert_eq!(&audio[0..4], b"RIFF");
+        assert_eq!(audio, render_wav("テスト", &preset, &master_control));
+
+        drop(host);
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn propagates_errors() {
+        let (host, worker) = connect_to_fake();
+
+        assert!(matches!(
+            host.get_text(),
+            Err(Error::ApiFailed(api)) if api == "Text"
+        ));
+        assert!(matches!(host.terminate_host(), Err(Error::ProcessNotFound)));
+
+        host.start().unwrap();
+        host.connect().unwrap();
+        assert!(matches!(
+            host.get_voice_preset("存在しないプリセット"),
+            Err(Error::ApiFailed(api)) if api == "GetVoicePreset"
+        ));
+        // 失敗した後も同じ接続で続けて呼べる
+        assert_eq!(host.status(), HostStatus::Idle);
+
+        drop(host);
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn rejects_malformed_line() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let worker = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1];
+            stream.read_exact(&mut request).unwrap();
+            stream.write_all(b"nullptr\n").unwrap();
+        });
+
+        let error = RemoteHost::connect(&address).unwrap_err();
+        assert!(matches!(error, Error::WorkerFailed(_)), "{}", error);
+
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn terminates_while_call_hangs() {
+        // `SaveAudio`には応答せず、`TerminateHost`に応答したら接続を閉じるワーカー
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let worker = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            for line in BufReader::new(stream).lines() {
+                let request: Request = serde_json::from_str(&line.unwrap()).unwrap();
+                let reply = match request.call {
+                    Call::Name => Reply::Text("Stand-in".to_string()),
+                    Call::TerminateHost => Reply::Done,
+                    _ => continue,
+                };
+                let terminated = matches!(reply, Reply::Done);
+                let response = Response::new(Some(request.id), Outcome::Result(reply));
+                writeln!(writer, "{}", serde_json::to_string(&response).unwrap()).unwrap();
+                if terminated {
+                    break;
+                }
+            }
+        });
+
+        let host = Arc::new(RemoteHost::connect(&address).unwrap());
+        let hung = {
+            let host = host.clone();
+            thread::spawn(move || host.save_audio_to_file("unused.wav"))
+        };
+        {
+            let host = host.clone();
+            within(Duration::from_secs(5), move || host.terminate_host()).unwrap();
+        }
+
+        // 接続が閉じられると、応答を待っていた呼び出しも失敗して返る
+        worker.join().unwrap();
+        assert!(matches!(hung.join().unwrap(), Err(Error::WorkerFailed(_))));
+        assert_eq!(host.status(), HostStatus::Error);
+    }
+
+    #[test]
+    fn worker_terminates_hung_call() {
+        // `SaveAudio`は`TerminateHost`が呼ばれるまで返らない
+        let terminated = Arc::new((Mutex::new(false), Condvar::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let worker = {
+            let terminated = terminated.clone();
+            thread::spawn(move || {
+                let (stream, _) = listener.accept().unwrap();
+                let reader = BufReader::new(stream.try_clone().unwrap());
+                let dispatch = |call| match call {
+                    Call::Name => Ok(Reply::Text("Hung".to_string())),
+                    Call::SaveAudio => {
+                        let (lock, condvar) = &*terminated;
+                        let _guard = condvar
+                            .wait_while(lock.lock().unwrap(), |terminated| !*terminated)
+                            .unwrap();
+                        Err(Error::ApiFailed("SaveAudioToFile".to_string()))
+                    }
+                    Call::TerminateHost => {
+                        let (lock, condvar) = &*terminated;
+                        *lock.lock().unwrap() = true;
+                        condvar.notify_all();
+                        Ok(Reply::Done)
+                    }
+                    _ => Err(Error::ApiFailed("unexpected".to_string())),
+                };
+                worker::serve(&dispatch, reader, stream).unwrap();
+            })
+        };
+
+        let host = Arc::new(RemoteHost::connect(&address).unwrap());
+        let hung = {
+            let host = host.clone();
+            thread::spawn(move || host.save_audio_to_file("unused.wav"))
+        };
+        {
+            let host = host.clone();
+            within(Duration::from_secs(5), move || host.terminate_host()).unwrap();
+        }
+        assert!(matches!(
+            hung.join().unwrap(),
+            Err(Error::ApiFailed(api)) if api == "SaveAudioToFile"
+        ));
+
+        drop(host);
+        worker.join().unwrap();
+    }
+}
@@ -0,0 +1,437 @@
+use super::protocol::{Call, Outcome, Reply};
+use super::record::Entry;
+use super::Backend;
+use crate::bridge::{HostStatus, MasterControl, TextEditMode, VoicePreset};
+use crate::error::{Error, Result};
+
+use base64::Engine as _;
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader},
+    path::Path,
+    sync::Mutex,
+};
+use tracing::{info, warn};
+
+/// `record::RecordingHost`で記録した呼び出しを再生するホスト。
+///
+/// 問い合わせ（話者一覧、プリセットなど）には同じ引数で記録された結果を返す。
+/// 合成結果は、直前に設定されたボイスプリセットとフレーズ辞書の読みが一致する記録を返す。
+/// ステータスやテキストなどのエディタの状態は、`fake::FakeHost`と同じように再生中に追う。
+#[derive(Debug)]
+pub struct ReplayHost {
+    name: String,
+    outcomes: HashMap<String, Outcome>,
+    audios: Vec<RecordedAudio>,
+    state: Mutex<ReplayState>,
+}
+
+#[derive(Debug)]
+struct RecordedAudio {
+    key: String,
+    voice_name: Option<String>,
+    /// Base64でエンコードしたWAV。
+    audio: String,
+}
+
+/// 合成結果を選ぶのに使う状態。記録の読み込み時と再生時で同じように追う。
+#[derive(Debug, Default)]
+struct SynthesisContext {
+    preset: Option<VoicePreset>,
+    master_control: Option<MasterControl>,
+    pronunciation: Option<String>,
+}
+
+#[derive(Debug)]
+struct ReplayState {
+    status: HostStatus,
+    master_control: MasterControl,
+    text: String,
+    current_preset_name: String,
+    context: SynthesisContext,
+}
+
+impl SynthesisContext {
+    fn apply(&mut self, call: &Call) {
+        match call {
+            Call::SetVoicePreset { preset } => self.preset = Some(preset.clone()),
+            Call::SetMasterControl { master_control } => {
+                self.master_control = Some(master_control.clone())
+            }
+            Call::WritePhraseDictionary { contents } => {
+                self.pronunciation = base64::engine::general_purpose::STANDARD
+                    .decode(contents)
+                    .ok()
+                    .and_then(|contents| phrase_pronunciation(&contents));
+            }
+            _ => {}
+        }
+    }
+
+    fn key(&self) -> String {
+        format!(
+            "{}\n{}\n{}",
+            self.preset
+                .as_ref()
+                .map(|preset| serde_json::to_string(preset).unwrap())
+                .unwrap_or_default(),
+            self.master_control
+                .as_ref()
+                .map(|master_control| serde_json::to_string(master_control).unwrap())
+                .unwrap_or_default(),
+            self.pronunciation.as_deref().unwrap_or_default()
+        )
+    }
+}
+
+/// フレーズ辞書から読みを取り出す。
+fn phrase_pronunciation(contents: &[u8]) -> Option<String> {
+    let (contents, _, _) = encoding_rs::SHIFT_JIS.decode(contents);
+    contents
+        .lines()
+        .find(|line| line.starts_with("$2_2"))
+        .map(|line| line.to_string())
+}
+
+fn call_key(call: &Call) -> String {
+    serde_json::to_string(call).unwrap()
+}
+
+impl ReplayHost {
+    pub fn open(path: &Path) -> Result<Self> {
+        info!("Replaying host calls from {}", path.display());
+        let file = std::fs::File::open(path).map_err(|e| Error::RecordFailed(e.into()))?;
+
+        let mut name = None;
+        let mut outcomes = HashMap::new();
+        let mut audios = Vec::new();
+        let mut context = SynthesisContext::default();
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| Error::RecordFailed(e.into()))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: Entry =
+                serde_json::from_str(&line).map_err(|e| Error::RecordFailed(e.into()))?;
+
+            if let Outcome::Result(_) = entry.outcome {
+                context.apply(&entry.call);
+            }
+            match (&entry.call, &entry.outcome) {
+                (Call::Name, Outcome::Result(Reply::Text(text))) => name = Some(text.clone()),
+                (Call::SaveAudio, Outcome::Result(Reply::Audio(audio))) => {
+                    audios.push(RecordedAudio {
+                        key: context.key(),
+                        voice_name: context.preset.as_ref().map(|x| x.voice_name.clone()),
+                        audio: audio.clone(),
+                    })
+                }
+                _ => {}
+            }
+            outcomes.insert(call_key(&entry.call), entry.outcome);
+        }
+        info!("Loaded {} recorded calls", outcomes.len());
+
+        let master_control = match outcomes.get(&call_key(&Call::GetMasterControl)) {
+            Some(Outcome::Result(Reply::MasterControl(master_control))) => master_control.clone(),
+            _ => MasterControl::default(),
+        };
+
+        Ok(Self {
+            name: name.unwrap_or_else(|| "Replay".to_string()),
+            outcomes,
+            audios,
+            state: Mutex::new(ReplayState {
+                status: HostStatus::NotRunning,
+                master_control,
+                text: String::new(),
+                current_preset_name: String::new(),
+                context: SynthesisContext::default(),
+            }),
+        })
+    }
+
+    /// 記録された結果を返す。記録が無い場合は`Error::ReplayMissing`。
+    fn replay(&self, call: &Call) -> Result<Reply> {
+        match self.outcomes.get(&call_key(call)) {
+            Some(Outcome::Result(reply)) => Ok(reply.clone()),
+            Some(Outcome::Error(e)) => Err(e.clone().into()),
+            None => Err(Error::ReplayMissing(call_key(call))),
+        }
+    }
+
+    /// 操作系の呼び出し。記録が無ければ成功したものとして扱う。
+    ///
+    /// 引数ごとに記録を引くので、記録に無いプリセットやフレーズ辞書の書き込みもすべて成功になる。
+    /// 記録の漏れに気づけるように、その場合は警告を出す。
+    fn replay_done(&self, call: Call) -> Result<()> {
+        self.state.lock().unwrap().context.apply(&call);
+        match self.replay(&call) {
+            Ok(_) => Ok(()),
+            Err(Error::ReplayMissing(call)) => {
+                warn!("No recording for {}, treating it as succeeded", call);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn replay_names(&self, call: Call) -> Result<Vec<String>> {
+        match self.replay(&call)? {
+            Reply::Names(names) => Ok(names),
+            _ => Err(Error::ReplayMissing(call_key(&call))),
+        }
+    }
+}
+
+impl Backend for ReplayHost {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn available_host_names(&self) -> Result<Vec<String>> {
+        self.replay_names(Call::AvailableHostNames)
+            .or_else(|_| Ok(vec![self.name.clone()]))
+    }
+
+    fn status(&self) -> HostStatus {
+        self.state.lock().unwrap().status
+    }
+
+    fn start(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.status != HostStatus::NotRunning {
+            return Err(Error::StartHostFailed);
+        }
+        state.status = HostStatus::NotConnected;
+        Ok(())
+    }
+
+    fn connect(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.status == HostStatus::NotRunning {
+            return Err(Error::ConnectFailed);
+        }
+        state.status = HostStatus::Idle;
+        Ok(())
+    }
+
+    fn version(&self) -> Result<String> {
+        match self.replay(&Call::Version)? {
+            Reply::Text(version) => Ok(version),
+            _ => Err(Error::ReplayMissing(call_key(&Call::Version))),
+        }
+    }
+
+    fn speakers(&self) -> Result<Vec<String>> {
+        self.replay_names(Call::Speakers)
+    }
+
+    fn voice_preset_names(&self) -> Result<Vec<String>> {
+        self.replay_names(Call::VoicePresetNames)
+    }
+
+    fn add_voice_preset(&self, preset: &VoicePreset) -> Result<()> {
+        self.replay_done(Call::AddVoicePreset {
+            preset: preset.clone(),
+        })
+    }
+
+    fn get_voice_preset(&self, preset_name: &str) -> Result<VoicePreset> {
+        let call = Call::GetVoicePreset {
+            preset_name: preset_name.to_string(),
+        };
+        match self.replay(&call)? {
+            Reply::VoicePreset(preset) => Ok(preset),
+            _ => Err(Error::ReplayMissing(call_key(&call))),
+        }
+    }
+
+    fn set_voice_preset(&self, preset: &VoicePreset) -> Result<()> {
+        self.replay_done(Call::SetVoicePreset {
+            preset: preset.clone(),
+        })
+    }
+
+    fn set_current_voice_preset_name(&self, preset_name: &str) -> Result<()> {
+        self.replay_done(Call::SetCurrentVoicePresetName {
+            preset_name: preset_name.to_string(),
+        })?;
+        self.state.lock().unwrap().current_preset_name = preset_name.to_string();
+        Ok(())
+    }
+
+    fn get_current_voice_preset_name(&self) -> Result<String> {
+        Ok(self.state.lock().unwrap().current_preset_name.clone())
+    }
+
+    fn set_text_edit_mode(&self, mode: TextEditMode) -> Result<()> {
+        self.replay_done(Call::SetTextEditMode { mode })
+    }
+
+    fn terminate_host(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.status == HostStatus::NotRunning {
+            return Err(Error::ProcessNotFound);
+        }
+        state.status = HostStatus::NotRunning;
+        Ok(())
+    }
+
+    fn reload_phrase_dictionary(&self) -> Result<()> {
+        self.replay_done(Call::ReloadPhraseDictionary)
+    }
+
+    fn set_text(&self, text: &str) -> Result<()> {
+        self.replay_done(Call::SetText {
+            text: text.to_string(),
+        })?;
+        self.state.lock().unwrap().text = text.to_string();
+        Ok(())
+    }
+
+    fn get_text(&self) -> Result<String> {
+        Ok(self.state.lock().unwrap().text.clone())
+    }
+
+    fn save_audio_to_file(&self, path: &str) -> Result<()> {
+        let (key, voice_name) = {
+            let state = self.state.lock().unwrap();
+            (
+                state.context.key(),
+                state.context.preset.as_ref().map(|x| x.voice_name.clone()),
+            )
+        };
+        let recorded = match self.audios.iter().find(|x| x.key == key) {
+            Some(recorded) => recorded,
+            None => {
+                warn!("No recording matches the current preset and phrase, using a fallback");
+                self.audios
+                    .iter()
+                    .find(|x| voice_name.is_some() && x.voice_name == voice_name)
+                    .or_else(|| self.audios.first())
+                    .ok_or_else(|| Error::ReplayMissing(call_key(&Call::SaveAudio)))?
+            }
+        };
+        let audio = base64::engine::general_purpose::STANDARD
+            .decode(&recorded.audio)
+            .map_err(|e| Error::RecordFailed(e.into()))?;
+        std::fs::write(path, audio).map_err(|e| Error::SynthesisFailed(e.into()))
+    }
+
+    fn get_master_control(&self) -> Result<MasterControl> {
+        Ok(self.state.lock().unwrap().master_control.clone())
+    }
+
+    fn set_master_control(&self, master_control: &MasterControl) -> Result<()> {
+        self.replay_done(Call::SetMasterControl {
+            master_control: master_control.clone(),
+        })?;
+        self.state.lock().unwrap().master_control = master_control.clone();
+        Ok(())
+    }
+
+    fn write_phrase_dictionary(&self, contents: &[u8]) -> Result<()> {
+        self.replay_done(Call::WritePhraseDictionary {
+            contents: base64::engine::general_purpose::STANDARD.encode(contents),
+        })
+    }
+
+    fn restore_phrase_dictionary(&self) -> Result<()> {
+        self.replay_done(Call::RestorePhraseDictionary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{fake::FakeHost, record::RecordingHost};
+    use std::time::Duration;
+
+    /// 合成するときと同じ順にホストを呼び出し、書き出された音声を返す。
+    fn synthesize(host: &dyn Backend, preset: &VoicePreset, pronunciation: &str) -> Vec<u8> {
+        let dictionary = format!("num:0\n{}\n$2_2{}$2_2\n", pronunciation, pronunciation);
+        let (dictionary, _, _) = encoding_rs::SHIFT_JIS.encode(&dictionary);
+        host.set_current_voice_preset_name(&preset.preset_name)
+            .unwrap();
+        host.set_voice_preset(preset).unwrap();
+        host.write_phrase_dictionary(&dictionary).unwrap();
+        host.reload_phrase_dictionary().unwrap();
+        // `FakeHost`の音声の長さはテキストで決まるので、読みごとに違う音声になる
+        host.set_text(pronunciation).unwrap();
+
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("audio.wav");
+        host.save_audio_to_file(path.to_str().unwrap()).unwrap();
+        std::fs::read(path).unwrap()
+    }
+
+    fn started(host: &dyn Backend) {
+        host.start().unwrap();
+        host.connect().unwrap();
+    }
+
+    /// `FakeHost`で`syntheses`を記録し、記録したファイルと合成結果を返す。
+    fn record(syntheses: &[(&VoicePreset, &str)]) -> (tempfile::NamedTempFile, Vec<Vec<u8>>) {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let host = RecordingHost::new(
+            Box::<FakeHost>::default(),
+            file.path(),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        started(&host);
+        let audios = syntheses
+            .iter()
+            .map(|(preset, pronunciation)| synthesize(&host, preset, pronunciation))
+            .collect();
+        (file, audios)
+    }
+
+    fn preset(speed: f64) -> VoicePreset {
+        let host = FakeHost::default();
+        started(&host);
+        let mut preset = host.get_voice_preset("フェイク（早口）").unwrap();
+        preset.speed = speed;
+        preset
+    }
+
+    #[test]
+    fn replays_recorded_synthesis() {
+        let preset = preset(1.5);
+        let (file, audios) = record(&[(&preset, "テ^スト")]);
+
+        let host = ReplayHost::open(file.path()).unwrap();
+        assert_eq!(host.name(), FakeHost::default().name());
+        started(&host);
+        assert_eq!(synthesize(&host, &preset, "テ^スト"), audios[0]);
+    }
+
+    #[test]
+    fn picks_recording_by_preset_and_pronunciation() {
+        let slow = preset(1.0);
+        let fast = preset(2.0);
+        let (file, audios) = record(&[
+            (&slow, "テ^スト"),
+            (&slow, "テ^ストテ^スト"),
+            (&fast, "テ^スト"),
+        ]);
+        assert_ne!(audios[0], audios[1]);
+        assert_ne!(audios[0], audios[2]);
+
+        let host = ReplayHost::open(file.path()).unwrap();
+        started(&host);
+        assert_eq!(synthesize(&host, &fast, "テ^スト"), audios[2]);
+        assert_eq!(synthesize(&host, &slow, "テ^ストテ^スト"), audios[1]);
+        assert_eq!(synthesize(&host, &slow, "テ^スト"), audios[0]);
+
+        // 一致する記録が無ければ、同じボイスの最初の記録を使う
+        assert_eq!(synthesize(&host, &fast, "ア^イ"), audios[0]);
+        // 同じボイスの記録も無ければ、最初の記録を使う
+        let other_voice = VoicePreset {
+            voice_name: "fake_voice".to_string(),
+            ..preset(1.0)
+        };
+        assert_eq!(synthesize(&host, &other_voice, "ア^イ"), audios[0]);
+    }
+}
@@ -1,15 +1,32 @@
+#[cfg(windows)]
+use crate::backend::{select_host_name, Backend};
+#[cfg(any(windows, test))]
 use crate::error::{Error, Result};
+#[cfg(windows)]
+use crate::settings_modifier::SettingsModifier;
 
+#[cfg(any(windows, test))]
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::ffi::c_char;
-use tasklist::{kill, tasklist};
+#[cfg(windows)]
+use std::{
+    ffi::{c_char, CStr, CString},
+    path::{Path, PathBuf},
+};
+#[cfg(windows)]
+use tasklist::{get_proc_path, kill, tasklist};
+#[cfg(windows)]
 use tracing::{info, warn};
 
+#[cfg(windows)]
 #[link(name = "bridge", kind = "static")]
 #[allow(dead_code)]
 extern "C" {
+    fn bridge_take_last_error(error: *mut RawBridgeError) -> bool;
+
     fn bridge_com_initialize() -> bool;
-    fn bridge_initialize_with_hostname() -> *const c_char;
+    fn bridge_get_available_host_names() -> *const *const c_char;
+    fn bridge_initialize(hostname: *const c_char) -> bool;
     fn bridge_get_status() -> i32;
     fn bridge_start_host() -> bool;
     fn bridge_terminate_host() -> bool;
@@ -27,20 +44,85 @@ extern "C" {
     fn bridge_reload_phrase_dictionary() -> bool;
 
     fn bridge_set_text(text: *const c_char) -> bool;
+    fn bridge_get_text() -> *const c_char;
     fn bridge_set_current_voice_preset_name(preset_name: *const c_char) -> bool;
+    fn bridge_get_current_voice_preset_name() -> *const c_char;
 
     fn bridge_save_audio_to_file(path: *const c_char) -> bool;
 
+    fn bridge_get_master_control() -> *const c_char;
+    fn bridge_set_master_control(json: *const c_char) -> bool;
+
     fn bridge_free(ptr: *mut c_char);
     fn bridge_free_array(ptr: *mut *const c_char);
 }
 
+#[cfg(windows)]
+#[repr(C)]
+struct RawBridgeError {
+    hresult: i32,
+    api: *mut c_char,
+    message: *mut c_char,
+}
+
+#[cfg(any(windows, test))]
+const E_FAIL: i32 = 0x80004005u32 as i32;
+#[cfg(windows)]
+const E_INVALIDARG: i32 = 0x80070057u32 as i32;
+
+/// ブリッジ関数が失敗したときの詳細。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BridgeError {
+    /// 呼び出したA.I.VoiceのAPI。
+    pub api: String,
+    pub hresult: i32,
+    /// COMのエラーメッセージ。
+    pub message: String,
+}
+
+/// `BridgeError`の大まかな分類。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BridgeErrorKind {
+    /// 渡したプリセットやテキストが不正。
+    InvalidArgument,
+    /// ホストが処理を受け付けられない状態（合成中、未接続など）。
+    InvalidOperation,
+    Other,
+}
+
+impl BridgeError {
+    pub fn kind(&self) -> BridgeErrorKind {
+        match self.hresult as u32 {
+            // E_INVALIDARG（ArgumentException）、E_POINTER（ArgumentNullException）、
+            // COR_E_ARGUMENTOUTOFRANGE
+            0x80070057 | 0x80004003 | 0x80131502 => BridgeErrorKind::InvalidArgument,
+            // COR_E_INVALIDOPERATION
+            0x80131509 => BridgeErrorKind::InvalidOperation,
+            _ => BridgeErrorKind::Other,
+        }
+    }
+}
+
+impl std::fmt::Display for BridgeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}（0x{:08X}：{}）",
+            self.api, self.hresult as u32, self.message
+        )
+    }
+}
+
+#[cfg(windows)]
 #[derive(Debug)]
 pub struct Host {
     pub name: String,
+    settings_path: PathBuf,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum HostStatus {
     Error,
     NotRunning,
@@ -49,7 +131,8 @@ pub enum HostStatus {
     Busy,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[allow(dead_code)]
 pub enum TextEditMode {
     Text = 0,
@@ -71,6 +154,36 @@ pub struct VoicePreset {
     pub merged_voice_container: MergedVoiceContainer,
 }
 
+/// エディタ全体に掛かるマスターコントロール。ボイスプリセットの値に掛け合わされる。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct MasterControl {
+    pub volume: f64,
+    pub speed: f64,
+    pub pitch: f64,
+    pub pitch_range: f64,
+    /// 短ポーズ（ミリ秒）。
+    pub middle_pause: i64,
+    /// 長ポーズ（ミリ秒）。
+    pub long_pause: i64,
+    /// 文末ポーズ（ミリ秒）。
+    pub sentence_pause: i64,
+}
+
+impl Default for MasterControl {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            speed: 1.0,
+            pitch: 1.0,
+            pitch_range: 1.0,
+            middle_pause: 150,
+            long_pause: 370,
+            sentence_pause: 800,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct VoicePresetStyle {
@@ -91,6 +204,64 @@ pub struct MergedVoice {
     pub voice_name: String,
 }
 
+/// `bridge_get_status`の戻り値を読む。取得に失敗した（-1）場合と知らない値の場合は`None`。
+#[cfg(any(windows, test))]
+fn decode_status(code: i32) -> Option<HostStatus> {
+    match code {
+        0 => Some(HostStatus::NotRunning),
+        1 => Some(HostStatus::NotConnected),
+        2 => Some(HostStatus::Idle),
+        3 => Some(HostStatus::Busy),
+        _ => None,
+    }
+}
+
+/// `api`が返したJSONを読む。読めなければ`Error::BridgeFailed`にする。
+#[cfg(any(windows, test))]
+fn decode_json<T: DeserializeOwned>(api: &str, json: &str) -> Result<T> {
+    serde_json::from_str(json).map_err(|e| {
+        Error::BridgeFailed(BridgeError {
+            api: api.to_string(),
+            hresult: E_FAIL,
+            message: format!("JSONを読めませんでした：{}", e),
+        })
+    })
+}
+
+/// `api`に渡すJSONを書く。
+#[cfg(windows)]
+fn encode_json<T: Serialize>(api: &str, value: &T) -> Result<CString> {
+    let json = serde_json::to_string(value).map_err(|e| {
+        Error::BridgeFailed(BridgeError {
+            api: api.to_string(),
+            hresult: E_INVALIDARG,
+            message: format!("JSONに変換できませんでした：{}", e),
+        })
+    })?;
+    to_c_string(api, &json)
+}
+
+/// ブリッジとの間の文字列はUTF-8でやり取りする。
+#[cfg(windows)]
+fn to_c_string(api: &str, value: &str) -> Result<CString> {
+    CString::new(value).map_err(|_| {
+        Error::BridgeFailed(BridgeError {
+            api: api.to_string(),
+            hresult: E_INVALIDARG,
+            message: "文字列にNUL文字が含まれています".to_string(),
+        })
+    })
+}
+
+/// ブリッジが返した文字列を読み取り、解放する。
+#[cfg(windows)]
+unsafe fn take_string(ptr: *const c_char) -> String {
+    let string = CStr::from_ptr(ptr).to_string_lossy().into_owned();
+    bridge_free(ptr as *mut c_char);
+    string
+}
+
+#[cfg(windows)]
 fn ptr_to_array(ptr: *const *const c_char) -> Vec<String> {
     let mut ret: Vec<String> = Vec::new();
     let mut i = 0;
@@ -100,115 +271,175 @@ fn ptr_to_array(ptr: *const *const c_char) -> Vec<String> {
             if ptr.is_null() {
                 break;
             }
-            let c_str = std::ffi::CStr::from_ptr(ptr);
-            let (speaker_name, _, invalid) = encoding_rs::SHIFT_JIS.decode(c_str.to_bytes());
-            if invalid {
-                warn!("Invalid SHIFT-JIS sequence: {}", speaker_name);
-                continue;
-            }
-            ret.push(speaker_name.to_string());
+            ret.push(CStr::from_ptr(ptr).to_string_lossy().into_owned());
             i += 1;
         }
     }
     ret
 }
 
+/// `ptr`が指すC文字列。ヌルポインタなら`None`。
+///
+/// # Safety
+///
+/// `ptr`はヌルか、ヌル終端された文字列を指していること。
+#[cfg(windows)]
+unsafe fn string_from_nullable(ptr: *const c_char) -> Option<String> {
+    (!ptr.is_null()).then(|| CStr::from_ptr(ptr).to_string_lossy().into_owned())
+}
+
+#[cfg(windows)]
 impl Host {
-    pub fn new() -> Self {
-        Self::initialize().unwrap();
-        let name = Self::hostname();
-        info!("Hostname: {}", name);
-        Self { name }
+    /// 直前に失敗したブリッジ関数のエラーを取り出す。
+    ///
+    /// ブリッジ側で記録されていなければ`api`と`E_FAIL`で埋める。
+    /// 記録されたAPI名やメッセージが無い（UTF-8に変換できなかった）場合は、`api`とHRESULTの値で埋める。
+    fn last_error(api: &str) -> Error {
+        let mut raw = RawBridgeError {
+            hresult: 0,
+            api: std::ptr::null_mut(),
+            message: std::ptr::null_mut(),
+        };
+        let error = unsafe {
+            if bridge_take_last_error(&mut raw) {
+                let error = BridgeError {
+                    api: string_from_nullable(raw.api).unwrap_or_else(|| api.to_string()),
+                    hresult: raw.hresult,
+                    message: string_from_nullable(raw.message)
+                        .unwrap_or_else(|| format!("HRESULT 0x{:08X}", raw.hresult as u32)),
+                };
+                bridge_free(raw.api);
+                bridge_free(raw.message);
+                error
+            } else {
+                BridgeError {
+                    api: api.to_string(),
+                    hresult: E_FAIL,
+                    message: "不明なエラー".to_string(),
+                }
+            }
+        };
+        warn!("Bridge call failed: {}", error);
+        Error::BridgeFailed(error)
     }
 
-    pub fn status(&self) -> HostStatus {
-        unsafe {
-            let status = bridge_get_status();
-            match status {
-                -1 => HostStatus::Error,
-                0 => HostStatus::NotRunning,
-                1 => HostStatus::NotConnected,
-                2 => HostStatus::Idle,
-                3 => HostStatus::Busy,
-                _ => panic!("Invalid status: {}", status),
-            }
-        }
+    /// `host_name`のホストを使う。`None`なら最初に見つかったホストを使う。
+    ///
+    /// `settings_path`が`None`の場合は、インストールされているA.I.Voiceの設定ファイルを探す。
+    pub fn new(host_name: Option<&str>, settings_path: Option<&Path>) -> Result<Self> {
+        let settings_path = SettingsModifier::find_settings_path(settings_path)?;
+        Self::initialize()?;
+        let available = Self::get_available_host_names()?;
+        info!("Available hosts: {:?}", available);
+        let name = select_host_name(host_name, &available)?;
+        Self::initialize_host(&name)?;
+        info!("Hostname: {}", name);
+        Ok(Self {
+            name,
+            settings_path,
+        })
     }
 
-    pub fn start(&self) -> Result<()> {
+    fn initialize() -> Result<()> {
         unsafe {
-            let success = bridge_start_host();
+            let success = bridge_com_initialize();
 
             if success {
                 Ok(())
             } else {
-                Err(Error::StartHostFailed)
+                Err(Self::last_error("CoInitialize"))
             }
         }
     }
 
-    pub fn connect(&self) -> Result<()> {
+    fn get_available_host_names() -> Result<Vec<String>> {
         unsafe {
-            let success = bridge_connect();
+            let ptr = bridge_get_available_host_names();
+            if ptr.is_null() {
+                return Err(Self::last_error("GetAvailableHostNames"));
+            }
+            let hosts = ptr_to_array(ptr);
+            bridge_free_array(ptr as *mut *const c_char);
+            Ok(hosts)
+        }
+    }
+
+    fn initialize_host(name: &str) -> Result<()> {
+        unsafe {
+            let c_str = to_c_string("Initialize", name)?;
+            let success = bridge_initialize(c_str.as_ptr());
+
             if success {
                 Ok(())
             } else {
-                Err(Error::ConnectFailed)
+                Err(Self::last_error("Initialize"))
             }
         }
     }
+}
 
-    pub async fn reconnect_if_required(&self) -> Result<()> {
-        match self.status() {
-            HostStatus::NotRunning => {
-                panic!("A.I.Voice is not running, probably crashed");
-            }
-            HostStatus::Idle => {
-                info!("A.I.Voice is already running and idle");
-                return Ok(());
-            }
-            HostStatus::Busy => {
-                info!("A.I.Voice is already running and busy");
-                return Ok(());
-            }
-            _ => {}
+#[cfg(windows)]
+impl Backend for Host {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn available_host_names(&self) -> Result<Vec<String>> {
+        Self::get_available_host_names()
+    }
+
+    fn status(&self) -> HostStatus {
+        unsafe {
+            let code = bridge_get_status();
+            decode_status(code).unwrap_or_else(|| {
+                if code == -1 {
+                    Self::last_error("Status");
+                } else {
+                    warn!("Unknown host status: {}", code);
+                }
+                HostStatus::Error
+            })
         }
+    }
 
-        loop {
-            info!("Host status: {:?}", self.status());
+    fn start(&self) -> Result<()> {
+        unsafe {
+            let success = bridge_start_host();
 
-            if self.status() == HostStatus::NotConnected {
-                break;
+            if success {
+                Ok(())
+            } else {
+                Err(Self::last_error("StartHost"))
             }
-
-            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
         }
-        info!("Connecting to A.I.Voice...");
-        self.connect()?;
-        info!("Connected to A.I.Voice");
+    }
 
-        Ok(())
+    fn connect(&self) -> Result<()> {
+        unsafe {
+            let success = bridge_connect();
+            if success {
+                Ok(())
+            } else {
+                Err(Self::last_error("Connect"))
+            }
+        }
     }
 
-    pub fn version(&self) -> Result<String> {
+    fn version(&self) -> Result<String> {
         unsafe {
             let ptr = bridge_get_version();
             if ptr.is_null() {
-                return Err(Error::VersionFailed);
+                return Err(Self::last_error("Version"));
             }
-            let c_str = std::ffi::CStr::from_ptr(ptr);
-            let str_slice = c_str.to_str().unwrap();
-            let string = str_slice.to_owned();
-            bridge_free(ptr as *mut c_char);
-            Ok(string)
+            Ok(take_string(ptr))
         }
     }
 
-    pub fn speakers(&self) -> Result<Vec<String>> {
+    fn speakers(&self) -> Result<Vec<String>> {
         unsafe {
             let ptr = bridge_get_speakers();
             if ptr.is_null() {
-                return Err(Error::SpeakersFailed);
+                return Err(Self::last_error("VoiceNames"));
             }
             let speakers = ptr_to_array(ptr);
             bridge_free_array(ptr as *mut *const c_char);
@@ -216,11 +447,11 @@ impl Host {
         }
     }
 
-    pub fn voice_preset_names(&self) -> Result<Vec<String>> {
+    fn voice_preset_names(&self) -> Result<Vec<String>> {
         unsafe {
             let ptr = bridge_get_voice_preset_names();
             if ptr.is_null() {
-                return Err(Error::ApiFailed("VoicePresetNames".to_string()));
+                return Err(Self::last_error("VoicePresetNames"));
             }
             let presets = ptr_to_array(ptr);
             bridge_free_array(ptr as *mut *const c_char);
@@ -228,51 +459,43 @@ impl Host {
         }
     }
 
-    pub fn add_voice_preset(&self, preset: VoicePreset) -> Result<()> {
+    fn add_voice_preset(&self, preset: &VoicePreset) -> Result<()> {
         unsafe {
-            let json = serde_json::to_string(&preset).unwrap();
-            let (encoded, _, _) = encoding_rs::SHIFT_JIS.encode(json.as_str());
-            let c_str = std::ffi::CString::new(encoded).unwrap();
+            let c_str = encode_json("AddVoicePreset", preset)?;
             let success = bridge_add_voice_preset(c_str.as_ptr());
 
             if success {
                 Ok(())
             } else {
-                Err(Error::ApiFailed("AddVoicePreset".to_string()))
+                Err(Self::last_error("AddVoicePreset"))
             }
         }
     }
 
-    pub fn get_voice_preset(&self, preset_name: &str) -> Result<VoicePreset> {
+    fn get_voice_preset(&self, preset_name: &str) -> Result<VoicePreset> {
         unsafe {
-            let (encoded, _, _) = encoding_rs::SHIFT_JIS.encode(preset_name);
-            let c_str = std::ffi::CString::new(encoded).unwrap();
+            let c_str = to_c_string("GetVoicePreset", preset_name)?;
             let ptr = bridge_get_voice_preset(c_str.as_ptr());
             if ptr.is_null() {
-                return Err(Error::ApiFailed("GetVoicePreset".to_string()));
+                return Err(Self::last_error("GetVoicePreset"));
             }
-            let c_str = std::ffi::CStr::from_ptr(ptr);
-            let (str_slice, _, _) = encoding_rs::SHIFT_JIS.decode(c_str.to_bytes());
-            let string = str_slice.to_string();
-            bridge_free(ptr as *mut c_char);
-
-            Ok(serde_json::from_str(&string).unwrap())
+            decode_json("GetVoicePreset", &take_string(ptr))
         }
     }
 
-    pub fn set_text_edit_mode(&self, mode: TextEditMode) -> Result<()> {
+    fn set_text_edit_mode(&self, mode: TextEditMode) -> Result<()> {
         unsafe {
             let success = bridge_set_text_edit_mode(mode as i32);
 
             if success {
                 Ok(())
             } else {
-                Err(Error::ApiFailed("TextEditMode=".to_string()))
+                Err(Self::last_error("TextEditMode="))
             }
         }
     }
 
-    pub fn terminate_host(&self) -> Result<()> {
+    fn terminate_host(&self) -> Result<()> {
         unsafe {
             // let success = bridge_terminate_host();
 
@@ -282,7 +505,9 @@ impl Host {
             //     Err(Error::TerminateHostFailed)
             // }
             let mut tasks = tasklist().into_iter();
-            let Some((_, aivoice_process_id)) = tasks.find(|(task_name, _)| task_name.to_lowercase() == "aivoiceeditor.exe") else {
+            let Some((_, aivoice_process_id)) =
+                tasks.find(|(task_name, _)| task_name.to_lowercase() == "aivoiceeditor.exe")
+            else {
                 return Err(Error::ProcessNotFound);
             };
 
@@ -295,98 +520,188 @@ impl Host {
         }
     }
 
-    pub fn reload_phrase_dictionary(&self) -> Result<()> {
+    fn reload_phrase_dictionary(&self) -> Result<()> {
         unsafe {
             let success = bridge_reload_phrase_dictionary();
 
             if success {
                 Ok(())
             } else {
-                Err(Error::ApiFailed("ReloadPhraseDictionary".to_string()))
+                Err(Self::last_error("ReloadPhraseDictionary"))
             }
         }
     }
 
-    pub fn set_text(&self, text: &str) -> Result<()> {
+    fn set_text(&self, text: &str) -> Result<()> {
         unsafe {
-            let (encoded, _, _) = encoding_rs::SHIFT_JIS.encode(text);
-            let c_str = std::ffi::CString::new(encoded).unwrap();
+            let c_str = to_c_string("Text=", text)?;
             let success = bridge_set_text(c_str.as_ptr());
 
             if success {
                 Ok(())
             } else {
-                Err(Error::ApiFailed("Text=".to_string()))
+                Err(Self::last_error("Text="))
             }
         }
     }
 
-    pub fn save_audio_to_file(&self, path: &str) -> Result<()> {
+    fn get_text(&self) -> Result<String> {
         unsafe {
-            let (encoded, _, _) = encoding_rs::SHIFT_JIS.encode(path);
-            let c_str = std::ffi::CString::new(encoded).unwrap();
+            let ptr = bridge_get_text();
+            if ptr.is_null() {
+                return Err(Self::last_error("Text"));
+            }
+            Ok(take_string(ptr))
+        }
+    }
+
+    fn save_audio_to_file(&self, path: &str) -> Result<()> {
+        unsafe {
+            let c_str = to_c_string("SaveAudioToFile", path)?;
             let success = bridge_save_audio_to_file(c_str.as_ptr());
 
             if success {
                 Ok(())
             } else {
-                Err(Error::ApiFailed("SaveAudioToFile".to_string()))
+                Err(Self::last_error("SaveAudioToFile"))
             }
         }
     }
 
-    pub fn set_current_voice_preset_name(&self, preset_name: &str) -> Result<()> {
+    fn set_current_voice_preset_name(&self, preset_name: &str) -> Result<()> {
         unsafe {
-            let (encoded, _, _) = encoding_rs::SHIFT_JIS.encode(preset_name);
-            let c_str = std::ffi::CString::new(encoded).unwrap();
+            let c_str = to_c_string("CurrentVoicePresetName=", preset_name)?;
             let success = bridge_set_current_voice_preset_name(c_str.as_ptr());
 
             if success {
                 Ok(())
             } else {
-                Err(Error::ApiFailed("CurrentVoicePresetName=".to_string()))
+                Err(Self::last_error("CurrentVoicePresetName="))
             }
         }
     }
 
-    pub fn set_voice_preset(&self, preset: &VoicePreset) -> Result<()> {
+    fn get_current_voice_preset_name(&self) -> Result<String> {
         unsafe {
-            let json = serde_json::to_string(preset).unwrap();
-            let (encoded, _, _) = encoding_rs::SHIFT_JIS.encode(json.as_str());
-            let c_str = std::ffi::CString::new(encoded).unwrap();
+            let ptr = bridge_get_current_voice_preset_name();
+            if ptr.is_null() {
+                return Err(Self::last_error("CurrentVoicePresetName"));
+            }
+            Ok(take_string(ptr))
+        }
+    }
+
+    fn set_voice_preset(&self, preset: &VoicePreset) -> Result<()> {
+        unsafe {
+            let c_str = encode_json("SetVoicePreset", preset)?;
             let success = bridge_set_voice_preset(c_str.as_ptr());
 
             if success {
                 Ok(())
             } else {
-                Err(Error::ApiFailed("SetVoicePreset".to_string()))
+                Err(Self::last_error("SetVoicePreset"))
             }
         }
     }
 
-    fn initialize() -> Result<()> {
+    fn get_master_control(&self) -> Result<MasterControl> {
         unsafe {
-            let success = bridge_com_initialize();
+            let ptr = bridge_get_master_control();
+            if ptr.is_null() {
+                return Err(Self::last_error("MasterControl"));
+            }
+            decode_json("MasterControl", &take_string(ptr))
+        }
+    }
+
+    fn set_master_control(&self, master_control: &MasterControl) -> Result<()> {
+        unsafe {
+            let c_str = encode_json("MasterControl", master_control)?;
+            let success = bridge_set_master_control(c_str.as_ptr());
 
             if success {
                 Ok(())
             } else {
-                Err(Error::InitializeFailed)
+                Err(Self::last_error("MasterControl"))
             }
         }
     }
 
-    fn hostname() -> String {
-        unsafe {
-            let ptr = bridge_initialize_with_hostname();
-            if ptr.is_null() {
-                panic!("Failed to get hostname");
-            }
-            let c_str = std::ffi::CStr::from_ptr(ptr);
-            let str_slice = c_str.to_str().unwrap();
-            let string = str_slice.to_owned();
-            bridge_free(ptr as *mut c_char);
-            string
+    fn phrase_dictionary_path(&self) -> Result<PathBuf> {
+        SettingsModifier::phrase_dictionary_path(&self.settings_path)
+    }
+
+    fn editor_settings_path(&self) -> Option<PathBuf> {
+        Some(self.settings_path.clone())
+    }
+
+    fn voice_directory(&self) -> Option<PathBuf> {
+        let mut tasks = unsafe { tasklist().into_iter() };
+        let (_, aivoice_process_id) =
+            tasks.find(|(task_name, _)| task_name.to_lowercase() == "aivoiceeditor.exe")?;
+
+        info!("A.I.Voice process id: {}", aivoice_process_id);
+        let aivoice_process_path = PathBuf::from(unsafe { get_proc_path(aivoice_process_id) });
+        info!("Process path: {}", &aivoice_process_path.display());
+
+        Some(aivoice_process_path.parent()?.parent()?.join("Voice"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_status() {
+        assert_eq!(decode_status(0), Some(HostStatus::NotRunning));
+        assert_eq!(decode_status(1), Some(HostStatus::NotConnected));
+        assert_eq!(decode_status(2), Some(HostStatus::Idle));
+        assert_eq!(decode_status(3), Some(HostStatus::Busy));
+        // -1は取得の失敗、-2はブリッジが知らないステータス
+        assert_eq!(decode_status(-1), None);
+        assert_eq!(decode_status(-2), None);
+        assert_eq!(decode_status(4), None);
+    }
+
+    #[test]
+    fn decodes_voice_preset() {
+        let json = r#"{
+            "PresetName": "琴葉 茜",
+            "VoiceName": "akane_west_emo_44",
+            "Volume": 1.0,
+            "Speed": 1.2,
+            "Pitch": 1.0,
+            "PitchRange": 1.0,
+            "MiddlePause": 150,
+            "LongPause": 370,
+            "Styles": [{ "Name": "J", "Value": 0.5 }],
+            "MergedVoiceContainer": { "BasePitchVoiceName": "", "MergedVoices": [] }
+        }"#;
+        let preset: VoicePreset = decode_json("GetVoicePreset", json).unwrap();
+        assert_eq!(preset.voice_name, "akane_west_emo_44");
+        assert_eq!(preset.speed, 1.2);
+        assert_eq!(preset.styles[0].value, 0.5);
+    }
+
+    #[test]
+    fn decodes_master_control() {
+        let json = serde_json::to_string(&MasterControl::default()).unwrap();
+        let master_control: MasterControl = decode_json("MasterControl", &json).unwrap();
+        assert_eq!(master_control, MasterControl::default());
+    }
+
+    #[test]
+    fn reports_invalid_json() {
+        for json in ["", "{", r#"{"Volume": "大きい"}"#] {
+            let Err(Error::BridgeFailed(error)) =
+                decode_json::<MasterControl>("MasterControl", json)
+            else {
+                panic!("{:?} was decoded", json);
+            };
+            assert_eq!(error.api, "MasterControl");
+            assert_eq!(error.hresult, E_FAIL);
+            assert_eq!(error.kind(), BridgeErrorKind::Other);
         }
     }
 }
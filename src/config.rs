@@ -0,0 +1,88 @@
+use crate::error::{Error, Result};
+use crate::settings_modifier::{ExportBitDepth, EXPORT_SAMPLING_RATES};
+
+use anyhow::anyhow;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// 設定ファイル。コマンドライン引数で指定されなかった項目に使う。
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// 使うA.I.Voiceのホスト名。省略すると最初に見つかったホストを使う。
+    pub host_name: Option<String>,
+    /// A.I.Voiceの設定の「文頭ポーズ」（ミリ秒）。
+    pub begin_pause: Option<u32>,
+    /// A.I.Voiceの設定の「文末ポーズ」（ミリ秒）。
+    pub term_pause: Option<u32>,
+    /// 起動中のA.I.Voiceを終了させてから起動し直すかどうか。
+    pub restart_editor: Option<bool>,
+    /// この秒数使われなかったらA.I.Voiceを終了させる。
+    pub idle_timeout: Option<u64>,
+    /// 処理を待てるリクエストの数。
+    pub queue_size: Option<usize>,
+    /// 話者とスタイルのIDを保存するファイル。
+    pub speaker_registry: Option<PathBuf>,
+    /// 別の話者とのモーフィングをA.I.Voiceのボイスフュージョンで行うかどうか。
+    pub voice_fusion: Option<bool>,
+    /// ユーザーが作ったボイスプリセットも話者として表示するかどうか。
+    pub user_presets: Option<bool>,
+    /// この秒数ごとに話者一覧を読み直す。
+    pub rescan_interval: Option<u64>,
+    /// 一時フレーズ辞書に残しておくフレーズの数。
+    pub phrase_pool_size: Option<usize>,
+    /// 合成結果のキャッシュの上限（MB）。省略するとキャッシュしない。
+    pub synthesis_cache_size: Option<u64>,
+    /// 合成結果のキャッシュを保存するフォルダ。
+    pub synthesis_cache_dir: Option<PathBuf>,
+    /// A.I.Voiceの設定ファイル。省略するとインストールされているものを探す。
+    pub editor_settings: Option<PathBuf>,
+    /// A.I.Voiceが書き出すサンプリング周波数。
+    pub export_sampling_rate: Option<u32>,
+    /// A.I.Voiceが書き出す量子化ビット数。
+    pub export_bit_depth: Option<ExportBitDepth>,
+    /// リクエストされたサンプリング周波数をA.I.Voiceが書き出せる場合は、A.I.Voiceを起動し直してその周波数で書き出す。
+    pub native_sampling_rate: Option<bool>,
+}
+
+impl Config {
+    /// `path`から読み込む。
+    ///
+    /// `path`が`None`の場合は実行ファイルと同じフォルダの`config.json`を読み、無ければ既定値を使う。
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => {
+                let path = default_path();
+                if !path.exists() {
+                    return Ok(Self::default());
+                }
+                path
+            }
+        };
+        info!("Loading config from {}", path.display());
+        let contents =
+            fs_err::read_to_string(&path).map_err(|e| Error::ConfigLoadFailed(e.into()))?;
+        let config: Self =
+            serde_json::from_str(&contents).map_err(|e| Error::ConfigLoadFailed(e.into()))?;
+
+        if let Some(rate) = config.export_sampling_rate {
+            if !EXPORT_SAMPLING_RATES.contains(&rate) {
+                return Err(Error::ConfigLoadFailed(anyhow!(
+                    "export_sampling_rate must be one of {:?}",
+                    EXPORT_SAMPLING_RATES
+                )));
+            }
+        }
+        Ok(config)
+    }
+}
+
+pub fn default_path() -> PathBuf {
+    process_path::get_executable_path()
+        .unwrap()
+        .parent()
+        .unwrap()
+        .join("config.json")
+}
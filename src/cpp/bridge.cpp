@@ -1,6 +1,5 @@
 #include <Windows.h>
 #include <comutil.h>
-#include <locale.h>
 #include <objbase.h>
 #include <shlobj.h>
 #include <stdio.h>
@@ -13,70 +12,212 @@ using namespace AIVoiceEditorApi;
 
 ITtsControlPtr pTtsControl;
 
-char *wchar_to_char(const wchar_t *wstr) {
-  size_t len;
-  setlocale(LC_ALL, "Japanese");
-  errno_t err = wcstombs_s(&len, NULL, 0, wstr, 0);
-  size_t len2 = wcslen(wstr);
-  if (err != 0) {
-    printf("wcstombs_s failed: %d\n", err);
+struct BridgeError {
+  int32_t hresult;
+  char *api;
+  char *message;
+};
+
+thread_local BridgeError last_error = {S_OK, nullptr, nullptr};
+
+// Strings crossing the bridge are UTF-8.
+char *wchar_to_utf8(const wchar_t *wstr) {
+  if (wstr == nullptr) {
+    wstr = L"";
+  }
+  int len = WideCharToMultiByte(CP_UTF8, 0, wstr, -1, NULL, 0, NULL, NULL);
+  if (len == 0) {
     return nullptr;
   }
-  if (len < len2) {
-    printf("Assertion failed: len (%zu) < wcslen(wstr) (%zu)\n", len, len2);
+  char *ret = (char *)malloc(sizeof(char) * len);
+  if (ret == nullptr) {
     return nullptr;
   }
-  char *ret = (char *)malloc(sizeof(char) * (len + 1));
-  wcstombs_s(NULL, ret, len + 1, wstr, len);
+  WideCharToMultiByte(CP_UTF8, 0, wstr, -1, ret, len, NULL, NULL);
 
   return ret;
 }
 
-extern "C" {
+_bstr_t utf8_to_bstr(const char *str) {
+  int len = MultiByteToWideChar(CP_UTF8, 0, str, -1, NULL, 0);
+  if (len == 0) {
+    return _bstr_t(L"");
+  }
+  wstring wstr(len, L'\0');
+  MultiByteToWideChar(CP_UTF8, 0, str, -1, &wstr[0], len);
 
-bool bridge_com_initialize() {
-  HRESULT hr = ::CoInitialize(0);
+  return _bstr_t(wstr.c_str());
+}
+
+wstring hresult_message(HRESULT hr) {
+  LPWSTR buffer = nullptr;
+  DWORD len = FormatMessageW(FORMAT_MESSAGE_ALLOCATE_BUFFER |
+                                 FORMAT_MESSAGE_FROM_SYSTEM |
+                                 FORMAT_MESSAGE_IGNORE_INSERTS,
+                             NULL, hr, 0, (LPWSTR)&buffer, 0, NULL);
+  wstring message = len > 0 ? wstring(buffer, len) : L"";
+  LocalFree(buffer);
+  while (!message.empty() &&
+         (message.back() == L'\r' || message.back() == L'\n')) {
+    message.pop_back();
+  }
+
+  return message;
+}
+
+void clear_last_error() {
+  free(last_error.api);
+  free(last_error.message);
+  last_error = {S_OK, nullptr, nullptr};
+}
+
+void set_last_error(const char *api, HRESULT hr, const wchar_t *description) {
+  clear_last_error();
+  wstring message = description != nullptr && *description != L'\0'
+                        ? wstring(description)
+                        : hresult_message(hr);
+  last_error.hresult = hr;
+  last_error.api = _strdup(api);
+  last_error.message = wchar_to_utf8(message.c_str());
+  if (last_error.message == nullptr) {
+    // The Rust side falls back to the HRESULT when this is still null
+    char fallback[32];
+    snprintf(fallback, sizeof(fallback), "HRESULT 0x%08lX", (unsigned long)hr);
+    last_error.message = _strdup(fallback);
+  }
+}
+
+void set_last_com_error(const char *api, const _com_error &e) {
+  _bstr_t description = e.Description();
+  set_last_error(api, e.Error(),
+                 description.length() > 0 ? (const wchar_t *)description
+                                          : nullptr);
+}
+
+bool check_hresult(const char *api, HRESULT hr) {
   if (FAILED(hr)) {
+    set_last_error(api, hr, nullptr);
     return false;
   }
-  ITtsControlPtr pTtsControl_(__uuidof(TtsControl));
-  pTtsControl = pTtsControl_;
-
   return true;
 }
 
-char *bridge_initialize_with_hostname() {
-  SAFEARRAY *hosts = pTtsControl->GetAvailableHostNames();
-  long lb;
-  long ub;
-  SafeArrayGetLBound(hosts, 1, &lb);
-  SafeArrayGetUBound(hosts, 1, &ub);
-
-  if (lb <= ub) {
-    _bstr_t initialhost;
-    for (long i = lb; i <= ub; i++) {
-      _bstr_t hostname;
-      SafeArrayGetElement(hosts, &i, (void **)hostname.GetAddress());
-      if (i == 0) {
-        initialhost = hostname.copy();
-      }
-    }
-    SafeArrayDestroy(hosts);
+// Converts `wstr` to UTF-8, recording the failure in `last_error`.
+char *to_utf8(const char *api, const wchar_t *wstr) {
+  char *ret = wchar_to_utf8(wstr);
+  if (ret == nullptr) {
+    set_last_error(api, E_FAIL, L"Failed to convert the string to UTF-8");
+  }
+  return ret;
+}
+
+void free_array(char **array) {
+  for (char **p = array; *p != nullptr; p++) {
+    free(*p);
+  }
+  free(array);
+}
 
-    HRESULT hr = pTtsControl->Initialize(initialhost);
-    if (FAILED(hr)) {
+// Converts a SAFEARRAY of BSTRs to a null-terminated array of UTF-8 strings
+// and destroys the SAFEARRAY. On failure, nothing is leaked and the error is
+// recorded in `last_error`.
+char **safearray_to_utf8_array(const char *api, SAFEARRAY *array) {
+  long lb, ub;
+  HRESULT hr = SafeArrayGetLBound(array, 1, &lb);
+  if (SUCCEEDED(hr)) {
+    hr = SafeArrayGetUBound(array, 1, &ub);
+  }
+  if (!check_hresult(api, hr)) {
+    SafeArrayDestroy(array);
+    return nullptr;
+  }
+
+  char **ret = (char **)calloc(ub - lb + 2, sizeof(char *));
+  if (ret == nullptr) {
+    set_last_error(api, E_OUTOFMEMORY, nullptr);
+    SafeArrayDestroy(array);
+    return nullptr;
+  }
+
+  for (long i = lb; i <= ub; i++) {
+    _bstr_t element;
+    hr = SafeArrayGetElement(array, &i, (void *)element.GetAddress());
+    char *element_ptr =
+        check_hresult(api, hr) ? to_utf8(api, element) : nullptr;
+    if (element_ptr == nullptr) {
+      // `ret` is zero-initialized, so it is terminated right after the
+      // converted elements.
+      free_array(ret);
+      SafeArrayDestroy(array);
       return nullptr;
     }
+    ret[i - lb] = element_ptr;
+  }
+  SafeArrayDestroy(array);
 
-    return wchar_to_char(initialhost);
-  } else {
-    return nullptr;
+  return ret;
+}
+
+#define BRIDGE_CATCH(api, failure)                                             \
+  catch (const _com_error &e) {                                                \
+    set_last_com_error(api, e);                                                \
+    return failure;                                                            \
+  }                                                                            \
+  catch (...) {                                                                \
+    set_last_error(api, E_UNEXPECTED, L"Unknown exception");                   \
+    return failure;                                                            \
+  }
+
+extern "C" {
+
+bool bridge_take_last_error(BridgeError *error) {
+  // Either string may be null if the allocation failed
+  if (last_error.api == nullptr && last_error.message == nullptr) {
+    return false;
   }
+  *error = last_error;
+  last_error = {S_OK, nullptr, nullptr};
+
+  return true;
+}
+
+bool bridge_com_initialize() {
+  HRESULT hr = ::CoInitialize(0);
+  if (!check_hresult("CoInitialize", hr)) {
+    return false;
+  }
+  try {
+    ITtsControlPtr pTtsControl_(__uuidof(TtsControl));
+    pTtsControl = pTtsControl_;
+  }
+  BRIDGE_CATCH("CreateInstance", false)
+
+  return true;
+}
+
+char **bridge_get_available_host_names() {
+  try {
+    SAFEARRAY *hosts = pTtsControl->GetAvailableHostNames();
+    return safearray_to_utf8_array("GetAvailableHostNames", hosts);
+  }
+  BRIDGE_CATCH("GetAvailableHostNames", nullptr)
+}
+
+bool bridge_initialize(char *hostname) {
+  try {
+    _bstr_t hostname_bstr = utf8_to_bstr(hostname);
+    HRESULT hr = pTtsControl->Initialize(hostname_bstr);
+    return check_hresult("Initialize", hr);
+  }
+  BRIDGE_CATCH("Initialize", false)
 }
 
 bool bridge_initialized(char *host) {
-  VARIANT_BOOL initialized = pTtsControl->IsInitialized;
-  return initialized == VARIANT_TRUE;
+  try {
+    VARIANT_BOOL initialized = pTtsControl->IsInitialized;
+    return initialized == VARIANT_TRUE;
+  }
+  BRIDGE_CATCH("IsInitialized", false)
 }
 
 int32_t bridge_get_status() {
@@ -94,181 +235,161 @@ int32_t bridge_get_status() {
     default:
       return -2;
     }
-  } catch (...) {
-    return -1;
   }
+  BRIDGE_CATCH("Status", -1)
 }
 
 bool bridge_start_host() {
   try {
     HRESULT hr = pTtsControl->StartHost();
-    return SUCCEEDED(hr);
-  } catch (...) {
-    return false;
+    return check_hresult("StartHost", hr);
   }
+  BRIDGE_CATCH("StartHost", false)
 }
 
 bool bridge_connect() {
   try {
     HRESULT hr = pTtsControl->Connect();
-    return SUCCEEDED(hr);
-  } catch (...) {
-    return false;
+    return check_hresult("Connect", hr);
   }
+  BRIDGE_CATCH("Connect", false)
 }
 
 char *bridge_get_version() {
   try {
     _bstr_t version = pTtsControl->Version;
 
-    return wchar_to_char(version);
-  } catch (...) {
-    return nullptr;
+    return to_utf8("Version", version);
   }
+  BRIDGE_CATCH("Version", nullptr)
 }
 
 char **bridge_get_speakers() {
-  long lb, ub;
-
-  SAFEARRAY *voices = pTtsControl->VoiceNames;
-
-  SafeArrayGetLBound(voices, 1, &lb);
-  SafeArrayGetUBound(voices, 1, &ub);
-
-  char **ret = (char **)malloc(sizeof(char *) * (ub - lb + 2));
-
-  for (long i = lb; i <= ub; i++) {
-    _bstr_t voice;
-    SafeArrayGetElement(voices, &i, (void **)voice.GetAddress());
-    char *voice_ptr = wchar_to_char(voice);
-    if (voice_ptr == nullptr) {
-      printf("voice_ptr is nullptr\n");
-      return nullptr;
-    }
-    ret[i - lb] = voice_ptr;
+  try {
+    SAFEARRAY *voices = pTtsControl->VoiceNames;
+    return safearray_to_utf8_array("VoiceNames", voices);
   }
-
-  ret[ub - lb + 1] = nullptr;
-  return ret;
+  BRIDGE_CATCH("VoiceNames", nullptr)
 }
 
-void bridge_free_array(char **array) {
-  while (*array != nullptr) {
-    free(*array);
-    array++;
-  }
-}
+void bridge_free_array(char **array) { free_array(array); }
 
 bool bridge_set_text_edit_mode(int32_t mode) {
   try {
     pTtsControl->PutTextEditMode((TextEditMode)mode);
     return true;
-  } catch (...) {
-    return false;
   }
+  BRIDGE_CATCH("TextEditMode", false)
 }
 
 char **bridge_get_voice_preset_names() {
-  long lb, ub;
-
-  SAFEARRAY *presets = pTtsControl->VoicePresetNames;
-
-  SafeArrayGetLBound(presets, 1, &lb);
-  SafeArrayGetUBound(presets, 1, &ub);
-
-  char **ret = (char **)malloc(sizeof(char *) * (ub - lb + 2));
-
-  for (long i = lb; i <= ub; i++) {
-    _bstr_t preset;
-    SafeArrayGetElement(presets, &i, (void **)preset.GetAddress());
-    char *preset_ptr = wchar_to_char(preset);
-    if (preset_ptr == nullptr) {
-      printf("preset_ptr is nullptr\n");
-      return nullptr;
-    }
-    ret[i - lb] = preset_ptr;
+  try {
+    SAFEARRAY *presets = pTtsControl->VoicePresetNames;
+    return safearray_to_utf8_array("VoicePresetNames", presets);
   }
-
-  ret[ub - lb + 1] = nullptr;
-  return ret;
+  BRIDGE_CATCH("VoicePresetNames", nullptr)
 }
 
 bool bridge_add_voice_preset(char *json) {
   try {
-    _bstr_t json_bstr(json);
+    _bstr_t json_bstr = utf8_to_bstr(json);
     HRESULT hr = pTtsControl->AddVoicePreset(json_bstr);
-    return SUCCEEDED(hr);
-  } catch (...) {
-    return false;
+    return check_hresult("AddVoicePreset", hr);
   }
+  BRIDGE_CATCH("AddVoicePreset", false)
 }
 
 char *bridge_get_voice_preset(char *name) {
   try {
-    _bstr_t name_bstr(name);
+    _bstr_t name_bstr = utf8_to_bstr(name);
     _bstr_t preset = pTtsControl->GetVoicePreset(name_bstr);
-    return wchar_to_char(preset);
-  } catch (...) {
-    return nullptr;
+    return to_utf8("GetVoicePreset", preset);
   }
+  BRIDGE_CATCH("GetVoicePreset", nullptr)
 }
 
 bool bridge_terminate_host() {
   try {
     HRESULT hr = pTtsControl->TerminateHost();
-    return SUCCEEDED(hr);
-  } catch (...) {
-    return false;
+    return check_hresult("TerminateHost", hr);
   }
+  BRIDGE_CATCH("TerminateHost", false)
 }
 
 bool bridge_reload_phrase_dictionary() {
   try {
     HRESULT hr = pTtsControl->ReloadPhraseDictionary();
-    return SUCCEEDED(hr);
-  } catch (...) {
-    return false;
+    return check_hresult("ReloadPhraseDictionary", hr);
   }
+  BRIDGE_CATCH("ReloadPhraseDictionary", false)
 }
 
 bool bridge_set_text(char *text) {
   try {
-    _bstr_t text_bstr(text);
+    _bstr_t text_bstr = utf8_to_bstr(text);
     pTtsControl->Text = text_bstr;
     return true;
-  } catch (...) {
-    return false;
   }
+  BRIDGE_CATCH("Text", false)
+}
+
+char *bridge_get_text() {
+  try {
+    _bstr_t text = pTtsControl->Text;
+    return to_utf8("Text", text);
+  }
+  BRIDGE_CATCH("Text", nullptr)
 }
 
 bool bridge_save_audio_to_file(char *path) {
   try {
-    _bstr_t path_bstr(path);
+    _bstr_t path_bstr = utf8_to_bstr(path);
     HRESULT hr = pTtsControl->SaveAudioToFile(path_bstr);
-    return SUCCEEDED(hr);
-  } catch (...) {
-    return false;
+    return check_hresult("SaveAudioToFile", hr);
   }
+  BRIDGE_CATCH("SaveAudioToFile", false)
 }
 
 bool bridge_set_current_voice_preset_name(char *name) {
   try {
-    _bstr_t name_bstr(name);
+    _bstr_t name_bstr = utf8_to_bstr(name);
     pTtsControl->CurrentVoicePresetName = name_bstr;
     return true;
-  } catch (...) {
-    return false;
   }
+  BRIDGE_CATCH("CurrentVoicePresetName", false)
+}
+
+char *bridge_get_current_voice_preset_name() {
+  try {
+    _bstr_t name = pTtsControl->CurrentVoicePresetName;
+    return to_utf8("CurrentVoicePresetName", name);
+  }
+  BRIDGE_CATCH("CurrentVoicePresetName", nullptr)
 }
 
 bool bridge_set_voice_preset(char *json) {
   try {
-    _bstr_t json_bstr(json);
+    _bstr_t json_bstr = utf8_to_bstr(json);
     HRESULT hr = pTtsControl->SetVoicePreset(json_bstr);
-    return SUCCEEDED(hr);
-  } catch (...) {
-    return false;
+    return check_hresult("SetVoicePreset", hr);
+  }
+  BRIDGE_CATCH("SetVoicePreset", false)
+}
+char *bridge_get_master_control() {
+  try {
+    _bstr_t json = pTtsControl->MasterControl;
+    return to_utf8("MasterControl", json);
+  }
+  BRIDGE_CATCH("MasterControl", nullptr)
+}
+
+bool bridge_set_master_control(char *json) {
+  try {
+    _bstr_t json_bstr = utf8_to_bstr(json);
+    pTtsControl->MasterControl = json_bstr;
+    return true;
   }
+  BRIDGE_CATCH("MasterControl", false)
 }
 void bridge_free(char *ptr) { free(ptr); }
 }
@@ -1,8 +1,10 @@
+use crate::bridge::{BridgeError, BridgeErrorKind};
 use axum::{
     http::StatusCode,
     response::{IntoResponse, Response},
     Json,
 };
+
 use serde::Serialize;
 use thiserror::Error;
 
@@ -29,12 +31,28 @@ pub enum Error {
     TerminateHostFailed,
     #[error("設定をパースできませんでした")]
     SettingsParseFailed(#[source] anyhow::Error),
+    #[error("設定ファイルに{0}がありません")]
+    SettingsElementNotFound(String),
+    #[error("設定ファイルに{0}が複数あります")]
+    SettingsElementAmbiguous(String),
+    #[error(
+        "A.I.Voiceの設定ファイルが見つかりませんでした（{0}）。--editor-settingsで指定してください"
+    )]
+    SettingsNotFound(String),
+    #[error("A.I.Voiceの設定ファイルや書き換えの記録を書き込めませんでした")]
+    WriteSettingsFailed(#[source] anyhow::Error),
+    #[error("A.I.Voiceの設定ファイルを元に戻せませんでした")]
+    RestoreSettingsFailed(#[source] anyhow::Error),
     #[error("辞書を書き込めませんでした")]
     WriteDictionaryFailed(#[source] tokio::io::Error),
+    #[error("フレーズ辞書を元に戻せませんでした")]
+    RestoreDictionaryFailed(#[source] anyhow::Error),
     #[error("画像を読み込めませんでした")]
     ReadImageFailed(#[source] anyhow::Error),
     #[error("辞書を読み込めませんでした")]
     ReadDictionaryFailed(#[source] anyhow::Error),
+    #[error("A.I.Voiceのフレーズ辞書が無効になっています。A.I.Voiceの設定で有効にするか、--restart-editorを指定してください")]
+    PhraseDictionaryDisabled,
     #[error("辞書の操作に失敗しました")]
     DictionaryOperationFailed(#[source] anyhow::Error),
     #[error("解析中にエラーが発生しました")]
@@ -43,20 +61,105 @@ pub enum Error {
     SynthesisFailed(#[source] anyhow::Error),
     #[error("話者が見つかりませんでした")]
     SpeakerNotFound,
+    #[error("この話者の組み合わせはモーフィングできません")]
+    MorphingNotAllowed,
+    #[error("morph_rateは0.0から1.0の範囲で指定してください：{0}")]
+    InvalidMorphRate(f64),
+    #[error("このバックエンドはこの環境では利用できません")]
+    BackendUnavailable,
+    #[error("A.I.Voiceを再起動できませんでした")]
+    HostUnavailable,
+    #[error("A.I.Voiceを再起動しています。しばらくしてからやり直してください")]
+    Recovering,
+    #[error("ワーカーとの通信に失敗しました")]
+    WorkerFailed(#[source] anyhow::Error),
+    #[error("A.I.Voiceが時間内に応答しませんでした：{0}")]
+    Timeout(String),
+    #[error("A.I.VoiceのAPI呼び出しに失敗しました：{0}")]
+    BridgeFailed(BridgeError),
+    #[error("ホスト「{requested}」が見つかりませんでした（利用可能なホスト：{}）", available.join("、"))]
+    HostNotFound {
+        requested: String,
+        available: Vec<String>,
+    },
+    #[error("利用可能なA.I.Voiceのホストがありません")]
+    NoHostAvailable,
+    #[error("設定ファイルを読み込めませんでした")]
+    ConfigLoadFailed(#[source] anyhow::Error),
+    #[error("記録ファイルを読み書きできませんでした")]
+    RecordFailed(#[source] anyhow::Error),
+    #[error("記録に含まれていない呼び出しです：{0}")]
+    ReplayMissing(String),
+    #[error("話者IDのファイルを読み書きできませんでした")]
+    RegistryFailed(#[source] anyhow::Error),
+    #[error("合成結果のキャッシュを読み書きできませんでした")]
+    CacheFailed(#[source] anyhow::Error),
+    #[error("処理待ちのリクエストが多すぎます")]
+    QueueFull,
+    #[error("A.I.Voiceを操作するタスクが停止しています")]
+    ActorUnavailable,
+    #[error("リクエストがキャンセルされました")]
+    Cancelled,
 }
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Serialize)]
 pub struct ErrorResponse {
     pub error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<ErrorDetail>,
+}
+
+/// ブリッジ関数が失敗したときにレスポンスに含める詳細。
+#[derive(Serialize)]
+pub struct ErrorDetail {
+    pub api: String,
+    /// `0x80070057`のような16進数の文字列。
+    pub hresult: String,
+    pub message: String,
+    pub kind: BridgeErrorKind,
+}
+
+impl From<&BridgeError> for ErrorDetail {
+    fn from(e: &BridgeError) -> Self {
+        Self {
+            api: e.api.clone(),
+            hresult: format!("0x{:08X}", e.hresult as u32),
+            message: e.message.clone(),
+            kind: e.kind(),
+        }
+    }
+}
+
+impl Error {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Error::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            Error::HostUnavailable
+            | Error::Recovering
+            | Error::QueueFull
+            | Error::ActorUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            Error::MorphingNotAllowed | Error::InvalidMorphRate(_) => StatusCode::BAD_REQUEST,
+            Error::BridgeFailed(e) => match e.kind() {
+                BridgeErrorKind::InvalidArgument => StatusCode::BAD_REQUEST,
+                BridgeErrorKind::InvalidOperation => StatusCode::SERVICE_UNAVAILABLE,
+                BridgeErrorKind::Other => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
 }
 
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
         (
-            StatusCode::INTERNAL_SERVER_ERROR,
+            self.status_code(),
             Json(&ErrorResponse {
                 error: self.to_string(),
+                detail: match &self {
+                    Error::BridgeFailed(e) => Some(e.into()),
+                    _ => None,
+                },
             }),
         )
             .into_response()
@@ -0,0 +1,53 @@
+//! ホストを呼び出す専用のスレッド。
+//!
+//! A.I.Voice Editor APIはCOMなので、初期化したスレッドからしか呼び出せない。
+//! ホストの作成も含めて、すべての呼び出しをこのスレッドで順番に行う。
+
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::mpsc,
+};
+use tokio::sync::oneshot;
+use tracing::error;
+
+type Task = Box<dyn FnOnce() + Send>;
+
+#[derive(Debug, Clone)]
+pub struct HostThread {
+    sender: mpsc::Sender<Task>,
+}
+
+impl HostThread {
+    pub fn spawn() -> Self {
+        let (sender, receiver) = mpsc::channel::<Task>();
+        std::thread::Builder::new()
+            .name("aivoice-host".to_string())
+            .spawn(move || {
+                for task in receiver {
+                    // パニックしてもスレッドは止めない。結果を待っている側には送信側が落ちたことで伝わる
+                    if panic::catch_unwind(AssertUnwindSafe(task)).is_err() {
+                        error!("Host call panicked");
+                    }
+                }
+            })
+            .expect("failed to spawn the host thread");
+
+        Self { sender }
+    }
+
+    /// `f`をホストのスレッドで実行する。パニックした場合は受信側が`RecvError`になる。
+    pub fn run<T, F>(&self, f: F) -> oneshot::Receiver<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let task: Task = Box::new(move || {
+            let _ = sender.send(f());
+        });
+        self.sender
+            .send(task)
+            .expect("the host thread is not running");
+        receiver
+    }
+}
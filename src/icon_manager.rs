@@ -1,4 +1,4 @@
-use crate::aivoice::AIVOICE;
+use crate::aivoice::{AiVoice, NORMAL_STYLE};
 use crate::error::{Error, Result};
 
 use anyhow::anyhow;
@@ -7,8 +7,7 @@ use derive_getters::Getters;
 use image::imageops;
 use once_cell::sync::Lazy;
 use std::io::Cursor;
-use std::{collections::HashMap, path::Path, sync::Arc};
-use tasklist::{get_proc_path, tasklist};
+use std::{collections::HashMap, sync::Arc};
 use tokio::sync::Mutex;
 use tracing::info;
 
@@ -17,12 +16,29 @@ pub struct IconManager {
     icons: HashMap<String, StyleImages>,
     portraits: HashMap<String, StyleImages>,
 }
-#[derive(Debug, Getters)]
-pub struct StyleImages {
-    normal: Vec<u8>,
-    joy: Vec<u8>,
-    anger: Vec<u8>,
-    sorrow: Vec<u8>,
+/// スタイル名ごとの画像。
+#[derive(Debug, Default)]
+pub struct StyleImages(HashMap<String, Vec<u8>>);
+
+impl StyleImages {
+    /// `style`の画像。無ければノーマルの画像を返す。
+    pub fn get(&self, style: &str) -> &[u8] {
+        self.0
+            .get(style)
+            .filter(|image| !image.is_empty())
+            .or_else(|| self.0.get(NORMAL_STYLE))
+            .map_or(&[], |image| image.as_slice())
+    }
+}
+
+/// アイコンの背景色。知らないスタイルは白にする。
+fn icon_background(style: &str) -> image::Rgba<u8> {
+    match style {
+        "J" => image::Rgba([255, 255, 200, 128]),
+        "A" => image::Rgba([255, 200, 200, 128]),
+        "S" => image::Rgba([200, 200, 255, 128]),
+        _ => image::Rgba([255, 255, 255, 128]),
+    }
 }
 
 impl IconManager {
@@ -33,26 +49,24 @@ impl IconManager {
         }
     }
 
-    pub async fn setup(&mut self) -> Result<()> {
-        let mut tasks = unsafe { tasklist().into_iter() };
-        let Some((_, aivoice_process_id)) = tasks.find(|(task_name, _)| task_name.to_lowercase() == "aivoiceeditor.exe") else {
-            return Err(Error::ProcessNotFound);
+    pub async fn setup(&mut self, aivoice: &AiVoice) -> Result<()> {
+        let Some(voice_path) = aivoice.host().voice_directory() else {
+            info!("Voice directory is not available, using blank images");
+            for speaker in aivoice.speakers().values() {
+                self.icons
+                    .insert(speaker.internal_name().to_string(), StyleImages::default());
+                self.portraits
+                    .insert(speaker.internal_name().to_string(), StyleImages::default());
+            }
+            return Ok(());
         };
 
-        info!("A.I.Voice process id: {}", aivoice_process_id);
-        let aivoice_process_path = unsafe { get_proc_path(aivoice_process_id) };
-        let aivoice_process_path = Path::new(&aivoice_process_path);
-        info!("Process path: {}", &aivoice_process_path.display());
-
-        let voice_path = aivoice_process_path
-            .parent()
-            .unwrap()
-            .parent()
-            .unwrap()
-            .join("Voice");
-
         info!("Voice path: {}", &voice_path.display());
-        for speaker in AIVOICE.lock().await.speakers().values() {
+        for speaker in aivoice.speakers().values() {
+            // ユーザーのボイスプリセットはボイスと同じ画像を使う
+            if self.icons.contains_key(speaker.internal_name()) {
+                continue;
+            }
             info!("Extracting icon for {}", speaker.internal_name());
             let images_path = voice_path.join(speaker.internal_name()).join("images.dat");
             info!("Images path: {}", &images_path.display());
@@ -93,77 +107,36 @@ impl IconManager {
                     .await
                     .map_err(|e| Error::ReadImageFailed(e.into()))?;
 
-                let mut normal_bg =
-                    image::ImageBuffer::from_fn(48, 48, |_, _| image::Rgba([255, 255, 255, 128]));
-                let mut joy_bg =
-                    image::ImageBuffer::from_fn(48, 48, |_, _| image::Rgba([255, 255, 200, 128]));
-                let mut anger_bg =
-                    image::ImageBuffer::from_fn(48, 48, |_, _| image::Rgba([255, 200, 200, 128]));
-                let mut sorrow_bg =
-                    image::ImageBuffer::from_fn(48, 48, |_, _| image::Rgba([200, 200, 255, 128]));
-
                 let icon = image::load_from_memory(&icon_buf)
                     .map_err(|e| Error::ReadImageFailed(e.into()))?
                     .resize(48, 48, image::imageops::FilterType::Triangle)
                     .to_rgba8();
 
-                imageops::overlay(&mut normal_bg, &icon, 0, 0);
-                imageops::overlay(&mut joy_bg, &icon, 0, 0);
-                imageops::overlay(&mut anger_bg, &icon, 0, 0);
-                imageops::overlay(&mut sorrow_bg, &icon, 0, 0);
-
-                let mut normal_icon_buf = Vec::new();
-                let mut joy_icon_buf = Vec::new();
-                let mut anger_icon_buf = Vec::new();
-                let mut sorrow_icon_buf = Vec::new();
-
-                normal_bg
-                    .write_to(
-                        &mut Cursor::new(&mut normal_icon_buf),
-                        image::ImageOutputFormat::Png,
-                    )
-                    .map_err(|e| Error::ReadImageFailed(e.into()))?;
-                joy_bg
-                    .write_to(
-                        &mut Cursor::new(&mut joy_icon_buf),
-                        image::ImageOutputFormat::Png,
-                    )
-                    .map_err(|e| Error::ReadImageFailed(e.into()))?;
-                anger_bg
-                    .write_to(
-                        &mut Cursor::new(&mut anger_icon_buf),
-                        image::ImageOutputFormat::Png,
-                    )
-                    .map_err(|e| Error::ReadImageFailed(e.into()))?;
-                sorrow_bg
-                    .write_to(
-                        &mut Cursor::new(&mut sorrow_icon_buf),
-                        image::ImageOutputFormat::Png,
-                    )
-                    .map_err(|e| Error::ReadImageFailed(e.into()))?;
-
-                self.icons.insert(
-                    speaker.internal_name().to_string(),
-                    StyleImages {
-                        normal: normal_icon_buf,
-                        joy: joy_icon_buf,
-                        anger: anger_icon_buf,
-                        sorrow: sorrow_icon_buf,
-                    },
-                );
+                let mut icons = StyleImages::default();
+                for style in speaker.styles() {
+                    let mut background =
+                        image::ImageBuffer::from_pixel(48, 48, icon_background(style.name()));
+                    imageops::overlay(&mut background, &icon, 0, 0);
+
+                    let mut icon_buf = Vec::new();
+                    background
+                        .write_to(
+                            &mut Cursor::new(&mut icon_buf),
+                            image::ImageOutputFormat::Png,
+                        )
+                        .map_err(|e| Error::ReadImageFailed(e.into()))?;
+                    icons.0.insert(style.name().clone(), icon_buf);
+                }
+                self.icons
+                    .insert(speaker.internal_name().to_string(), icons);
 
                 root
             };
 
             info!("{} root: {:?}", speaker.internal_name(), root);
 
-            let mut portraits = StyleImages {
-                normal: Vec::new(),
-                joy: Vec::new(),
-                anger: Vec::new(),
-                sorrow: Vec::new(),
-            };
-            for emotion in ['A', 'J', 'N', 'S'].iter() {
+            let mut portraits = StyleImages::default();
+            for style in speaker.styles() {
                 let mut images = zip_reader.file().entries().iter().enumerate();
                 let image_index = match images.find(|(_, x)| {
                     let name = x
@@ -173,7 +146,7 @@ impl IconManager {
                         .unwrap_or("")
                         .replace('\\', "/");
 
-                    name.starts_with(&format!("{}{}/OpenEyes", root, emotion))
+                    name.starts_with(&format!("{}{}/OpenEyes", root, style.name()))
                         && name.split('/').last().and_then(|n| n.chars().nth(4)) == Some('0')
                 }) {
                     Some((i, _)) => i,
@@ -184,13 +157,7 @@ impl IconManager {
                     Err(_) => continue,
                 };
 
-                let final_image_buf = match emotion {
-                    'A' => &mut portraits.anger,
-                    'J' => &mut portraits.joy,
-                    'N' => &mut portraits.normal,
-                    'S' => &mut portraits.sorrow,
-                    _ => unreachable!(),
-                };
+                let final_image_buf = portraits.0.entry(style.name().clone()).or_default();
                 let image_buf = &mut Vec::new();
                 image_entry
                     .read_to_end_checked(image_buf)
@@ -207,24 +174,7 @@ impl IconManager {
                     .write_to(&mut final_image_cursor, image::ImageOutputFormat::Png)
                     .map_err(|e| Error::ReadImageFailed(e.into()))?;
             }
-            // 一部キャラはN以外の立ち絵がないので全部チェックはしない
-            // for (name, portrait) in [
-            //     ("Normal", &portraits.normal),
-            //     ("Joy", &portraits.joy),
-            //     ("Anger", &portraits.anger),
-            //     ("Sorrow", &portraits.sorrow),
-            // ]
-            // .iter()
-            // {
-            //     if portrait.is_empty() {
-            //         return Err(Error::ReadImageFailed(anyhow!(
-            //             "{}の{}の立ち絵が見つかりませんでした。",
-            //             speaker.internal_name(),
-            //             name,
-            //         )));
-            //     }
-            // }
-
+            // 一部キャラはN以外の立ち絵がないので、無いスタイルはノーマルの立ち絵を使う
             self.portraits
                 .insert(speaker.internal_name().to_string(), portraits);
         }
@@ -233,5 +183,18 @@ impl IconManager {
     }
 }
 
+/// 話者一覧が変わったときに画像を読み直す。まだ読み込んでいなければ何もしない。
+///
+/// 読み込み中も`speaker_info`に答えられるように、別に読み込んでから入れ替える。
+pub async fn reload_icons(aivoice: &AiVoice) -> Result<()> {
+    if ICON_MANAGER.lock().await.icons().is_empty() {
+        return Ok(());
+    }
+    let mut icon_manager = IconManager::new();
+    icon_manager.setup(aivoice).await?;
+    *ICON_MANAGER.lock().await = icon_manager;
+    Ok(())
+}
+
 pub static ICON_MANAGER: Lazy<Arc<Mutex<IconManager>>> =
     Lazy::new(|| Arc::new(Mutex::new(IconManager::new())));
@@ -1,16 +1,35 @@
 #![allow(dead_code)]
+mod actor;
 mod aivoice;
+mod backend;
 mod bridge;
+mod config;
 mod error;
+mod host_thread;
 mod icon_manager;
+mod phrase_dictionary_journal;
+mod phrase_pool;
+mod preset_mapping;
+mod registry;
 mod routes;
+mod settings_document;
+mod settings_journal;
 mod settings_modifier;
+mod supervisor;
+mod synthesis_cache;
 mod voicevox;
+mod watchdog;
+mod wav;
+mod worker;
 
-use crate::aivoice::AIVOICE;
-use crate::icon_manager::ICON_MANAGER;
+use crate::actor::ACTOR;
+use crate::aivoice::AiVoiceOptions;
+use crate::backend::{BackendKind, BackendOptions};
+use crate::config::Config;
 use crate::routes::audio_query::OPEN_JTALK;
 use crate::routes::user_dict::USER_DICT;
+use crate::settings_modifier::{EditorSettings, ExportFormat, SettingsModifier};
+use crate::watchdog::WatchdogOptions;
 
 use anyhow::Result;
 use axum::{
@@ -18,8 +37,8 @@ use axum::{
     routing::{delete, get, post, put},
     Router,
 };
-use clap::Parser;
-use std::net::SocketAddr;
+use clap::{Parser, Subcommand};
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
 use tower_http::{cors::CorsLayer, trace};
 use tracing::{info, Level};
 
@@ -33,6 +52,77 @@ struct Cli {
     /// ポート番号。
     #[clap(short, long)]
     port: Option<u16>,
+    /// 音声合成に使うバックエンド。
+    #[clap(long, value_enum, global = true)]
+    backend: Option<BackendKind>,
+    /// `--backend remote`で接続するワーカーのアドレス（host:port）。
+    #[clap(long)]
+    worker_address: Option<String>,
+    /// `--backend remote`で起動するワーカーのプログラム。標準入出力で通信する。
+    #[clap(long)]
+    worker_command: Option<PathBuf>,
+    /// `--worker-command`に渡す引数。複数指定できる。
+    #[clap(long = "worker-arg", allow_hyphen_values = true)]
+    worker_args: Vec<String>,
+    /// 設定ファイルのパス。省略すると実行ファイルと同じフォルダの`config.json`を使う。
+    #[clap(long, global = true)]
+    config: Option<PathBuf>,
+    /// 使うA.I.Voiceのホスト名。設定ファイルの`host_name`より優先する。
+    #[clap(long, global = true)]
+    host_name: Option<String>,
+    /// ホストの呼び出しと結果を記録するファイル。
+    #[clap(long, global = true)]
+    record: Option<PathBuf>,
+    /// `--backend replay`で再生する、`--record`で記録したファイル。
+    #[clap(long, global = true)]
+    replay_file: Option<PathBuf>,
+    /// A.I.VoiceのAPI呼び出しの制限時間（秒）。
+    #[clap(long, global = true)]
+    call_timeout: Option<u64>,
+    /// 音声の書き出しの制限時間（秒）。
+    #[clap(long, global = true)]
+    synthesis_timeout: Option<u64>,
+    /// A.I.Voiceの起動を待つ時間（秒）。
+    #[clap(long, global = true)]
+    start_timeout: Option<u64>,
+    /// 続けてこの回数タイムアウトしたらA.I.Voiceを再起動する。
+    #[clap(long, global = true)]
+    hang_limit: Option<u32>,
+    /// 起動中のA.I.Voiceを終了させてから起動し直す。指定しない場合は起動中のA.I.Voiceに接続する。
+    #[clap(long, global = true)]
+    restart_editor: bool,
+    /// この秒数使われなかったらA.I.Voiceを終了させる。次のリクエストで起動し直す。
+    #[clap(long, global = true)]
+    idle_timeout: Option<u64>,
+    /// 処理を待てるリクエストの数。
+    #[clap(long, global = true)]
+    queue_size: Option<usize>,
+    /// この秒数ごとに話者一覧を読み直す。A.I.Voiceを起動していない間は読み直さない。
+    #[clap(long, global = true)]
+    rescan_interval: Option<u64>,
+    /// A.I.Voiceの設定ファイル。省略するとインストールされているA.I.Voiceの`Standard.settings`を探す。
+    #[clap(long, global = true)]
+    editor_settings: Option<PathBuf>,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// A.I.Voiceを操作するワーカーとして起動する。
+    Worker {
+        /// 待ち受けるアドレス（host:port）かポート番号。ポート番号だけなら127.0.0.1で待ち受ける。
+        /// 省略すると標準入出力で通信する。
+        #[clap(long)]
+        listen: Option<String>,
+        /// ループバック以外のアドレスでの待ち受けを許す。ワーカーは認証しないので、信頼できるネットワークでだけ使う。
+        #[clap(long)]
+        allow_remote: bool,
+    },
+    /// A.I.Voiceの設定ファイルを書き換えずに、起動時に書き換える内容を表示する。
+    SettingsDiff,
+    /// 前回のプロセスが書き換えたまま終了したA.I.Voiceの設定ファイルを元に戻す。
+    RestoreSettings,
 }
 
 #[tokio::main]
@@ -44,21 +134,118 @@ async fn main() -> Result<()> {
         .with_ansi(cfg!(debug_assertions))
         .init();
 
-    AIVOICE.lock().await.setup().await?;
+    let config = Config::load(args.config.as_deref())?;
+
+    let default_watchdog = WatchdogOptions::default();
+    let options = AiVoiceOptions {
+        backend: BackendOptions {
+            kind: args.backend.unwrap_or_default(),
+            worker_address: args.worker_address.clone(),
+            worker_command: args.worker_command.clone(),
+            worker_args: args.worker_args.clone(),
+            host_name: args.host_name.clone().or(config.host_name),
+            record: args.record.clone(),
+            replay_file: args.replay_file.clone(),
+            editor_settings: args.editor_settings.clone().or(config.editor_settings),
+        },
+        watchdog: WatchdogOptions {
+            call_timeout: args
+                .call_timeout
+                .map_or(default_watchdog.call_timeout, Duration::from_secs),
+            synthesis_timeout: args
+                .synthesis_timeout
+                .map_or(default_watchdog.synthesis_timeout, Duration::from_secs),
+            start_timeout: args
+                .start_timeout
+                .map_or(default_watchdog.start_timeout, Duration::from_secs),
+            hang_limit: args.hang_limit.unwrap_or(default_watchdog.hang_limit),
+        },
+        editor: EditorSettings {
+            begin_pause: config.begin_pause.unwrap_or_default(),
+            term_pause: config.term_pause.unwrap_or_default(),
+            export: ExportFormat {
+                sampling_rate: config
+                    .export_sampling_rate
+                    .unwrap_or(ExportFormat::default().sampling_rate),
+                bit_depth: config.export_bit_depth.unwrap_or_default(),
+            },
+        },
+        restart_editor: args.restart_editor || config.restart_editor.unwrap_or_default(),
+        idle_timeout: args
+            .idle_timeout
+            .or(config.idle_timeout)
+            .map(Duration::from_secs),
+        queue_size: args
+            .queue_size
+            .or(config.queue_size)
+            .unwrap_or(actor::DEFAULT_QUEUE_SIZE),
+        registry_path: config.speaker_registry,
+        voice_fusion: config.voice_fusion.unwrap_or(true),
+        user_presets: config.user_presets.unwrap_or_default(),
+        rescan_interval: args
+            .rescan_interval
+            .or(config.rescan_interval)
+            .map(Duration::from_secs),
+        phrase_pool_size: config
+            .phrase_pool_size
+            .unwrap_or(phrase_pool::DEFAULT_PHRASE_POOL_SIZE),
+        native_sampling_rate: config.native_sampling_rate.unwrap_or_default(),
+    };
+
+    match args.command {
+        Some(Command::Worker {
+            listen,
+            allow_remote,
+        }) => {
+            worker::run(options, listen, allow_remote).await?;
+            return Ok(());
+        }
+        Some(Command::SettingsDiff) => {
+            let path =
+                SettingsModifier::find_settings_path(options.backend.editor_settings.as_deref())?;
+            let settings = fs_err::read_to_string(&path)?;
+            let document = SettingsModifier::new(options.editor, path.clone())
+                .apply(settings, &backend::temporary_phrase_dict_path())?;
+            println!("--- {}", path.display());
+            print!("{}", document.diff());
+            return Ok(());
+        }
+        Some(Command::RestoreSettings) => {
+            let path =
+                SettingsModifier::find_settings_path(options.backend.editor_settings.as_deref())?;
+            if SettingsModifier::restore(&path)? {
+                println!("設定ファイルを元に戻しました：{}", path.display());
+            } else {
+                println!("元に戻す設定はありません：{}", path.display());
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+
+    if let Some(size) = config.synthesis_cache_size.filter(|size| *size > 0) {
+        let directory = config
+            .synthesis_cache_dir
+            .unwrap_or_else(synthesis_cache::default_directory);
+        let cache = synthesis_cache::SynthesisCache::open(&directory, size * 1024 * 1024)?;
+        synthesis_cache::SYNTHESIS_CACHE.set(cache).unwrap();
+    }
+
+    actor::init(options).await?;
 
     let result = main_impl(args).await;
 
     info!("Shutting down...");
 
-    AIVOICE.lock().await.shutdown().await?;
+    ACTOR.run(|aivoice, _| Box::pin(aivoice.shutdown())).await?;
 
     result?;
 
     Ok(())
 }
 
-async fn main_impl(args: Cli) -> Result<()> {
-    let app = Router::new()
+fn app() -> Router {
+    Router::new()
         .route("/", get(get_index))
         .route("/version", get(routes::info::get_version))
         .route("/engine_manifest", get(routes::info::get_engine_manifest))
@@ -68,6 +255,10 @@ async fn main_impl(args: Cli) -> Result<()> {
         )
         .route("/speakers", get(routes::speakers::get_speakers))
         .route("/speaker_info", get(routes::speakers::get_speaker_info))
+        .route(
+            "/rescan_speakers",
+            post(routes::speakers::post_rescan_speakers),
+        )
         .route(
             "/is_initialized_speaker",
             get(routes::speakers::get_is_initialized_speaker),
@@ -95,32 +286,43 @@ async fn main_impl(args: Cli) -> Result<()> {
             post(routes::audio_query::post_accent_phrases),
         )
         .route("/synthesis", post(routes::synthesis::post_synthesis))
+        .route(
+            "/synthesis_cache",
+            get(routes::synthesis::get_synthesis_cache)
+                .delete(routes::synthesis::delete_synthesis_cache),
+        )
+        .route(
+            "/synthesis_morphing",
+            post(routes::synthesis::post_synthesis_morphing),
+        )
+        .route(
+            "/morphable_targets",
+            post(routes::speakers::post_morphable_targets),
+        )
+        .route("/host_state", get(routes::host::get_host_state))
+        .route("/hosts", get(routes::host::get_hosts))
+        .route("/queue", get(routes::host::get_queue))
+        .route(
+            "/master_control",
+            get(routes::master_control::get_master_control),
+        )
+        .route(
+            "/master_control",
+            put(routes::master_control::put_master_control),
+        )
         .layer(CorsLayer::permissive())
         .layer(
             trace::TraceLayer::new_for_http()
                 .make_span_with(trace::DefaultMakeSpan::new().level(Level::INFO))
                 .on_response(trace::DefaultOnResponse::new().level(Level::INFO)),
-        );
-
-    ICON_MANAGER.lock().await.setup().await?;
+        )
+}
 
+async fn main_impl(args: Cli) -> Result<()> {
     let port = args.port.unwrap_or(50201);
 
     let addr = SocketAddr::from(([127, 0, 0, 1], port));
 
-    info!("Speakers:");
-    {
-        let aivoice = AIVOICE.lock().await;
-        for speaker in aivoice.speakers().values() {
-            info!(
-                "  {} ({}, {})",
-                speaker.display_name(),
-                speaker.id(),
-                speaker.uuid().hyphenated()
-            );
-        }
-    }
-
     info!("Starting server...");
 
     {
@@ -132,7 +334,7 @@ async fn main_impl(args: Cli) -> Result<()> {
     info!("Listening on port {}", port);
 
     axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+        .serve(app().into_make_service())
         .with_graceful_shutdown(async {
             tokio::signal::ctrl_c()
                 .await
@@ -146,3 +348,340 @@ async fn main_impl(args: Cli) -> Result<()> {
 async fn get_index() -> impl IntoResponse {
     Redirect::permanent("https://github.com/sevenc-nanashi/aivoice-vox")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use once_cell::sync::Lazy;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    static REGISTRY_DIR: Lazy<tempfile::TempDir> = Lazy::new(|| tempfile::tempdir().unwrap());
+    static CACHE_DIR: Lazy<tempfile::TempDir> = Lazy::new(|| tempfile::tempdir().unwrap());
+
+    /// アクターのタスクがテストごとのランタイムと一緒に止まらないよう、全テストで同じランタイムを使う。
+    static RUNTIME: Lazy<tokio::runtime::Runtime> = Lazy::new(|| {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime
+            .block_on(actor::init(AiVoiceOptions {
+                backend: BackendOptions {
+                    kind: BackendKind::Fake,
+                    ..Default::default()
+                },
+                watchdog: WatchdogOptions::default(),
+                editor: EditorSettings::default(),
+                restart_editor: false,
+                idle_timeout: None,
+                queue_size: actor::DEFAULT_QUEUE_SIZE,
+                registry_path: Some(REGISTRY_DIR.path().join("speakers.json")),
+                voice_fusion: false,
+                user_presets: true,
+                rescan_interval: None,
+                phrase_pool_size: phrase_pool::DEFAULT_PHRASE_POOL_SIZE,
+                native_sampling_rate: false,
+            }))
+            .unwrap();
+        let cache =
+            synthesis_cache::SynthesisCache::open(CACHE_DIR.path(), 64 * 1024 * 1024).unwrap();
+        synthesis_cache::SYNTHESIS_CACHE.set(cache).unwrap();
+        runtime
+    });
+
+    async fn request(method: &str, uri: &str, body: Option<Value>) -> (StatusCode, Vec<u8>) {
+        let request = Request::builder().method(method).uri(uri);
+        let request = match body {
+            Some(body) => request
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        }
+        .unwrap();
+        let response = app().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, body.to_vec())
+    }
+
+    async fn first_style_id() -> u64 {
+        let (status, body) = request("GET", "/speakers", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let speakers: Value = serde_json::from_slice(&body).unwrap();
+        speakers[0]["styles"][0]["id"].as_u64().unwrap()
+    }
+
+    fn audio_query(output_sampling_rate: u32, output_stereo: bool) -> Value {
+        let mora = |text: &str, consonant: &str, vowel: &str| {
+            serde_json::json!({
+                "text": text,
+                "consonant": consonant,
+                "consonant_length": 0.1,
+                "vowel": vowel,
+                "vowel_length": 0.1,
+                "pitch": 5.0,
+            })
+        };
+        serde_json::json!({
+            "accent_phrases": [{
+                "moras": [mora("テ", "t", "e"), mora("ス", "s", "U"), mora("ト", "t", "o")],
+                "accent": 1,
+                "pause_mora": null,
+            }],
+            "speedScale": 1.0,
+            "pitchScale": 0.0,
+            "intonationScale": 1.0,
+            "volumeScale": 1.0,
+            "prePhonemeLength": 0.1,
+            "postPhonemeLength": 0.1,
+            "outputSamplingRate": output_sampling_rate,
+            "outputStereo": output_stereo,
+            "kana": "テ'スト",
+        })
+    }
+
+    fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn speakers_lists_fake_voices() {
+        RUNTIME.block_on(async {
+            let (status, body) = request("GET", "/speakers", None).await;
+            assert_eq!(status, StatusCode::OK);
+            let speakers: Value = serde_json::from_slice(&body).unwrap();
+            let speakers = speakers.as_array().unwrap();
+            assert!(!speakers.is_empty());
+            for speaker in speakers {
+                assert!(!speaker["styles"].as_array().unwrap().is_empty());
+            }
+        });
+    }
+
+    #[test]
+    fn audio_query_returns_accent_phrases() {
+        RUNTIME.block_on(async {
+            let style_id = first_style_id().await;
+            let (status, body) = request(
+                "POST",
+                // 「テスト」
+                &format!(
+                    "/audio_query?text=%E3%83%86%E3%82%B9%E3%83%88&speaker={}",
+                    style_id
+                ),
+                None,
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+            let audio_query: Value = serde_json::from_slice(&body).unwrap();
+            assert!(!audio_query["accent_phrases"].as_array().unwrap().is_empty());
+            assert_eq!(audio_query["outputSamplingRate"], 24000);
+        });
+    }
+
+    #[test]
+    fn synthesis_returns_wav() {
+        RUNTIME.block_on(async {
+            let style_id = first_style_id().await;
+            for (sampling_rate, stereo) in [(24000, true), (48000, false)] {
+                let (status, body) = request(
+                    "POST",
+                    &format!("/synthesis?speaker={}", style_id),
+                    Some(audio_query(sampling_rate, stereo)),
+                )
+                .await;
+                assert_eq!(status, StatusCode::OK);
+                assert_eq!(&body[0..4], b"RIFF");
+                assert_eq!(u32_at(&body, 4) as usize, body.len() - 8);
+                assert_eq!(&body[8..16], b"WAVEfmt ");
+                assert_eq!(u16_at(&body, 22), if stereo { 2 } else { 1 });
+                assert_eq!(u32_at(&body, 24), sampling_rate);
+                assert!(body.len() > 44);
+            }
+        });
+    }
+
+    #[test]
+    fn synthesis_rejects_invalid_query() {
+        RUNTIME.block_on(async {
+            let style_id = first_style_id().await;
+            let (status, _) = request(
+                "POST",
+                &format!("/synthesis?speaker={}", style_id),
+                Some(serde_json::json!({ "kana": "テスト" })),
+            )
+            .await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        });
+    }
+
+    #[test]
+    fn cached_synthesis_does_not_use_host() {
+        RUNTIME.block_on(async {
+            let style_id = first_style_id().await;
+            let uri = format!("/synthesis?speaker={}", style_id);
+            let mut query = audio_query(22050, false);
+            query["volumeScale"] = serde_json::json!(0.75);
+            let (status, synthesized) = request("POST", &uri, Some(query.clone())).await;
+            assert_eq!(status, StatusCode::OK);
+            // 合成したジョブの後に公開された`HostInfo`でキーを作れるように、次のジョブまで待つ
+            ACTOR.run(|_, _| Box::pin(async { Ok(()) })).await.unwrap();
+
+            // ホストはアクターからしか使えないので、アクターを止めている間に返せればホストを使っていない
+            let (started_sender, started) = tokio::sync::oneshot::channel();
+            let (release, released) = tokio::sync::oneshot::channel::<()>();
+            let blocker = tokio::spawn(ACTOR.run(move |_, _| {
+                Box::pin(async move {
+                    let _ = started_sender.send(());
+                    let _ = released.await;
+                    Ok(())
+                })
+            }));
+            started.await.unwrap();
+            let cached =
+                tokio::time::timeout(Duration::from_secs(5), request("POST", &uri, Some(query)))
+                    .await;
+            release.send(()).unwrap();
+            blocker.await.unwrap().unwrap();
+
+            let (status, cached) = cached.expect("cache hit waited for the actor");
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(cached, synthesized);
+        });
+    }
+
+    /// `/speakers`から`name`の話者を探し、スタイル名とIDを返す。
+    async fn speaker_styles(name: &str) -> Vec<(String, u64)> {
+        let (status, body) = request("GET", "/speakers", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let speakers: Value = serde_json::from_slice(&body).unwrap();
+        let speaker = speakers
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|speaker| speaker["name"] == name)
+            .unwrap();
+        assert_eq!(
+            speaker["supported_features"]["permitted_synthesis_morphing"],
+            "SELF_ONLY"
+        );
+        speaker["styles"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|style| {
+                (
+                    style["name"].as_str().unwrap().to_string(),
+                    style["id"].as_u64().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn speaker_info_returns_style_images() {
+        RUNTIME.block_on(async {
+            let (status, body) = request("GET", "/speakers", None).await;
+            assert_eq!(status, StatusCode::OK);
+            let speakers: Value = serde_json::from_slice(&body).unwrap();
+            let speaker = &speakers[0];
+            let (status, body) = request(
+                "GET",
+                &format!(
+                    "/speaker_info?speaker_uuid={}",
+                    speaker["speaker_uuid"].as_str().unwrap()
+                ),
+                None,
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+            let speaker_info: Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(
+                speaker_info["style_infos"].as_array().unwrap().len(),
+                speaker["styles"].as_array().unwrap().len()
+            );
+
+            let (status, _) = request(
+                "GET",
+                "/speaker_info?speaker_uuid=00000000-0000-0000-0000-000000000000",
+                None,
+            )
+            .await;
+            assert_eq!(status, StatusCode::NOT_FOUND);
+        });
+    }
+
+    #[test]
+    fn morphable_targets_are_self_only() {
+        RUNTIME.block_on(async {
+            let preset = speaker_styles("フェイク（早口）").await;
+            let voice = speaker_styles("フェイク（感情）").await;
+            let (status, body) = request(
+                "POST",
+                "/morphable_targets",
+                Some(serde_json::json!([preset[0].1, voice[0].1])),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+            let targets: Value = serde_json::from_slice(&body).unwrap();
+
+            // ユーザーのボイスプリセットは、同じボイスでも元の話者とは別の話者として扱う
+            for (_, id) in &preset {
+                assert_eq!(targets[0][id.to_string()]["is_morphable"], true);
+                assert_eq!(targets[1][id.to_string()]["is_morphable"], false);
+            }
+            for (_, id) in &voice {
+                assert_eq!(targets[0][id.to_string()]["is_morphable"], false);
+                assert_eq!(targets[1][id.to_string()]["is_morphable"], true);
+            }
+        });
+    }
+
+    #[test]
+    fn synthesis_morphing_is_self_only() {
+        RUNTIME.block_on(async {
+            let preset = speaker_styles("フェイク（早口）").await;
+            let voice = speaker_styles("フェイク（感情）").await;
+            let morph = |base: u64, target: u64| {
+                format!(
+                    "/synthesis_morphing?base_speaker={}&target_speaker={}&morph_rate=0.5",
+                    base, target
+                )
+            };
+
+            let (status, body) = request(
+                "POST",
+                &morph(preset[0].1, preset[1].1),
+                Some(audio_query(24000, false)),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(&body[0..4], b"RIFF");
+
+            let (status, _) = request(
+                "POST",
+                &morph(preset[0].1, voice[0].1),
+                Some(audio_query(24000, false)),
+            )
+            .await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+        });
+    }
+
+    #[test]
+    fn actor_survives_panicking_job() {
+        RUNTIME.block_on(async {
+            let result: error::Result<()> = ACTOR
+                .run(|_, _| Box::pin(async { panic!("job panicked") }))
+                .await;
+            assert!(matches!(result, Err(error::Error::ActorUnavailable)));
+            first_style_id().await;
+        });
+    }
+}
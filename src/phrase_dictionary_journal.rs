@@ -0,0 +1,278 @@
+//! ユーザーのフレーズ辞書を書き換えたことの記録。
+//!
+//! 起動中のA.I.Voiceにアタッチしている場合は、ユーザーのフレーズ辞書にフレーズを書き込むしかない。
+//! 最初に書き込む前に元の辞書をバックアップしてこの記録を書き、元に戻したら両方消す。
+//! 途中でプロセスが終了して記録が残っていた場合は、次に起動したときに記録を使って元に戻す。
+
+use crate::error::{Error, Result};
+use crate::settings_journal::{checksum, write_atomically};
+
+use anyhow::anyhow;
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::{
+    panic,
+    path::{Path, PathBuf},
+    sync::Once,
+    thread,
+};
+use tracing::{error, info, warn};
+
+const JOURNAL_FILE_NAME: &str = "phrase_dictionary.journal";
+const BACKUP_FILE_NAME: &str = "phrase_dictionary.pdic.bak";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PhraseDictionaryJournal {
+    /// 書き換えたプロセスのID。ログにだけ使う。
+    pid: u32,
+    /// 最初に書き換えた日時（RFC 3339）。
+    modified_at: String,
+    /// 書き換えたフレーズ辞書。
+    path: PathBuf,
+    /// 元のフレーズ辞書（バックアップ）のチェックサム。元は無かった場合は`None`。
+    original_checksum: Option<String>,
+    /// 最後に書き込んだフレーズ辞書のチェックサム。
+    written_checksum: String,
+}
+
+impl PhraseDictionaryJournal {
+    fn load(path: &Path) -> Result<Option<Self>> {
+        let contents = match fs_err::read(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::RestoreDictionaryFailed(e.into())),
+        };
+        serde_json::from_slice(&contents)
+            .map(Some)
+            .map_err(|e| Error::RestoreDictionaryFailed(e.into()))
+    }
+
+    fn save(&self, path: &Path) -> std::io::Result<()> {
+        write_atomically(path, &serde_json::to_vec_pretty(self)?)
+    }
+}
+
+/// 記録とバックアップを置くフォルダ。実行ファイルと同じフォルダ。
+fn journal_directory() -> PathBuf {
+    process_path::get_executable_path()
+        .unwrap()
+        .parent()
+        .unwrap()
+        .to_path_buf()
+}
+
+/// ユーザーのフレーズ辞書`path`に書き込む。最初に書き込む前に元の辞書をバックアップする。
+pub fn write(path: &Path, contents: &[u8]) -> Result<()> {
+    write_in(&journal_directory(), path, contents)
+}
+
+/// 記録が残っていれば、書き換えたフレーズ辞書を元に戻す。戻した場合は`true`。
+pub fn restore() -> Result<bool> {
+    restore_in(&journal_directory())
+}
+
+/// パニックで終了する場合も、書き換えたフレーズ辞書を元に戻す。
+///
+/// ホストのスレッドやtokioのワーカーでのパニックはプロセスを終了させないので、メインスレッドの場合だけ戻す。
+pub fn install_panic_hook() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            default_hook(info);
+            if thread::current().name() != Some("main") {
+                return;
+            }
+            if let Err(e) = restore() {
+                error!("Failed to restore the phrase dictionary: {}", e);
+            }
+        }));
+    });
+}
+
+fn write_in(directory: &Path, path: &Path, contents: &[u8]) -> Result<()> {
+    let journal_path = directory.join(JOURNAL_FILE_NAME);
+    let mut journal = match PhraseDictionaryJournal::load(&journal_path)? {
+        Some(journal) if journal.path == path => journal,
+        Some(_) => {
+            // 別の辞書を書き換えた記録が残っていたら、先にそちらを戻す
+            restore_in(directory)?;
+            begin(directory, path)?
+        }
+        None => begin(directory, path)?,
+    };
+
+    // 記録を先に書いておけば、辞書を書き込む途中で終了しても元に戻せる
+    journal.written_checksum = checksum(contents);
+    journal
+        .save(&journal_path)
+        .map_err(Error::WriteDictionaryFailed)?;
+    info!("Writing phrase dictionary to {}", path.display());
+    write_atomically(path, contents).map_err(Error::WriteDictionaryFailed)
+}
+
+/// 元のフレーズ辞書をバックアップし、書き換える前の記録を作る。
+fn begin(directory: &Path, path: &Path) -> Result<PhraseDictionaryJournal> {
+    let original_checksum = match fs_err::read(path) {
+        Ok(original) => {
+            write_atomically(&directory.join(BACKUP_FILE_NAME), &original)
+                .map_err(Error::WriteDictionaryFailed)?;
+            Some(checksum(&original))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(Error::WriteDictionaryFailed(e)),
+    };
+    info!("Backed up phrase dictionary {}", path.display());
+    Ok(PhraseDictionaryJournal {
+        pid: std::process::id(),
+        modified_at: Local::now().to_rfc3339(),
+        path: path.to_path_buf(),
+        written_checksum: original_checksum.clone().unwrap_or_default(),
+        original_checksum,
+    })
+}
+
+fn restore_in(directory: &Path) -> Result<bool> {
+    let journal_path = directory.join(JOURNAL_FILE_NAME);
+    let backup_path = directory.join(BACKUP_FILE_NAME);
+    let Some(journal) = PhraseDictionaryJournal::load(&journal_path)? else {
+        return Ok(false);
+    };
+
+    match fs_err::read(&journal.path) {
+        Ok(current) if checksum(&current) != journal.written_checksum => {
+            // 書き換えた後にユーザーが編集した可能性があるので、消さずに残しておく
+            let mut modified_path = journal.path.as_os_str().to_owned();
+            modified_path.push(".modified");
+            warn!(
+                "Phrase dictionary {} was changed after it was written, keeping it as {}",
+                journal.path.display(),
+                Path::new(&modified_path).display()
+            );
+            write_atomically(Path::new(&modified_path), &current)
+                .map_err(|e| Error::RestoreDictionaryFailed(e.into()))?;
+        }
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(Error::RestoreDictionaryFailed(e.into())),
+    }
+
+    match &journal.original_checksum {
+        Some(original_checksum) => {
+            let original =
+                fs_err::read(&backup_path).map_err(|e| Error::RestoreDictionaryFailed(e.into()))?;
+            if checksum(&original) != *original_checksum {
+                return Err(Error::RestoreDictionaryFailed(anyhow!(
+                    "Backup of the phrase dictionary is corrupted: {}",
+                    backup_path.display()
+                )));
+            }
+            write_atomically(&journal.path, &original)
+                .map_err(|e| Error::RestoreDictionaryFailed(e.into()))?;
+        }
+        None => match fs_err::remove_file(&journal.path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(Error::RestoreDictionaryFailed(e.into())),
+        },
+    }
+
+    for path in [&backup_path, &journal_path] {
+        match fs_err::remove_file(path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(Error::RestoreDictionaryFailed(e.into())),
+        }
+    }
+    info!(
+        "Restored phrase dictionary {} written by process {} at {}",
+        journal.path.display(),
+        journal.pid,
+        journal.modified_at
+    );
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restores_original_dictionary() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("user.pdic");
+        fs_err::write(&path, b"original").unwrap();
+
+        write_in(directory.path(), &path, b"first").unwrap();
+        write_in(directory.path(), &path, b"second").unwrap();
+        assert_eq!(fs_err::read(&path).unwrap(), b"second");
+        assert_eq!(
+            fs_err::read(directory.path().join(BACKUP_FILE_NAME)).unwrap(),
+            b"original"
+        );
+
+        assert!(restore_in(directory.path()).unwrap());
+        assert_eq!(fs_err::read(&path).unwrap(), b"original");
+        assert!(!directory.path().join(BACKUP_FILE_NAME).exists());
+        assert!(!directory.path().join(JOURNAL_FILE_NAME).exists());
+        assert!(!restore_in(directory.path()).unwrap());
+    }
+
+    #[test]
+    fn removes_dictionary_that_did_not_exist() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("user.pdic");
+
+        write_in(directory.path(), &path, b"written").unwrap();
+        assert!(restore_in(directory.path()).unwrap());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn keeps_dictionary_changed_after_writing() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("user.pdic");
+        fs_err::write(&path, b"original").unwrap();
+
+        write_in(directory.path(), &path, b"written").unwrap();
+        fs_err::write(&path, b"edited").unwrap();
+        assert!(restore_in(directory.path()).unwrap());
+        assert_eq!(fs_err::read(&path).unwrap(), b"original");
+        assert_eq!(
+            fs_err::read(directory.path().join("user.pdic.modified")).unwrap(),
+            b"edited"
+        );
+    }
+
+    #[test]
+    fn restores_previous_dictionary_before_writing_another() {
+        let directory = tempfile::tempdir().unwrap();
+        let first = directory.path().join("first.pdic");
+        let second = directory.path().join("second.pdic");
+        fs_err::write(&first, b"first original").unwrap();
+        fs_err::write(&second, b"second original").unwrap();
+
+        write_in(directory.path(), &first, b"written").unwrap();
+        write_in(directory.path(), &second, b"written").unwrap();
+        assert_eq!(fs_err::read(&first).unwrap(), b"first original");
+
+        assert!(restore_in(directory.path()).unwrap());
+        assert_eq!(fs_err::read(&second).unwrap(), b"second original");
+    }
+
+    #[test]
+    fn rejects_corrupted_backup() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("user.pdic");
+        fs_err::write(&path, b"original").unwrap();
+
+        write_in(directory.path(), &path, b"written").unwrap();
+        fs_err::write(directory.path().join(BACKUP_FILE_NAME), b"broken").unwrap();
+        assert!(matches!(
+            restore_in(directory.path()),
+            Err(Error::RestoreDictionaryFailed(_))
+        ));
+        assert_eq!(fs_err::read(&path).unwrap(), b"written");
+    }
+}
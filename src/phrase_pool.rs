@@ -0,0 +1,120 @@
+//! 一時フレーズ辞書に入れておくフレーズ。
+//!
+//! 合成のたびに辞書を書き換えて読み込み直すと遅いので、最近使ったフレーズを辞書に残しておく。
+//! 辞書に無いフレーズを使うときだけ書き換え、いっぱいなら一番長く使われていないフレーズを消す。
+
+use crate::aivoice::Phrase;
+
+use indexmap::IndexMap;
+use tracing::debug;
+use uuid::Uuid;
+
+pub const DEFAULT_PHRASE_POOL_SIZE: usize = 64;
+
+#[derive(Debug)]
+pub struct PhrasePool {
+    capacity: usize,
+    /// 最後に使ったものが末尾。
+    phrases: IndexMap<Uuid, Phrase>,
+    /// A.I.Voiceが読み込んでいる辞書が`phrases`と同じかどうか。
+    synced: bool,
+}
+
+impl PhrasePool {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            phrases: IndexMap::new(),
+            synced: false,
+        }
+    }
+
+    /// `phrase`を使う。辞書を書き換える必要があれば`true`を返す。
+    pub fn acquire(&mut self, phrase: &Phrase) -> bool {
+        if let Some(existing) = self.phrases.shift_remove(phrase.uuid()) {
+            self.phrases.insert(*phrase.uuid(), existing);
+            return !self.synced;
+        }
+
+        while self.phrases.len() >= self.capacity {
+            if let Some((_, evicted)) = self.phrases.shift_remove_index(0) {
+                debug!("Evicting phrase {}", evicted.pronunciation());
+            }
+        }
+        self.phrases.insert(*phrase.uuid(), phrase.clone());
+        self.synced = false;
+        true
+    }
+
+    pub fn phrases(&self) -> impl Iterator<Item = &Phrase> {
+        self.phrases.values()
+    }
+
+    /// 辞書を書き込んで読み込ませたときに呼ぶ。
+    pub fn mark_synced(&mut self) {
+        self.synced = true;
+    }
+
+    /// 辞書を空にしたときに呼ぶ。
+    pub fn clear(&mut self) {
+        self.phrases.clear();
+        self.synced = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn phrase(pronunciation: &str) -> Phrase {
+        Phrase::new(pronunciation.to_string(), pronunciation.to_string())
+    }
+
+    fn pronunciations(pool: &PhrasePool) -> Vec<&str> {
+        pool.phrases().map(|x| x.pronunciation().as_str()).collect()
+    }
+
+    #[test]
+    fn keeps_recently_used_phrases_last() {
+        let mut pool = PhrasePool::new(3);
+        pool.acquire(&phrase("a"));
+        pool.acquire(&phrase("b"));
+        pool.acquire(&phrase("c"));
+        pool.acquire(&phrase("a"));
+        assert_eq!(pronunciations(&pool), ["b", "c", "a"]);
+    }
+
+    #[test]
+    fn evicts_least_recently_used_phrase() {
+        let mut pool = PhrasePool::new(2);
+        pool.acquire(&phrase("a"));
+        pool.acquire(&phrase("b"));
+        pool.acquire(&phrase("a"));
+        pool.mark_synced();
+
+        assert!(pool.acquire(&phrase("c")));
+        assert_eq!(pronunciations(&pool), ["a", "c"]);
+        pool.mark_synced();
+        assert!(pool.acquire(&phrase("b")));
+        assert_eq!(pronunciations(&pool), ["c", "b"]);
+    }
+
+    #[test]
+    fn rewrites_only_until_synced() {
+        let mut pool = PhrasePool::new(2);
+        assert!(pool.acquire(&phrase("a")));
+        // 書き込みに失敗して`mark_synced`されていなければ、同じフレーズでも書き込み直す
+        assert!(pool.acquire(&phrase("a")));
+        pool.mark_synced();
+        assert!(!pool.acquire(&phrase("a")));
+
+        assert!(pool.acquire(&phrase("b")));
+        assert!(pool.acquire(&phrase("a")));
+        pool.mark_synced();
+        assert!(!pool.acquire(&phrase("b")));
+
+        pool.clear();
+        assert!(pool.acquire(&phrase("a")));
+        assert_eq!(pronunciations(&pool), ["a"]);
+    }
+}
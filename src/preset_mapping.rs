@@ -0,0 +1,211 @@
+//! AudioQueryの値からA.I.Voiceのボイスプリセットの値への変換。
+//!
+//! 基準値（ユーザーのボイスプリセットの値、無ければA.I.Voiceの既定値）にAudioQueryの値を反映し、
+//! A.I.Voiceが受け付ける範囲に収める。モーラごとの音高と音素の長さは反映できないので、
+//! `SupportedFeatures`の`adjust_mora_pitch`と`adjust_phoneme_length`は`false`にしている。
+
+use crate::bridge::{MasterControl, VoicePreset};
+use crate::routes::audio_query::AudioQuery;
+
+use std::ops::RangeInclusive;
+
+/// A.I.Voiceのボイスプリセットで指定できる範囲。
+pub const VOLUME_RANGE: RangeInclusive<f64> = 0.0..=5.0;
+pub const SPEED_RANGE: RangeInclusive<f64> = 0.5..=4.0;
+pub const PITCH_RANGE: RangeInclusive<f64> = 0.5..=2.0;
+pub const PITCH_RANGE_RANGE: RangeInclusive<f64> = 0.0..=2.0;
+pub const MIDDLE_PAUSE_RANGE: RangeInclusive<i64> = 80..=500;
+pub const LONG_PAUSE_RANGE: RangeInclusive<i64> = 100..=2000;
+
+/// `pitch`で使う、`pitchScale`が0のときの対数音高の目安（約245Hz）。
+const REFERENCE_LOG_F0: f64 = 5.5;
+
+/// ボイスプリセットのうちAudioQueryで変える値。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PresetParameters {
+    pub volume: f64,
+    pub speed: f64,
+    pub pitch: f64,
+    pub pitch_range: f64,
+    /// 短ポーズ（ミリ秒）。
+    pub middle_pause: i64,
+    /// 長ポーズ（ミリ秒）。
+    pub long_pause: i64,
+}
+
+/// A.I.Voiceの既定値。A.I.Voice Editorで新しく作ったボイスプリセットの値で、マスターコントロールの既定値と同じ。
+impl Default for PresetParameters {
+    fn default() -> Self {
+        let master_control = MasterControl::default();
+        Self {
+            volume: master_control.volume,
+            speed: master_control.speed,
+            pitch: master_control.pitch,
+            pitch_range: master_control.pitch_range,
+            middle_pause: master_control.middle_pause,
+            long_pause: master_control.long_pause,
+        }
+    }
+}
+
+impl From<&VoicePreset> for PresetParameters {
+    fn from(preset: &VoicePreset) -> Self {
+        Self {
+            volume: preset.volume,
+            speed: preset.speed,
+            pitch: preset.pitch,
+            pitch_range: preset.pitch_range,
+            middle_pause: preset.middle_pause,
+            long_pause: preset.long_pause,
+        }
+    }
+}
+
+impl PresetParameters {
+    /// `base`に`audio_query`の値を反映する。
+    pub fn apply(base: &Self, audio_query: &AudioQuery) -> Self {
+        let speed_scale = audio_query.speed_scale as f64;
+        Self {
+            volume: volume(base.volume, audio_query.volume_scale as f64),
+            speed: speed(base.speed, speed_scale),
+            pitch: pitch(base.pitch, audio_query.pitch_scale as f64),
+            pitch_range: pitch_range(base.pitch_range, audio_query.intonation_scale as f64),
+            middle_pause: pause(base.middle_pause, speed_scale, MIDDLE_PAUSE_RANGE),
+            long_pause: pause(base.long_pause, speed_scale, LONG_PAUSE_RANGE),
+        }
+    }
+}
+
+/// `volumeScale`（既定1）。どちらも振幅の倍率なので、そのまま掛ける。
+pub fn volume(base: f64, volume_scale: f64) -> f64 {
+    clamp(base * volume_scale, VOLUME_RANGE)
+}
+
+/// `speedScale`（既定1）。どちらも話速の倍率なので、そのまま掛ける。
+pub fn speed(base: f64, speed_scale: f64) -> f64 {
+    clamp(base * speed_scale, SPEED_RANGE)
+}
+
+/// `pitchScale`（既定0）。
+///
+/// VOICEVOXは対数音高に`2^pitchScale`を掛けるので、基準の音高での周波数の比`exp(log_f0 * (2^pitchScale - 1))`を掛ける。
+/// VOICEVOXで指定できる±0.15は、およそ0.58倍から1.83倍になる。
+pub fn pitch(base: f64, pitch_scale: f64) -> f64 {
+    let ratio = (REFERENCE_LOG_F0 * (2f64.powf(pitch_scale) - 1.0)).exp();
+    clamp(base * ratio, PITCH_RANGE)
+}
+
+/// `intonationScale`（既定1）。どちらも平均からの音高の幅の倍率なので、そのまま掛ける。
+pub fn pitch_range(base: f64, intonation_scale: f64) -> f64 {
+    clamp(base * intonation_scale, PITCH_RANGE_RANGE)
+}
+
+/// 短ポーズと長ポーズ（ミリ秒）。VOICEVOXは話速に合わせて無音も縮めるので、`speedScale`で割る。
+pub fn pause(base: i64, speed_scale: f64, range: RangeInclusive<i64>) -> i64 {
+    let pause = (base as f64 / speed_scale.max(f64::EPSILON)).round();
+    (pause.min(i64::MAX as f64) as i64).clamp(*range.start(), *range.end())
+}
+
+/// 範囲外の値とNaNを範囲に収める。NaNは下限にする。
+fn clamp(value: f64, range: RangeInclusive<f64>) -> f64 {
+    if value.is_nan() {
+        return *range.start();
+    }
+    value.clamp(*range.start(), *range.end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Number;
+
+    fn audio_query(speed_scale: f32, pitch_scale: f32) -> AudioQuery {
+        AudioQuery {
+            accent_phrases: vec![],
+            speed_scale,
+            pitch_scale,
+            intonation_scale: 1.0,
+            volume_scale: 1.0,
+            pre_phoneme_length: 0.1,
+            post_phoneme_length: 0.1,
+            output_sampling_rate: Number::from(24000),
+            output_stereo: false,
+            kana: String::new(),
+            master_control: None,
+        }
+    }
+
+    #[test]
+    fn defaults_to_editor_preset() {
+        let defaults = PresetParameters::default();
+        assert_eq!(defaults.middle_pause, 150);
+        assert_eq!(defaults.long_pause, 370);
+        assert_eq!(defaults.middle_pause, MasterControl::default().middle_pause);
+        assert_eq!(defaults.long_pause, MasterControl::default().long_pause);
+    }
+
+    #[test]
+    fn keeps_preset_at_default_scales() {
+        assert_eq!(pitch(1.0, 0.0), 1.0);
+        let base = PresetParameters {
+            volume: 1.5,
+            speed: 1.2,
+            pitch: 0.9,
+            pitch_range: 1.3,
+            middle_pause: 200,
+            long_pause: 500,
+        };
+        let applied = PresetParameters::apply(&base, &audio_query(1.0, 0.0));
+        assert_eq!(applied, base);
+        assert_eq!(
+            PresetParameters::apply(&PresetParameters::default(), &audio_query(1.0, 0.0)),
+            PresetParameters::default()
+        );
+    }
+
+    #[test]
+    fn maps_pitch_scale_range() {
+        assert!((pitch(1.0, -0.15) - 0.58).abs() < 0.01);
+        assert!((pitch(1.0, 0.15) - 1.83).abs() < 0.01);
+    }
+
+    #[test]
+    fn clamps_to_preset_range() {
+        assert_eq!(volume(1.0, 10.0), *VOLUME_RANGE.end());
+        assert_eq!(volume(1.0, -1.0), *VOLUME_RANGE.start());
+        assert_eq!(speed(1.0, 10.0), *SPEED_RANGE.end());
+        assert_eq!(speed(1.0, 0.1), *SPEED_RANGE.start());
+        assert_eq!(pitch(1.0, 1.0), *PITCH_RANGE.end());
+        assert_eq!(pitch(1.0, -1.0), *PITCH_RANGE.start());
+        assert_eq!(pitch_range(1.0, 10.0), *PITCH_RANGE_RANGE.end());
+        assert_eq!(pitch_range(1.0, -1.0), *PITCH_RANGE_RANGE.start());
+        assert_eq!(
+            pause(150, 0.01, MIDDLE_PAUSE_RANGE),
+            *MIDDLE_PAUSE_RANGE.end()
+        );
+        assert_eq!(
+            pause(150, 10.0, MIDDLE_PAUSE_RANGE),
+            *MIDDLE_PAUSE_RANGE.start()
+        );
+    }
+
+    #[test]
+    fn clamps_nan_to_lower_bound() {
+        assert_eq!(volume(1.0, f64::NAN), *VOLUME_RANGE.start());
+        assert_eq!(speed(1.0, f64::NAN), *SPEED_RANGE.start());
+        assert_eq!(pitch(1.0, f64::NAN), *PITCH_RANGE.start());
+        assert_eq!(pitch_range(1.0, f64::NAN), *PITCH_RANGE_RANGE.start());
+    }
+
+    #[test]
+    fn divides_pause_by_zero_speed() {
+        assert_eq!(
+            pause(150, 0.0, MIDDLE_PAUSE_RANGE),
+            *MIDDLE_PAUSE_RANGE.end()
+        );
+        assert_eq!(pause(370, 0.0, LONG_PAUSE_RANGE), *LONG_PAUSE_RANGE.end());
+        let applied = PresetParameters::apply(&PresetParameters::default(), &audio_query(0.0, 0.0));
+        assert_eq!(applied.speed, *SPEED_RANGE.start());
+        assert_eq!(applied.long_pause, *LONG_PAUSE_RANGE.end());
+    }
+}
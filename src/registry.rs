@@ -0,0 +1,351 @@
+//! 話者とスタイルのIDを保存するファイル。
+//!
+//! 一度割り当てたスタイルIDは変えない。ファイルを直接編集すると、IDの変更や
+//! `/speakers`での話者の非表示、名前の変更、並べ替えができる。
+
+use crate::aivoice::NORMAL_STYLE;
+use crate::error::{Error, Result};
+
+use fxhash::FxHasher;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    hash::{Hash as _, Hasher as _},
+    path::{Path, PathBuf},
+};
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RegistryFile {
+    /// ボイス名ごとの設定。
+    speakers: IndexMap<String, SpeakerEntry>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpeakerEntry {
+    /// `/speakers`に表示する名前。省略するとボイスプリセットの名前を使う。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// `/speakers`に表示しない。合成はできる。
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub hidden: bool,
+    /// `/speakers`での並び順。小さいほど先に表示する。省略するとA.I.Voiceの順で最後に表示する。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<i32>,
+    /// スタイル名（`N`、`J`など）とスタイルID。
+    pub styles: IndexMap<String, u32>,
+    /// スタイル名と`/speakers`に表示する名前。省略したスタイルは既定の名前を使う。
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    pub style_names: IndexMap<String, String>,
+}
+
+#[derive(Debug)]
+pub struct Registry {
+    path: PathBuf,
+    file: RegistryFile,
+}
+
+impl Registry {
+    /// `path`から読み込む。無ければ空の状態から始める。
+    ///
+    /// 同じスタイルIDが複数回使われている場合は、後に書かれた方に新しいIDを割り当てて保存し直す。
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = match fs_err::read_to_string(path) {
+            Ok(contents) => {
+                serde_json::from_str(&contents).map_err(|e| Error::RegistryFailed(e.into()))?
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => RegistryFile::default(),
+            Err(e) => return Err(Error::RegistryFailed(e.into())),
+        };
+        let mut registry = Self {
+            path: path.to_path_buf(),
+            file,
+        };
+
+        let mut used = HashSet::new();
+        let mut duplicates = Vec::new();
+        for (voice_name, entry) in &registry.file.speakers {
+            for (style, id) in &entry.styles {
+                if !used.insert(*id) {
+                    duplicates.push((voice_name.clone(), style.clone()));
+                }
+            }
+        }
+        if !duplicates.is_empty() {
+            for (voice_name, style) in duplicates {
+                let id = registry.next_free_id();
+                let entry = registry.file.speakers.get_mut(&voice_name).unwrap();
+                warn!(
+                    "Style ID {} of {}/{} is already used, reassigning it to {}",
+                    entry.styles[&style], voice_name, style, id
+                );
+                entry.styles.insert(style, id);
+            }
+            registry.save()?;
+        }
+
+        Ok(registry)
+    }
+
+    pub fn default_path() -> PathBuf {
+        process_path::get_executable_path()
+            .unwrap()
+            .parent()
+            .unwrap()
+            .join("speaker_registry.json")
+    }
+
+    /// `voice_name`の`styles`にIDを割り当て、その話者の設定を返す。
+    ///
+    /// 新しいIDは、以前のバージョンと同じくボイス名のハッシュから作る。既に使われている場合は空いているIDを使う。
+    pub fn assign(&mut self, voice_name: &str, styles: &[String]) -> Result<SpeakerEntry> {
+        let used = self.used_ids();
+        let mut assigned = Vec::new();
+        {
+            let entry = self
+                .file
+                .speakers
+                .entry(voice_name.to_string())
+                .or_default();
+            for (index, style) in styles.iter().enumerate() {
+                if entry.styles.contains_key(style) {
+                    continue;
+                }
+                let id = match legacy_style_id(voice_name, style, index) {
+                    Some(legacy_id)
+                        if !used.contains(&legacy_id) && !assigned.contains(&legacy_id) =>
+                    {
+                        legacy_id
+                    }
+                    Some(legacy_id) => {
+                        let id = free_id(used.iter().chain(assigned.iter()));
+                        warn!(
+                            "Style ID {} for {}/{} collides with another style, using {}",
+                            legacy_id, voice_name, style, id
+                        );
+                        id
+                    }
+                    None => free_id(used.iter().chain(assigned.iter())),
+                };
+                entry.styles.insert(style.clone(), id);
+                assigned.push(id);
+            }
+        }
+
+        if !assigned.is_empty() {
+            info!("Assigned style IDs {:?} to {}", assigned, voice_name);
+            self.save()?;
+        }
+        Ok(self.file.speakers[voice_name].clone())
+    }
+
+    fn used_ids(&self) -> HashSet<u32> {
+        self.file
+            .speakers
+            .values()
+            .flat_map(|entry| entry.styles.values().copied())
+            .collect()
+    }
+
+    fn next_free_id(&self) -> u32 {
+        free_id(self.used_ids().iter())
+    }
+
+    fn save(&self) -> Result<()> {
+        let contents = serde_json::to_string_pretty(&self.file)
+            .map_err(|e| Error::RegistryFailed(e.into()))?;
+        // 書き込み中に落ちても壊れないように、別のファイルに書いてから置き換える
+        let temporary_path = self.path.with_extension("json.tmp");
+        fs_err::write(&temporary_path, contents).map_err(|e| Error::RegistryFailed(e.into()))?;
+        fs_err::rename(&temporary_path, &self.path).map_err(|e| Error::RegistryFailed(e.into()))
+    }
+}
+
+/// 以前のバージョンのIDと重ならないように、新しく空いているIDを割り当てるときはここから使う。
+const FREE_IDS_START: u32 = u16::MAX as u32 * 10;
+
+/// `used`と重ならない、`FREE_IDS_START`以降のID。
+fn free_id<'a>(used: impl Iterator<Item = &'a u32>) -> u32 {
+    used.filter(|id| **id >= FREE_IDS_START)
+        .max()
+        .map_or(FREE_IDS_START, |max| max + 1)
+}
+
+/// 以前のバージョンでのスタイルID。既存のVOICEVOXのプロジェクトが使えるように、初めて割り当てるときに使う。
+///
+/// 以前のバージョンが知らなかったスタイルは、話者の中での順番を使う。
+/// 話者ごとのIDは10個ずつなので、10番目以降のスタイルは次の話者と重ならないように`None`を返す。
+fn legacy_style_id(voice_name: &str, style: &str, index: usize) -> Option<u32> {
+    let mut hasher = FxHasher::default();
+    voice_name.hash(&mut hasher);
+    let speaker_id = (hasher.finish() % u16::MAX as u64) as u32;
+    let offset = match style {
+        NORMAL_STYLE => 0,
+        "J" => 1,
+        "A" => 2,
+        "S" => 3,
+        _ if index < 10 => index as u32,
+        _ => return None,
+    };
+    Some(speaker_id * 10 + offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn styles(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn keeps_ids_across_reloads() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("speakers.json");
+        let mut registry = Registry::load(&path).unwrap();
+        let akane = registry
+            .assign("kotonoha_akane", &styles(&["N", "J", "A", "S"]))
+            .unwrap();
+        let aoi = registry
+            .assign("kotonoha_aoi", &styles(&["N", "J"]))
+            .unwrap();
+
+        let mut registry = Registry::load(&path).unwrap();
+        // 順番やスタイルが変わっても、割り当て済みのIDは変えない
+        let aoi_reloaded = registry
+            .assign("kotonoha_aoi", &styles(&["J", "N", "A"]))
+            .unwrap();
+        let akane_reloaded = registry
+            .assign("kotonoha_akane", &styles(&["N", "J", "A", "S"]))
+            .unwrap();
+        assert_eq!(akane_reloaded.styles, akane.styles);
+        assert_eq!(aoi_reloaded.styles["N"], aoi.styles["N"]);
+        assert_eq!(aoi_reloaded.styles["J"], aoi.styles["J"]);
+        assert_eq!(aoi_reloaded.styles["A"], aoi.styles["N"] + 2);
+    }
+
+    #[test]
+    fn assigns_legacy_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut registry = Registry::load(&dir.path().join("speakers.json")).unwrap();
+        let entry = registry
+            .assign("kotonoha_akane", &styles(&["N", "S", "J", "A", "Whisper"]))
+            .unwrap();
+        let normal = entry.styles["N"];
+        assert_eq!(Some(normal), legacy_style_id("kotonoha_akane", "N", 0));
+        assert_eq!(normal % 10, 0);
+        assert_eq!(entry.styles["J"], normal + 1);
+        assert_eq!(entry.styles["A"], normal + 2);
+        assert_eq!(entry.styles["S"], normal + 3);
+        assert_eq!(entry.styles["Whisper"], normal + 4);
+    }
+
+    #[test]
+    fn keeps_many_styles_in_free_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut registry = Registry::load(&dir.path().join("speakers.json")).unwrap();
+        let names: Vec<String> = std::iter::once("N".to_string())
+            .chain((1..12).map(|i| format!("style{}", i)))
+            .collect();
+        let entry = registry.assign("kotonoha_akane", &names).unwrap();
+        let normal = entry.styles["N"];
+        for (index, name) in names.iter().enumerate() {
+            let id = entry.styles[name];
+            if index < 10 {
+                assert_eq!(id, normal + index as u32);
+            } else {
+                assert!(id >= FREE_IDS_START, "{} overflowed to {}", name, id);
+            }
+        }
+        assert_eq!(entry.styles["style10"] + 1, entry.styles["style11"]);
+    }
+
+    #[test]
+    fn reassigns_colliding_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("speakers.json");
+        let normal = legacy_style_id("kotonoha_akane", NORMAL_STYLE, 0).unwrap();
+        fs_err::write(
+            &path,
+            format!(
+                r#"{{
+                    "speakers": {{
+                        "kotonoha_aoi": {{ "styles": {{ "N": {}, "J": 1 }} }},
+                        "kizuna_akari": {{ "styles": {{ "N": 1 }} }}
+                    }}
+                }}"#,
+                normal
+            ),
+        )
+        .unwrap();
+
+        // 重複していたIDは後に書かれた方を振り直して保存する
+        let mut registry = Registry::load(&path).unwrap();
+        let akari = registry.file.speakers["kizuna_akari"].styles["N"];
+        assert_eq!(akari, FREE_IDS_START);
+        let saved: RegistryFile =
+            serde_json::from_str(&fs_err::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved.speakers["kizuna_akari"].styles["N"], akari);
+
+        // 以前のバージョンのIDが使われていれば空いているIDを使う
+        let akane = registry
+            .assign("kotonoha_akane", &styles(&["N", "J"]))
+            .unwrap();
+        assert_eq!(akane.styles["N"], FREE_IDS_START + 1);
+        assert_eq!(akane.styles["J"], normal + 1);
+    }
+
+    #[test]
+    fn keeps_overrides() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("speakers.json");
+        fs_err::write(
+            &path,
+            serde_json::json!({
+                "speakers": {
+                    "kotonoha_akane": {
+                        "name": "琴葉 茜",
+                        "hidden": true,
+                        "order": -1,
+                        "styles": { "N": 10, "J": 11 },
+                        "style_names": { "J": "嬉しい" },
+                    }
+                }
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let mut registry = Registry::load(&path).unwrap();
+        let entry = registry
+            .assign("kotonoha_akane", &styles(&["N", "J", "A"]))
+            .unwrap();
+        assert_eq!(entry.name.as_deref(), Some("琴葉 茜"));
+        assert!(entry.hidden);
+        assert_eq!(entry.order, Some(-1));
+        assert_eq!(entry.styles["N"], 10);
+        assert_eq!(entry.styles["J"], 11);
+        assert_eq!(entry.style_names["J"], "嬉しい");
+
+        // 新しいスタイルを割り当てて保存しても、手で書いた設定は残る
+        let registry = Registry::load(&path).unwrap();
+        let entry = &registry.file.speakers["kotonoha_akane"];
+        assert_eq!(entry.name.as_deref(), Some("琴葉 茜"));
+        assert!(entry.hidden);
+        assert_eq!(entry.styles.len(), 3);
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("speakers.json");
+        fs_err::write(&path, r#"{ "speakers": {}, "version": 2 }"#).unwrap();
+        assert!(matches!(
+            Registry::load(&path),
+            Err(Error::RegistryFailed(_))
+        ));
+    }
+}
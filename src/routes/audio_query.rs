@@ -1,3 +1,4 @@
+use super::master_control::MasterControlOverride;
 use crate::error::{Error, Result};
 use crate::voicevox::model::AccentPhraseModel;
 use crate::voicevox::open_jtalk::OpenJtalk;
@@ -30,6 +31,9 @@ pub struct AudioQuery {
     pub output_sampling_rate: Number,
     pub output_stereo: bool,
     pub kana: String,
+    /// この合成の間だけ使うマスターコントロール。AIVoiceVox独自の拡張。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub master_control: Option<MasterControlOverride>,
 }
 
 pub static OPEN_JTALK: Lazy<Arc<Mutex<OpenJtalk>>> = Lazy::new(|| {
@@ -58,7 +62,7 @@ pub async fn post_audio_query(Query(query): Query<AudioQueryParams>) -> Result<J
     Ok(Json(AudioQuery {
         accent_phrases,
         speed_scale: 1.0,
-        pitch_scale: 1.0,
+        pitch_scale: 0.0,
         intonation_scale: 1.0,
         volume_scale: 1.0,
         pre_phoneme_length: 0.0,
@@ -66,6 +70,7 @@ pub async fn post_audio_query(Query(query): Query<AudioQueryParams>) -> Result<J
         output_sampling_rate: 24000.into(),
         output_stereo: true,
         kana: query.text.clone(),
+        master_control: None,
     }))
 }
 
@@ -0,0 +1,33 @@
+use crate::actor::{QueueStatus, ACTOR};
+use crate::error::Result;
+use crate::supervisor::{SupervisorStatus, SUPERVISOR};
+
+use axum::Json;
+use serde::Serialize;
+
+pub async fn get_host_state() -> Json<SupervisorStatus> {
+    Json(SUPERVISOR.status())
+}
+
+#[derive(Debug, Serialize)]
+pub struct Hosts {
+    /// 使用中のホスト名。
+    pub current: String,
+    /// このマシンに登録されているホスト名。
+    pub available: Vec<String>,
+}
+
+pub async fn get_hosts() -> Result<Json<Hosts>> {
+    let available = ACTOR
+        .run(|aivoice, _| Box::pin(aivoice.available_host_names()))
+        .await?;
+
+    Ok(Json(Hosts {
+        current: ACTOR.info().host_name,
+        available,
+    }))
+}
+
+pub async fn get_queue() -> Json<QueueStatus> {
+    Json(ACTOR.status())
+}
@@ -1,6 +1,6 @@
-use crate::aivoice::AIVOICE;
+use crate::actor::ACTOR;
 
-use axum::{http::StatusCode, response::IntoResponse, Json};
+use axum::{response::IntoResponse, Json};
 use base64::Engine as _;
 use serde::{Deserialize, Serialize};
 
@@ -65,11 +65,11 @@ pub struct SupportedDeveices {
     pub dml: bool,
 }
 
+/// A.I.Voiceのバージョンは起動済みの場合だけ付ける。バージョンを返すためだけには起動しない。
 pub async fn get_version() -> impl IntoResponse {
-    if let Ok(version) = AIVOICE.lock().await.version().await {
-        Json(format!("{} ({})", env!("CARGO_PKG_VERSION"), version)).into_response()
-    } else {
-        (StatusCode::INTERNAL_SERVER_ERROR, "Failed to get version").into_response()
+    match ACTOR.info().version {
+        Some(version) => Json(format!("{} ({})", env!("CARGO_PKG_VERSION"), version)),
+        None => Json(env!("CARGO_PKG_VERSION").to_string()),
     }
 }
 
@@ -133,7 +133,7 @@ pub async fn get_engine_manifest() -> Json<EngineManifest> {
             adjust_intonation_scale: true,
             adjust_volume_scale: true,
             interrogative_upspeak: false,
-            synthesis_morphing: false,
+            synthesis_morphing: true,
             manage_library: false,
         },
     })
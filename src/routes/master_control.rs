@@ -0,0 +1,64 @@
+use crate::actor::ACTOR;
+use crate::aivoice::MasterControl;
+use crate::error::Result;
+
+use axum::Json;
+use serde::{Deserialize, Serialize};
+
+/// マスターコントロールのうち、指定された項目だけを上書きする。
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct MasterControlOverride {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volume: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pitch: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pitch_range: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub middle_pause: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub long_pause: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sentence_pause: Option<i64>,
+}
+
+impl MasterControlOverride {
+    pub fn apply(&self, base: &MasterControl) -> MasterControl {
+        MasterControl {
+            volume: self.volume.unwrap_or(base.volume),
+            speed: self.speed.unwrap_or(base.speed),
+            pitch: self.pitch.unwrap_or(base.pitch),
+            pitch_range: self.pitch_range.unwrap_or(base.pitch_range),
+            middle_pause: self.middle_pause.unwrap_or(base.middle_pause),
+            long_pause: self.long_pause.unwrap_or(base.long_pause),
+            sentence_pause: self.sentence_pause.unwrap_or(base.sentence_pause),
+        }
+    }
+}
+
+pub async fn get_master_control() -> Result<Json<MasterControl>> {
+    let master_control = ACTOR
+        .run(|aivoice, _| Box::pin(aivoice.master_control()))
+        .await?;
+
+    Ok(Json(master_control))
+}
+
+pub async fn put_master_control(
+    Json(master_control): Json<MasterControlOverride>,
+) -> Result<Json<MasterControl>> {
+    let new_master_control = ACTOR
+        .run(move |aivoice, _| {
+            Box::pin(async move {
+                let new_master_control = master_control.apply(&aivoice.master_control().await?);
+                aivoice.set_master_control(&new_master_control).await?;
+                Ok(new_master_control)
+            })
+        })
+        .await?;
+
+    Ok(Json(new_master_control))
+}
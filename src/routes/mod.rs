@@ -1,5 +1,7 @@
 pub mod audio_query;
+pub mod host;
 pub mod info;
+pub mod master_control;
 pub mod speakers;
 pub mod synthesis;
 pub mod user_dict;
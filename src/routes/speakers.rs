@@ -1,13 +1,15 @@
-use crate::aivoice::Style;
 use crate::{
-    aivoice::{Speaker, AIVOICE},
-    error::{ErrorResponse, Result},
-    icon_manager::ICON_MANAGER,
+    actor::{self, ACTOR},
+    aivoice::{Speaker, SpeakerChanges, NORMAL_STYLE},
+    error::{Error, ErrorResponse, Result},
+    icon_manager::{StyleImages, ICON_MANAGER},
 };
 
 use axum::{http::StatusCode, response::IntoResponse, Json};
 use base64::Engine as _;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::warn;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct VvSpeaker {
@@ -15,6 +17,7 @@ pub struct VvSpeaker {
     pub speaker_uuid: String,
     pub styles: Vec<VvStyle>,
     pub version: String,
+    pub supported_features: SupportedFeatures,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -37,6 +40,11 @@ pub struct SupportedFeatures {
     pub permitted_synthesis_morphing: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MorphableTargetInfo {
+    pub is_morphable: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct VvStyle {
     pub name: String,
@@ -44,28 +52,40 @@ pub struct VvStyle {
 }
 
 pub async fn get_speakers() -> Result<Json<Vec<VvSpeaker>>> {
-    let aivoice = AIVOICE.lock().await;
-    let version = aivoice.version().await?;
+    let info = ACTOR.started_info().await?;
+    let version = info.version.clone().unwrap_or_default();
+    let mut speakers: Vec<&Speaker> = info
+        .speakers
+        .values()
+        .filter(|speaker| !speaker.hidden())
+        .collect();
+    speakers.sort_by_key(|speaker| speaker.order().unwrap_or(i32::MAX));
     Ok(Json(
-        aivoice
-            .speakers()
-            .values()
-            .map(|speaker| {
-                Ok(VvSpeaker {
-                    name: speaker.display_name().to_string(),
-                    speaker_uuid: speaker.uuid().hyphenated().to_string(),
-                    version: version.clone(),
-                    styles: speaker
-                        .styles()
-                        .iter()
-                        .map(|style| VvStyle {
-                            name: style.to_japanese().to_string(),
-                            id: speaker.id() * 10 + *style as u32,
-                        })
-                        .collect(),
-                })
+        speakers
+            .into_iter()
+            .map(|speaker| VvSpeaker {
+                name: speaker.display_name().to_string(),
+                speaker_uuid: speaker.uuid().hyphenated().to_string(),
+                version: version.clone(),
+                // 同じ話者のスタイル同士は感情パラメータを混ぜ、別の話者とはボイスフュージョンを使う
+                supported_features: SupportedFeatures {
+                    permitted_synthesis_morphing: if info.voice_fusion {
+                        "ALL"
+                    } else {
+                        "SELF_ONLY"
+                    }
+                    .to_string(),
+                },
+                styles: speaker
+                    .styles()
+                    .iter()
+                    .map(|style| VvStyle {
+                        name: style.display_name().clone(),
+                        id: *style.id(),
+                    })
+                    .collect(),
             })
-            .collect::<Result<Vec<_>>>()?,
+            .collect(),
     ))
 }
 
@@ -75,10 +95,12 @@ pub struct SpeakerInfoQuery {
 }
 
 pub async fn get_speaker_info(query: axum::extract::Query<SpeakerInfoQuery>) -> impl IntoResponse {
-    let aivoice = AIVOICE.lock().await;
-    let icon = ICON_MANAGER.lock().await;
+    let info = match ACTOR.started_info().await {
+        Ok(info) => info,
+        Err(e) => return e.into_response(),
+    };
 
-    let speaker: &Speaker = match aivoice.speakers().values().find(|speaker| {
+    let speaker: &Speaker = match info.speakers.values().find(|speaker| {
         speaker
             .uuid()
             .hyphenated()
@@ -91,6 +113,7 @@ pub async fn get_speaker_info(query: axum::extract::Query<SpeakerInfoQuery>) ->
                 StatusCode::NOT_FOUND,
                 Json(ErrorResponse {
                     error: "Speaker not found".into(),
+                    detail: None,
                 })
                 .into_response(),
             )
@@ -98,30 +121,54 @@ pub async fn get_speaker_info(query: axum::extract::Query<SpeakerInfoQuery>) ->
         }
     };
 
-    let portraits = icon.portraits().get(speaker.internal_name()).unwrap();
-    let icons = icon.icons().get(speaker.internal_name()).unwrap();
+    // アイコンはホストを起動してから読み込む
+    if !ICON_MANAGER
+        .lock()
+        .await
+        .icons()
+        .contains_key(speaker.internal_name())
+    {
+        let loaded = ACTOR
+            .run(|aivoice, _| {
+                Box::pin(async move { ICON_MANAGER.lock().await.setup(aivoice).await })
+            })
+            .await;
+        if let Err(e) = loaded {
+            return e.into_response();
+        }
+    }
+    let icon = ICON_MANAGER.lock().await;
+
+    // 読み込んだ後に追加された話者などで画像が無い場合は、ボイスのフォルダが無いときと同じく空の画像を返す
+    let blank = StyleImages::default();
+    let (portraits, icons) = match (
+        icon.portraits().get(speaker.internal_name()),
+        icon.icons().get(speaker.internal_name()),
+    ) {
+        (Some(portraits), Some(icons)) => (portraits, icons),
+        _ => {
+            warn!(
+                "No images for {}, using blank images",
+                speaker.internal_name()
+            );
+            (&blank, &blank)
+        }
+    };
 
     Json(VvSpeakerInfo {
         policy: "[A.I.Voiceのキャラクター規約](https://aivoice.jp/character/)を参照してください。"
             .into(),
-        portrait: base64::engine::general_purpose::STANDARD_NO_PAD.encode(portraits.normal()),
+        portrait: base64::engine::general_purpose::STANDARD_NO_PAD
+            .encode(portraits.get(NORMAL_STYLE)),
         style_infos: speaker
             .styles()
             .iter()
             .map(|style| VvStyleInfo {
-                id: speaker.id() * 10 + *style as u32,
-                icon: base64::engine::general_purpose::STANDARD_NO_PAD.encode(match style {
-                    Style::Normal => icons.normal(),
-                    Style::Joy => icons.joy(),
-                    Style::Anger => icons.anger(),
-                    Style::Sorrow => icons.sorrow(),
-                }),
-                portrait: base64::engine::general_purpose::STANDARD_NO_PAD.encode(match style {
-                    Style::Normal => portraits.normal(),
-                    Style::Joy => portraits.joy(),
-                    Style::Anger => portraits.anger(),
-                    Style::Sorrow => portraits.sorrow(),
-                }),
+                id: *style.id(),
+                icon: base64::engine::general_purpose::STANDARD_NO_PAD
+                    .encode(icons.get(style.name())),
+                portrait: base64::engine::general_purpose::STANDARD_NO_PAD
+                    .encode(portraits.get(style.name())),
                 voice_samples: vec![],
             })
             .collect(),
@@ -129,6 +176,42 @@ pub async fn get_speaker_info(query: axum::extract::Query<SpeakerInfoQuery>) ->
     .into_response()
 }
 
+/// `base_speakers`のそれぞれについて、モーフィングできるスタイルを返す。
+pub async fn post_morphable_targets(
+    Json(base_speakers): Json<Vec<u32>>,
+) -> Result<Json<Vec<HashMap<String, MorphableTargetInfo>>>> {
+    let info = ACTOR.started_info().await?;
+    base_speakers
+        .into_iter()
+        .map(|base| {
+            let base_speaker = info
+                .speakers
+                .values()
+                .find(|speaker| speaker.styles().iter().any(|style| *style.id() == base))
+                .ok_or(Error::SpeakerNotFound)?;
+            Ok(info
+                .speakers
+                .values()
+                .flat_map(|speaker| {
+                    let is_morphable = info.is_morphable(base_speaker, speaker);
+                    speaker.styles().iter().map(move |style| {
+                        (style.id().to_string(), MorphableTargetInfo { is_morphable })
+                    })
+                })
+                .collect())
+        })
+        .collect::<Result<Vec<_>>>()
+        .map(Json)
+}
+
+/// 話者一覧と画像を読み直す。読み直している間のリクエストは、読み直す前の一覧で答える。
+pub async fn post_rescan_speakers() -> Result<Json<SpeakerChanges>> {
+    ACTOR
+        .run(|aivoice, _| Box::pin(actor::rescan_speakers(aivoice, true)))
+        .await
+        .map(Json)
+}
+
 pub async fn get_is_initialized_speaker() -> &'static str {
     "true"
 }
@@ -1,24 +1,82 @@
 use super::audio_query::AudioQuery;
 use crate::{
-    aivoice::{Phrase, Style, AIVOICE},
-    bridge::{MergedVoiceContainer, VoicePreset, VoicePresetStyle},
+    actor::{self, Cancellation, HostInfo, ACTOR},
+    aivoice::{find_style, is_morphable, AiVoice, MasterControl, Phrase, Speaker},
+    bridge::{MergedVoice, MergedVoiceContainer, VoicePreset, VoicePresetStyle},
     error::{Error, Result},
+    preset_mapping::PresetParameters,
+    settings_modifier::ExportFormat,
+    synthesis_cache::{CacheStatus, SynthesisCache, SYNTHESIS_CACHE},
+    wav::Wav,
 };
 
 use anyhow::anyhow;
-use axum::{extract::Query, Json};
-use serde::Deserialize;
-use tracing::info;
+use axum::{extract::Query, http::StatusCode, Json};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{info, warn};
 
 #[derive(Debug, Deserialize)]
 pub struct AudioQueryQuery {
     pub speaker: u32,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct SynthesisMorphingQuery {
+    pub base_speaker: u32,
+    pub target_speaker: u32,
+    pub morph_rate: f64,
+}
+
+/// A.I.Voiceが書き出した音声と、書き出したときのマスターコントロールと形式。
+struct SavedAudio {
+    wav: Wav,
+    master_control: MasterControl,
+    export_format: ExportFormat,
+}
+
+/// 合成に使うスタイル。`target`がある場合は、同じ話者のスタイルと`morph_rate`の割合で混ぜる。
+#[derive(Debug, Clone, Copy)]
+struct StyleBlend {
+    base: u32,
+    target: Option<(u32, f64)>,
+}
+
 pub async fn post_synthesis(
     Query(query): Query<AudioQueryQuery>,
     Json(audio_query): Json<AudioQuery>,
 ) -> Result<Vec<u8>> {
+    synthesize(
+        StyleBlend {
+            base: query.speaker,
+            target: None,
+        },
+        audio_query,
+    )
+    .await
+}
+
+/// 同じ話者のスタイル同士は感情パラメータを`morph_rate`で混ぜる。
+/// `voice_fusion`が有効なら、別の話者とはボイスフュージョンで混ぜる。
+pub async fn post_synthesis_morphing(
+    Query(query): Query<SynthesisMorphingQuery>,
+    Json(audio_query): Json<AudioQuery>,
+) -> Result<Vec<u8>> {
+    if !(0.0..=1.0).contains(&query.morph_rate) {
+        return Err(Error::InvalidMorphRate(query.morph_rate));
+    }
+    synthesize(
+        StyleBlend {
+            base: query.base_speaker,
+            target: Some((query.target_speaker, query.morph_rate)),
+        },
+        audio_query,
+    )
+    .await
+}
+
+async fn synthesize(blend: StyleBlend, audio_query: AudioQuery) -> Result<Vec<u8>> {
     let mut pronunciation = vec!["$2_2".to_string()];
     for (i, ap) in audio_query.accent_phrases.iter().enumerate() {
         let mut pronunciation_local = Vec::new();
@@ -46,54 +104,353 @@ pub async fn post_synthesis(
         }
     }
 
-    let aivoice = AIVOICE.lock().await;
-
     info!("Pronunciation: {:?}", pronunciation.join(""));
-    let phrase = Phrase::new(pronunciation.join(""));
-    aivoice.write_temporary_phrase_dict(Some(&phrase)).await?;
+    let phrase = Phrase::new(pronunciation.join(""), reading(&audio_query));
+    let output_sampling_rate = output_sampling_rate(&audio_query)?;
+
+    // キャッシュを引くときはホストを使わないように、アクターが最後に公開した情報からキーを作る。
+    // マスターコントロールをまだ読んでいなければ引かずに合成する
+    let cache = SYNTHESIS_CACHE.get();
+    if let Some(cache) = cache {
+        let info = ACTOR.info();
+        if let Some(master_control) = &info.master_control {
+            let master_control = match &audio_query.master_control {
+                Some(master_control_override) => master_control_override.apply(master_control),
+                None => master_control.clone(),
+            };
+            if let Ok(key) = cache_key(
+                &info,
+                info.export_format,
+                &master_control,
+                blend,
+                &audio_query,
+                &phrase,
+            ) {
+                if let Some(wav) = cache.get(&key).await {
+                    info!("Using cached audio: {}", key);
+                    return Ok(wav);
+                }
+            }
+        }
+    }
+
+    let audio_query = Arc::new(audio_query);
+    let (
+        SavedAudio {
+            mut wav,
+            master_control,
+            export_format,
+        },
+        info,
+    ) = {
+        let audio_query = audio_query.clone();
+        let phrase = phrase.clone();
+        ACTOR
+            .run(move |aivoice, cancellation| {
+                Box::pin(async move {
+                    aivoice.prefer_sampling_rate(output_sampling_rate).await?;
+                    let saved =
+                        synthesize_with_retry(aivoice, blend, &audio_query, &phrase, &cancellation)
+                            .await?;
+                    Ok((saved, actor::host_info(aivoice)))
+                })
+            })
+            .await?
+    };
+
+    if wav.channels != 1 {
+        return Err(Error::SynthesisFailed(anyhow!(
+            "Expected mono audio from A.I.Voice, got {} channels",
+            wav.channels
+        )));
+    }
+
+    wav.pad_silence(
+        audio_query.pre_phoneme_length,
+        audio_query.post_phoneme_length,
+    );
+    // A.I.Voiceが同じ周波数で書き出した場合は、サンプルをそのまま使う
+    wav.resample(output_sampling_rate);
+    if audio_query.output_stereo {
+        wav.mono_to_stereo();
+    }
+    let wav = wav.to_bytes();
+
+    if let Some(cache) = cache {
+        // 実際に使った形式とマスターコントロールのキーで保存する
+        match cache_key(
+            &info,
+            export_format,
+            &master_control,
+            blend,
+            &audio_query,
+            &phrase,
+        ) {
+            Ok(key) => {
+                if let Err(e) = cache.put(&key, &wav).await {
+                    warn!("Failed to cache audio: {}", e);
+                }
+            }
+            Err(e) => warn!("Failed to cache audio: {}", e),
+        }
+    }
+
+    Ok(wav)
+}
+
+fn output_sampling_rate(audio_query: &AudioQuery) -> Result<u32> {
+    let output_sampling_rate = audio_query
+        .output_sampling_rate
+        .as_u64()
+        .or(audio_query.output_sampling_rate.as_f64().map(|x| x as u64));
+
+    output_sampling_rate
+        .and_then(|x| u32::try_from(x).ok())
+        .ok_or_else(|| {
+            Error::SynthesisFailed(anyhow!(
+                "Invalid output sampling rate: {:?}",
+                audio_query.output_sampling_rate
+            ))
+        })
+}
+
+/// 合成結果のキャッシュのキーに使う値。出力が変わる値はすべて含める。
+#[derive(Serialize)]
+struct CacheKey<'a> {
+    host_name: &'a str,
+    export_format: ExportFormat,
+    preset: &'a VoicePreset,
+    pronunciation: &'a str,
+    /// `AudioQuery`で上書きした後の、合成に使うマスターコントロール。
+    master_control: &'a MasterControl,
+    pre_phoneme_length: f32,
+    post_phoneme_length: f32,
+    output_sampling_rate: &'a serde_json::Number,
+    output_stereo: bool,
+}
+
+/// `info`の話者一覧と、A.I.Voiceが書き出す形式と合成に使うマスターコントロールから、キャッシュのキーを作る。
+fn cache_key(
+    info: &HostInfo,
+    export_format: ExportFormat,
+    master_control: &MasterControl,
+    blend: StyleBlend,
+    audio_query: &AudioQuery,
+    phrase: &Phrase,
+) -> Result<String> {
+    let preset = voice_preset(&info.speakers, info.voice_fusion, blend, audio_query)?;
+    Ok(SynthesisCache::key(&CacheKey {
+        host_name: &info.host_name,
+        export_format,
+        preset: &preset,
+        pronunciation: phrase.pronunciation(),
+        master_control,
+        pre_phoneme_length: audio_query.pre_phoneme_length,
+        post_phoneme_length: audio_query.post_phoneme_length,
+        output_sampling_rate: &audio_query.output_sampling_rate,
+        output_stereo: audio_query.output_stereo,
+    }))
+}
+
+/// キャッシュが無効な場合は`null`を返す。
+pub async fn get_synthesis_cache() -> Json<Option<CacheStatus>> {
+    Json(SYNTHESIS_CACHE.get().map(|cache| cache.status()))
+}
+
+pub async fn delete_synthesis_cache() -> Result<StatusCode> {
+    if let Some(cache) = SYNTHESIS_CACHE.get() {
+        cache.clear().await?;
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// 合成する。合成中にホストが落ちていた場合は、再起動してから一度だけやり直す。
+async fn synthesize_with_retry(
+    aivoice: &mut AiVoice,
+    blend: StyleBlend,
+    audio_query: &AudioQuery,
+    phrase: &Phrase,
+    cancellation: &Cancellation,
+) -> Result<SavedAudio> {
+    match synthesize_once(aivoice, blend, audio_query, phrase, cancellation).await {
+        Ok(saved) => Ok(saved),
+        Err(e @ (Error::Timeout(_) | Error::Cancelled)) => Err(e),
+        Err(e) => {
+            if !aivoice.is_crashed().await {
+                return Err(e);
+            }
+            warn!("A.I.Voice crashed during synthesis, retrying once: {}", e);
+            aivoice.recover().await?;
+            synthesize_once(aivoice, blend, audio_query, phrase, cancellation).await
+        }
+    }
+}
+
+/// プリセットとテキストを設定して一度だけ合成する。ホストが落ちた場合は呼び出し側で再試行する。
+///
+/// 起動中のA.I.Voiceにアタッチしている場合は、最初の合成の前にエディタの状態を保存する。
+/// 戻すのは合成が途切れたときにアクターが行う。
+async fn synthesize_once(
+    aivoice: &mut AiVoice,
+    blend: StyleBlend,
+    audio_query: &AudioQuery,
+    phrase: &Phrase,
+    cancellation: &Cancellation,
+) -> Result<SavedAudio> {
+    aivoice.begin_attach_session().await?;
+
+    synthesize_with_preset(aivoice, blend, audio_query, phrase, cancellation).await
+}
+
+async fn synthesize_with_preset(
+    aivoice: &mut AiVoice,
+    blend: StyleBlend,
+    audio_query: &AudioQuery,
+    phrase: &Phrase,
+    cancellation: &Cancellation,
+) -> Result<SavedAudio> {
+    let text = aivoice.use_phrase(phrase).await?;
 
-    aivoice.reload_phrase_dictionary().await?;
+    let new_preset = voice_preset(
+        aivoice.speakers(),
+        *aivoice.voice_fusion(),
+        blend,
+        audio_query,
+    )?;
+
+    aivoice.set_current_voice_preset_name("AIVoiceVox").await?;
+    aivoice.set_voice_preset(&new_preset).await?;
+
+    let export_format = aivoice.export_format();
+    let previous_master_control = aivoice.master_control().await?;
+    let Some(master_control_override) = &audio_query.master_control else {
+        return Ok(SavedAudio {
+            wav: save_audio(aivoice, &text, cancellation).await?,
+            master_control: previous_master_control,
+            export_format,
+        });
+    };
+
+    let master_control = master_control_override.apply(&previous_master_control);
+    aivoice.set_master_control(&master_control).await?;
+
+    let result = save_audio(aivoice, &text, cancellation).await;
+
+    if let Err(e) = aivoice.set_master_control(&previous_master_control).await {
+        warn!("Failed to restore master control: {}", e);
+    }
 
-    let speaker_id = query.speaker / 10;
-    let style: Style = num::FromPrimitive::from_u32(query.speaker % 10).ok_or_else(|| {
-        Error::SynthesisFailed(anyhow!("Invalid style id: {}", query.speaker % 10))
-    })?;
+    Ok(SavedAudio {
+        wav: result?,
+        master_control,
+        export_format,
+    })
+}
 
-    let speaker = aivoice
-        .speakers()
-        .values()
-        .find(|speaker| *speaker.id() == speaker_id)
-        .ok_or_else(|| Error::SpeakerNotFound)?;
+/// 合成に使うボイスプリセット。ホストを使わずに決まるので、キャッシュのキーにも使う。
+fn voice_preset(
+    speakers: &IndexMap<String, Speaker>,
+    voice_fusion: bool,
+    blend: StyleBlend,
+    audio_query: &AudioQuery,
+) -> Result<VoicePreset> {
+    let (speaker, styles, merged_voice_container) = blend_voice(speakers, voice_fusion, blend)?;
 
-    let new_preset = VoicePreset {
+    // ユーザーのボイスプリセットの話者は、プリセットの値にAudioQueryの値を反映する
+    let base = speaker
+        .preset()
+        .as_ref()
+        .map(PresetParameters::from)
+        .unwrap_or_default();
+    let parameters = PresetParameters::apply(&base, audio_query);
+    Ok(VoicePreset {
         preset_name: "AIVoiceVox".to_string(),
         voice_name: speaker.internal_name().to_string(),
-        volume: audio_query.volume_scale as f64,
-        speed: audio_query.speed_scale as f64,
-        pitch: 2f32.powf(audio_query.pitch_scale) as f64,
-        pitch_range: 1.0,
-        middle_pause: 750,
-        long_pause: 750,
-        styles: speaker
-            .styles()
-            .iter()
-            .map(|x| VoicePresetStyle {
-                name: x.to_string(),
-                value: if *x == style { 1.0 } else { 0.0 },
-            })
-            .collect(),
-        merged_voice_container: MergedVoiceContainer {
-            base_pitch_voice_name: speaker.internal_name().to_string(),
-            merged_voices: vec![],
-        },
+        volume: parameters.volume,
+        speed: parameters.speed,
+        pitch: parameters.pitch,
+        pitch_range: parameters.pitch_range,
+        middle_pause: parameters.middle_pause,
+        long_pause: parameters.long_pause,
+        styles,
+        merged_voice_container,
+    })
+}
+
+/// `blend`の話者と、ボイスプリセットに設定する感情パラメータとボイスフュージョン。
+///
+/// 別の話者のスタイルと混ぜる場合は、A.I.Voiceのボイスフュージョンで2人の声質を混ぜる。
+/// ボイスフュージョンは割合を指定できないので、`morph_rate`は声の高さに使う話者を選ぶのに使う。
+/// 感情パラメータは`Speaker::style_weights`を`morph_rate`の割合で混ぜる。別の話者の場合は`base`の話者にあるスタイルだけ使う。
+fn blend_voice(
+    speakers: &IndexMap<String, Speaker>,
+    voice_fusion: bool,
+    blend: StyleBlend,
+) -> Result<(&Speaker, Vec<VoicePresetStyle>, MergedVoiceContainer)> {
+    let (speaker, base) = find_style(speakers, blend.base).ok_or_else(|| Error::SpeakerNotFound)?;
+    let (target_speaker, target, morph_rate) = match blend.target {
+        Some((target, morph_rate)) => {
+            let (target_speaker, target) =
+                find_style(speakers, target).ok_or_else(|| Error::SpeakerNotFound)?;
+            if !is_morphable(voice_fusion, speaker, target_speaker) {
+                return Err(Error::MorphingNotAllowed);
+            }
+            (target_speaker, target, morph_rate)
+        }
+        None => (speaker, base, 0.0),
     };
 
-    aivoice.set_current_voice_preset_name("AIVoiceVox").await?;
-    aivoice.set_voice_preset(&new_preset).await?;
+    let target_weights = target_speaker.style_weights(target);
+    let styles = speaker
+        .style_weights(base)
+        .into_iter()
+        .map(|style| {
+            let target_value = target_weights
+                .iter()
+                .find(|x| x.name == style.name)
+                .map_or(0.0, |x| x.value);
+            VoicePresetStyle {
+                value: style.value * (1.0 - morph_rate) + target_value * morph_rate,
+                name: style.name,
+            }
+        })
+        .collect();
 
-    aivoice
-        .set_text(phrase.uuid().hyphenated().to_string().as_str())
-        .await?;
+    let merged_voice_container = if target_speaker.internal_name() == speaker.internal_name() {
+        // ユーザーのボイスプリセットがボイスフュージョンを使っていればそのまま使う
+        match speaker.preset() {
+            Some(preset) if !preset.merged_voice_container.merged_voices.is_empty() => {
+                preset.merged_voice_container.clone()
+            }
+            _ => MergedVoiceContainer {
+                base_pitch_voice_name: speaker.internal_name().to_string(),
+                merged_voices: vec![],
+            },
+        }
+    } else {
+        let base_pitch_speaker = if morph_rate < 0.5 {
+            speaker
+        } else {
+            target_speaker
+        };
+        MergedVoiceContainer {
+            base_pitch_voice_name: base_pitch_speaker.internal_name().to_string(),
+            merged_voices: [speaker, target_speaker]
+                .iter()
+                .map(|speaker| MergedVoice {
+                    voice_name: speaker.internal_name().to_string(),
+                })
+                .collect(),
+        }
+    };
+    Ok((speaker, styles, merged_voice_container))
+}
+
+/// `text`を読み上げた音声を書き出して読み込む。
+///
+/// 書き出しには時間が掛かるので、その前にリクエストが破棄されていたら書き出さずに終える。
+async fn save_audio(aivoice: &mut AiVoice, text: &str, cancellation: &Cancellation) -> Result<Wav> {
+    aivoice.set_text(text).await?;
 
     let temp_audio_file = tempfile::Builder::new()
         .suffix(".wav")
@@ -101,89 +458,239 @@ pub async fn post_synthesis(
         .map_err(|e| Error::SynthesisFailed(e.into()))?;
     let temp_audio_file = temp_audio_file.into_temp_path();
 
+    cancellation.check()?;
+
     info!("Synthesis started: to {}", temp_audio_file.display());
 
     aivoice
         .save_audio_to_file(temp_audio_file.to_str().unwrap())
         .await?;
 
-    loop {
-        let metadata = tokio::fs::metadata(&temp_audio_file)
-            .await
-            .map_err(|e| Error::SynthesisFailed(e.into()))?;
-        if metadata.len() > 0 {
-            break;
+    aivoice.wait_for_audio(&temp_audio_file).await?;
+
+    let bytes = tokio::fs::read(&temp_audio_file)
+        .await
+        .map_err(|e| Error::SynthesisFailed(e.into()))?;
+
+    tokio::fs::remove_file(&temp_audio_file)
+        .await
+        .map_err(|e| Error::SynthesisFailed(e.into()))?;
+
+    Wav::parse(&bytes)
+}
+
+/// フレーズ辞書を使えない場合に読ませるカタカナ。ポーズは読点、疑問文は疑問符にする。
+fn reading(audio_query: &AudioQuery) -> String {
+    let mut reading = String::new();
+    for ap in &audio_query.accent_phrases {
+        for m in &ap.moras {
+            reading.push_str(&m.text);
+        }
+        if ap.is_interrogative {
+            reading.push('？');
+        }
+        if ap.pause_mora.is_some() {
+            reading.push('、');
+        }
+    }
+    reading
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        aivoice::{AiVoiceOptions, HostStatus},
+        backend::{fake::FakeHost, Backend},
+        host_thread::HostThread,
+        supervisor::{BackoffPolicy, HostState, Supervisor},
+    };
+    use serde_json::Number;
+
+    fn audio_query() -> AudioQuery {
+        AudioQuery {
+            accent_phrases: vec![],
+            speed_scale: 1.0,
+            pitch_scale: 0.0,
+            intonation_scale: 1.0,
+            volume_scale: 1.0,
+            pre_phoneme_length: 0.1,
+            post_phoneme_length: 0.1,
+            output_sampling_rate: Number::from(24000),
+            output_stereo: false,
+            kana: String::new(),
+            master_control: None,
         }
-        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
     }
 
-    let file =
-        std::fs::File::open(&temp_audio_file).map_err(|e| Error::SynthesisFailed(e.into()))?;
+    async fn started(
+        host: &FakeHost,
+        registry: &std::path::Path,
+        supervisor: &Arc<Supervisor>,
+    ) -> (AiVoice, StyleBlend) {
+        let options = AiVoiceOptions {
+            registry_path: Some(registry.join("speakers.json")),
+            ..Default::default()
+        };
+        let host: Box<dyn Backend> = Box::new(host.clone());
+        let mut aivoice =
+            AiVoice::new(host, HostThread::spawn(), &options).with_supervisor(supervisor.clone());
+        aivoice.ensure_started().await.unwrap();
+        let base = *aivoice.speakers()[0].styles()[0].id();
+        let blend = StyleBlend { base, target: None };
+        (aivoice, blend)
+    }
 
-    let (mut header, av_audio) =
-        wav_io::read_from_file(file).map_err(|e| Error::SynthesisFailed(anyhow!(e)))?;
+    #[tokio::test]
+    async fn retries_once_after_crash() {
+        let registry = tempfile::tempdir().unwrap();
+        let host = FakeHost::default();
+        let supervisor = Arc::new(Supervisor::new(BackoffPolicy::default()));
+        let (mut aivoice, blend) = started(&host, registry.path(), &supervisor).await;
+        assert_eq!(supervisor.state(), HostState::Ready);
+        let text_edit_modes = host.calls("TextEditMode=");
+        let preset_names = host.calls("VoicePresetNames");
+        let phrase = Phrase::new("$2_2テ^スト".to_string(), "テスト".to_string());
 
-    assert_eq!(header.channels, 1);
+        host.crash_on("SaveAudioToFile");
+        synthesize_with_retry(
+            &mut aivoice,
+            blend,
+            &audio_query(),
+            &phrase,
+            &Cancellation::default(),
+        )
+        .await
+        .unwrap();
 
-    let pre_silence = generate_silence(
-        header.sample_rate,
-        header.channels,
-        audio_query.pre_phoneme_length,
-    );
-    let post_silence = generate_silence(
-        header.sample_rate,
-        header.channels,
-        audio_query.post_phoneme_length,
-    );
+        assert_eq!(host.calls("SaveAudioToFile"), 2);
+        // 起動し直した後、テキストモードと「AIVoiceVox」プリセットを設定し直している
+        assert_eq!(host.calls("TextEditMode="), text_edit_modes + 1);
+        assert_eq!(host.calls("VoicePresetNames"), preset_names + 1);
+        assert_eq!(host.status(), HostStatus::Idle);
+        let status = supervisor.status();
+        assert_eq!(status.state, HostState::Ready);
+        assert_eq!(status.restarts, 1);
+        assert!(status.last_crash.is_some());
+    }
 
-    let mut audio = pre_silence;
-    audio.extend(av_audio);
-    audio.extend(post_silence);
+    #[tokio::test]
+    async fn gives_up_after_second_crash() {
+        let registry = tempfile::tempdir().unwrap();
+        let host = FakeHost::default();
+        let supervisor = Arc::new(Supervisor::new(BackoffPolicy::default()));
+        let (mut aivoice, blend) = started(&host, registry.path(), &supervisor).await;
+        let phrase = Phrase::new("$2_2テ^スト".to_string(), "テスト".to_string());
 
-    let output_sampling_rate = audio_query
-        .output_sampling_rate
-        .as_u64()
-        .or(audio_query.output_sampling_rate.as_f64().map(|x| x as u64));
+        host.crash_on("SaveAudioToFile");
+        host.crash_on("SaveAudioToFile");
+        let result = synthesize_with_retry(
+            &mut aivoice,
+            blend,
+            &audio_query(),
+            &phrase,
+            &Cancellation::default(),
+        )
+        .await;
 
-    let Some(output_sampling_rate) = output_sampling_rate else {
-        return Err(Error::SynthesisFailed(anyhow!(
-            "Invalid output sampling rate: {:?}",
-            audio_query.output_sampling_rate
-        )));
-    };
-    let Ok(output_sampling_rate) = u32::try_from(output_sampling_rate) else {
-        return Err(Error::SynthesisFailed(anyhow!(
-            "Invalid output sampling rate: {:?}",
-            audio_query.output_sampling_rate
-        )));
-    };
+        assert!(matches!(result, Err(Error::ApiFailed(_))));
+        assert_eq!(host.calls("SaveAudioToFile"), 2);
+        assert_eq!(supervisor.status().restarts, 1);
+    }
 
-    let new_audio = wav_io::resample::linear(
-        audio,
-        header.channels,
-        header.sample_rate,
-        output_sampling_rate,
-    );
-    header.sample_rate = output_sampling_rate;
+    fn style_id(speakers: &IndexMap<String, Speaker>, key: &str, name: &str) -> u32 {
+        *speakers[key]
+            .styles()
+            .iter()
+            .find(|style| style.name() == name)
+            .unwrap()
+            .id()
+    }
 
-    tokio::fs::remove_file(&temp_audio_file)
-        .await
-        .map_err(|e| Error::SynthesisFailed(e.into()))?;
+    fn weights(styles: &[VoicePresetStyle]) -> Vec<(&str, f64)> {
+        styles
+            .iter()
+            .map(|style| (style.name.as_str(), style.value))
+            .collect()
+    }
 
-    header.sample_rate = output_sampling_rate;
-    header.channels = if audio_query.output_stereo { 2 } else { 1 };
+    #[tokio::test]
+    async fn blends_styles_of_same_speaker() {
+        let registry = tempfile::tempdir().unwrap();
+        let supervisor = Arc::new(Supervisor::new(BackoffPolicy::default()));
+        let (aivoice, _) = started(&FakeHost::default(), registry.path(), &supervisor).await;
+        let speakers = aivoice.speakers();
+        let joy = style_id(speakers, "fake_voice_emo", "J");
+        let anger = style_id(speakers, "fake_voice_emo", "A");
 
-    let new_audio = if audio_query.output_stereo {
-        wav_io::utils::mono_to_stereo(new_audio)
-    } else {
-        new_audio
-    };
+        for (morph_rate, expected) in [
+            (0.0, [("J", 1.0), ("A", 0.0), ("S", 0.0)]),
+            (0.25, [("J", 0.75), ("A", 0.25), ("S", 0.0)]),
+            (1.0, [("J", 0.0), ("A", 1.0), ("S", 0.0)]),
+        ] {
+            let blend = StyleBlend {
+                base: joy,
+                target: Some((anger, morph_rate)),
+            };
+            let (speaker, styles, merged_voice_container) =
+                blend_voice(speakers, false, blend).unwrap();
+            assert_eq!(speaker.internal_name(), "fake_voice_emo");
+            assert_eq!(weights(&styles), expected);
+            // 同じ話者なので、ボイスフュージョンは使わない
+            assert_eq!(
+                merged_voice_container.base_pitch_voice_name,
+                "fake_voice_emo"
+            );
+            assert!(merged_voice_container.merged_voices.is_empty());
+        }
+    }
 
-    wav_io::write_to_bytes(&header, &new_audio).map_err(|e| Error::SynthesisFailed(anyhow!(e)))
-}
+    #[tokio::test]
+    async fn fuses_other_speaker() {
+        let registry = tempfile::tempdir().unwrap();
+        let supervisor = Arc::new(Supervisor::new(BackoffPolicy::default()));
+        let (aivoice, _) = started(&FakeHost::default(), registry.path(), &supervisor).await;
+        let speakers = aivoice.speakers();
+        let joy = style_id(speakers, "fake_voice_emo", "J");
+        let whisper = style_id(speakers, "fake_voice_ex", "Whisper");
+
+        // `morph_rate`は声の高さに使う話者だけを選ぶ。感情パラメータは`base`の話者にあるスタイルだけ混ぜる
+        for (morph_rate, joy_weight, base_pitch_voice_name) in [
+            (0.0, 1.0, "fake_voice_emo"),
+            (0.25, 0.75, "fake_voice_emo"),
+            (0.5, 0.5, "fake_voice_ex"),
+            (1.0, 0.0, "fake_voice_ex"),
+        ] {
+            let blend = StyleBlend {
+                base: joy,
+                target: Some((whisper, morph_rate)),
+            };
+            let (speaker, styles, merged_voice_container) =
+                blend_voice(speakers, true, blend).unwrap();
+            assert_eq!(speaker.internal_name(), "fake_voice_emo");
+            assert_eq!(
+                weights(&styles),
+                [("J", joy_weight), ("A", 0.0), ("S", 0.0)]
+            );
+            assert_eq!(
+                merged_voice_container.base_pitch_voice_name,
+                base_pitch_voice_name
+            );
+            let merged_voices: Vec<_> = merged_voice_container
+                .merged_voices
+                .iter()
+                .map(|voice| voice.voice_name.as_str())
+                .collect();
+            assert_eq!(merged_voices, ["fake_voice_emo", "fake_voice_ex"]);
+        }
 
-fn generate_silence(sampling_rate: u32, channels: u16, duration: f32) -> Vec<f32> {
-    let samples = (sampling_rate as f32 * duration) as usize;
-    let silence = vec![0f32; samples * channels as usize];
-    silence
+        let blend = StyleBlend {
+            base: joy,
+            target: Some((whisper, 0.5)),
+        };
+        assert!(matches!(
+            blend_voice(speakers, false, blend),
+            Err(Error::MorphingNotAllowed)
+        ));
+    }
 }
@@ -0,0 +1,293 @@
+//! A.I.Voiceの設定ファイル（XML）の読み書き。
+//!
+//! 書き換えた要素の中身だけを置き換えるので、それ以外の書式や知らない要素はそのまま残る。
+
+use crate::error::{Error, Result};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use xmlparser::{ElementEnd, Token, Tokenizer};
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    /// 開始タグから終了タグまで。
+    span: Range<usize>,
+    /// 開始タグと終了タグの間。`<Foo />`の場合は`None`。
+    content: Option<Range<usize>>,
+    children: Vec<Element>,
+}
+
+/// 1つの要素の書き換え。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettingsChange {
+    pub path: String,
+    pub old: String,
+    pub new: String,
+}
+
+#[derive(Debug)]
+struct Edit {
+    range: Range<usize>,
+    replacement: String,
+    change: SettingsChange,
+}
+
+#[derive(Debug)]
+pub struct SettingsDocument {
+    source: String,
+    root: Element,
+    edits: Vec<Edit>,
+}
+
+impl SettingsDocument {
+    pub fn parse(source: String) -> Result<Self> {
+        let mut stack: Vec<Element> = Vec::new();
+        let mut root = None;
+        for token in Tokenizer::from(source.as_str()) {
+            match token.map_err(|e| Error::SettingsParseFailed(e.into()))? {
+                Token::ElementStart {
+                    prefix,
+                    local,
+                    span,
+                } => stack.push(Element {
+                    name: qualified_name(prefix.as_str(), local.as_str()),
+                    span: span.start()..span.end(),
+                    content: None,
+                    children: Vec::new(),
+                }),
+                Token::ElementEnd { end, span } => {
+                    let element = stack.last_mut().ok_or_else(|| {
+                        Error::SettingsParseFailed(anyhow!("Unexpected end of element"))
+                    })?;
+                    match end {
+                        ElementEnd::Open => {
+                            element.content = Some(span.end()..span.end());
+                            continue;
+                        }
+                        ElementEnd::Close(prefix, local) => {
+                            let name = qualified_name(prefix.as_str(), local.as_str());
+                            if name != element.name {
+                                return Err(Error::SettingsParseFailed(anyhow!(
+                                    "Mismatched end of element: expected {}, found {}",
+                                    element.name,
+                                    name
+                                )));
+                            }
+                            if let Some(content) = &mut element.content {
+                                content.end = span.start();
+                            }
+                        }
+                        ElementEnd::Empty => {}
+                    }
+                    let mut element = stack.pop().unwrap();
+                    element.span.end = span.end();
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(element),
+                        None => root = Some(element),
+                    }
+                }
+                _ => {}
+            }
+        }
+        if let Some(element) = stack.last() {
+            return Err(Error::SettingsParseFailed(anyhow!(
+                "Unclosed element: {}",
+                element.name
+            )));
+        }
+        let root = root.ok_or_else(|| Error::SettingsParseFailed(anyhow!("No root element")))?;
+
+        Ok(Self {
+            source,
+            root,
+            edits: Vec::new(),
+        })
+    }
+
+    /// `path`（`PhraseDic/FilePath/PartialPath`のような`/`区切りの要素名）で要素を探す。
+    ///
+    /// 最初の要素名は一番浅いものを使い、同じ深さに複数ある場合はエラーにする。それ以降は直下の子要素を辿る。
+    fn find(&self, path: &str) -> Result<&Element> {
+        let mut names = path.split('/');
+        let first = names.next().unwrap_or_default();
+
+        let mut level = vec![&self.root];
+        let mut element = loop {
+            if level.is_empty() {
+                return Err(Error::SettingsElementNotFound(path.to_string()));
+            }
+            match level
+                .iter()
+                .filter(|element| element.name == first)
+                .collect::<Vec<_>>()[..]
+            {
+                [] => {}
+                [element] => break *element,
+                _ => return Err(Error::SettingsElementAmbiguous(path.to_string())),
+            }
+            level = level
+                .into_iter()
+                .flat_map(|element| &element.children)
+                .collect();
+        };
+
+        for name in names {
+            element = match element
+                .children
+                .iter()
+                .filter(|child| child.name == name)
+                .collect::<Vec<_>>()[..]
+            {
+                [] => return Err(Error::SettingsElementNotFound(path.to_string())),
+                [child] => child,
+                _ => return Err(Error::SettingsElementAmbiguous(path.to_string())),
+            };
+        }
+        Ok(element)
+    }
+
+    fn raw_text(&self, element: &Element) -> &str {
+        element
+            .content
+            .as_ref()
+            .map_or("", |content| &self.source[content.clone()])
+    }
+
+    /// `path`の要素の中身。
+    pub fn text(&self, path: &str) -> Result<String> {
+        let element = self.find(path)?;
+        Ok(html_escape::decode_html_entities(self.raw_text(element).trim()).into_owned())
+    }
+
+    /// `path`の要素の中身を`value`にする。子要素がある要素は書き換えられない。
+    pub fn set_text(&mut self, path: &str, value: &str) -> Result<()> {
+        let element = self.find(path)?;
+        if !element.children.is_empty() {
+            return Err(Error::SettingsParseFailed(anyhow!(
+                "Element has children: {}",
+                path
+            )));
+        }
+        let old = html_escape::decode_html_entities(self.raw_text(element).trim()).into_owned();
+        let escaped = html_escape::encode_text(value);
+        let (range, replacement) = match &element.content {
+            Some(content) => (content.clone(), escaped.into_owned()),
+            None => {
+                // `<Foo />`は`<Foo>値</Foo>`にする
+                let tag = self.source[element.span.clone()]
+                    .trim_end_matches('>')
+                    .trim_end_matches('/')
+                    .trim_end();
+                (
+                    element.span.clone(),
+                    format!("{}>{}</{}>", tag, escaped, element.name),
+                )
+            }
+        };
+
+        self.edits.retain(|edit| edit.range != range);
+        if old != value {
+            self.edits.push(Edit {
+                range,
+                replacement,
+                change: SettingsChange {
+                    path: path.to_string(),
+                    old,
+                    new: value.to_string(),
+                },
+            });
+        }
+        Ok(())
+    }
+
+    pub fn changes(&self) -> impl Iterator<Item = &SettingsChange> {
+        self.edits.iter().map(|edit| &edit.change)
+    }
+
+    pub fn is_changed(&self) -> bool {
+        !self.edits.is_empty()
+    }
+
+    /// 書き換えを反映した設定ファイルの中身。
+    pub fn render(&self) -> String {
+        let mut edits = self.edits.iter().collect::<Vec<_>>();
+        edits.sort_by_key(|edit| edit.range.start);
+
+        let mut rendered = String::with_capacity(self.source.len());
+        let mut position = 0;
+        for edit in edits {
+            rendered.push_str(&self.source[position..edit.range.start]);
+            rendered.push_str(&edit.replacement);
+            position = edit.range.end;
+        }
+        rendered.push_str(&self.source[position..]);
+        rendered
+    }
+
+    /// 書き換える要素ごとの差分。
+    pub fn diff(&self) -> String {
+        self.changes()
+            .map(|change| format!("{}\n- {}\n+ {}\n", change.path, change.old, change.new))
+            .collect()
+    }
+}
+
+fn qualified_name(prefix: &str, local: &str) -> String {
+    if prefix.is_empty() {
+        local.to_string()
+    } else {
+        format!("{}:{}", prefix, local)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = include_str!("../fixtures/Standard.settings");
+
+    fn document(source: &str) -> SettingsDocument {
+        SettingsDocument::parse(source.to_string()).unwrap()
+    }
+
+    #[test]
+    fn finds_shallowest_element() {
+        let document = document(FIXTURE);
+        // `RecentFiles/Item/PhraseDic`より浅い`DictionarySetting/PhraseDic`を使う
+        assert_eq!(
+            document.text("PhraseDic/FilePath/PartialPath").unwrap(),
+            r"AI\A.I.VOICE Editor\user.pdic"
+        );
+        assert_eq!(document.text("SplitCondition").unwrap(), "");
+    }
+
+    #[test]
+    fn rejects_ambiguous_path() {
+        let document =
+            document("<Root><A><B>1</B></A><A><B>2</B></A><C><D>1</D><D>2</D></C></Root>");
+        assert!(matches!(
+            document.text("A/B"),
+            Err(Error::SettingsElementAmbiguous(path)) if path == "A/B"
+        ));
+        assert!(matches!(
+            document.text("C/D"),
+            Err(Error::SettingsElementAmbiguous(path)) if path == "C/D"
+        ));
+    }
+
+    #[test]
+    fn rejects_missing_element() {
+        let mut document = document(FIXTURE);
+        assert!(matches!(
+            document.text("PhraseDic/FilePath/Missing"),
+            Err(Error::SettingsElementNotFound(path)) if path == "PhraseDic/FilePath/Missing"
+        ));
+        assert!(matches!(
+            document.set_text("Missing", "value"),
+            Err(Error::SettingsElementNotFound(path)) if path == "Missing"
+        ));
+        assert!(!document.is_changed());
+    }
+}
@@ -0,0 +1,100 @@
+//! 設定ファイルを書き換えたことの記録。
+//!
+//! 書き換える前に元の設定ファイルのバックアップとこの記録を書き、元に戻したら消す。
+//! 途中でプロセスが終了して記録が残っていた場合は、次に起動したときに記録を使って元に戻す。
+
+use crate::error::{Error, Result};
+use crate::settings_document::SettingsChange;
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SettingsJournal {
+    /// 書き換えたプロセスのID。ログにだけ使う。
+    pub pid: u32,
+    /// 書き換えた日時（RFC 3339）。
+    pub modified_at: String,
+    /// 元の設定ファイル（バックアップ）のチェックサム。
+    pub original_checksum: String,
+    /// 書き換えた直後の設定ファイルのチェックサム。
+    pub modified_checksum: String,
+    pub changes: Vec<SettingsChange>,
+}
+
+impl SettingsJournal {
+    pub fn new(original: &[u8], modified: &[u8], changes: Vec<SettingsChange>) -> Self {
+        Self {
+            pid: std::process::id(),
+            modified_at: Local::now().to_rfc3339(),
+            original_checksum: checksum(original),
+            modified_checksum: checksum(modified),
+            changes,
+        }
+    }
+
+    /// 記録が無い場合は`None`。
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        let contents = match fs_err::read(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::RestoreSettingsFailed(e.into())),
+        };
+        serde_json::from_slice(&contents)
+            .map(Some)
+            .map_err(|e| Error::RestoreSettingsFailed(e.into()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let contents =
+            serde_json::to_vec_pretty(self).map_err(|e| Error::WriteSettingsFailed(e.into()))?;
+        write_atomically(path, &contents).map_err(|e| Error::WriteSettingsFailed(e.into()))
+    }
+}
+
+/// ファイルの中身のチェックサム。
+pub fn checksum(contents: &[u8]) -> String {
+    Uuid::new_v5(&Uuid::NAMESPACE_OID, contents)
+        .simple()
+        .to_string()
+}
+
+/// 書き込み中に終了しても壊れたファイルが残らないように、別のファイルに書いてから置き換える。
+pub fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut temporary_path = path.as_os_str().to_owned();
+    temporary_path.push(".tmp");
+    fs_err::write(&temporary_path, contents)?;
+    fs_err::rename(&temporary_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_journal() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.json");
+        assert!(SettingsJournal::load(&path).unwrap().is_none());
+
+        SettingsJournal::new(b"original", b"modified", vec![])
+            .save(&path)
+            .unwrap();
+        let journal = SettingsJournal::load(&path).unwrap().unwrap();
+        assert_eq!(journal.original_checksum, checksum(b"original"));
+        assert_eq!(journal.modified_checksum, checksum(b"modified"));
+    }
+
+    #[test]
+    fn reports_write_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing").join("journal.json");
+        assert!(matches!(
+            SettingsJournal::new(b"original", b"modified", vec![]).save(&path),
+            Err(Error::WriteSettingsFailed(_))
+        ));
+    }
+}
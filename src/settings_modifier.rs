@@ -1,113 +1,554 @@
 use crate::error::{Error, Result};
+use crate::settings_document::{SettingsChange, SettingsDocument};
+use crate::settings_journal::{checksum, write_atomically, SettingsJournal};
 
-use indoc::indoc;
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
 use std::env;
+use std::panic;
 use std::path::{Path, PathBuf};
-use tracing::{info, warn};
+use std::sync::Once;
+use std::thread;
+use tracing::{error, info, warn};
+
+/// A.I.Voiceが書き出せるサンプリング周波数。
+pub const EXPORT_SAMPLING_RATES: [u32; 6] = [8000, 11025, 16000, 22050, 44100, 48000];
+
+/// A.I.Voiceが書き出す音声の量子化ビット数。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportBitDepth {
+    #[default]
+    Int16,
+    Int24,
+    Float32,
+}
+
+impl ExportBitDepth {
+    /// 設定ファイルの`BitDepth`の値。A.I.Voiceの音声保存設定の選択肢の順番。
+    fn setting_value(self) -> &'static str {
+        match self {
+            ExportBitDepth::Int16 => "0",
+            ExportBitDepth::Int24 => "1",
+            ExportBitDepth::Float32 => "2",
+        }
+    }
+}
+
+/// A.I.Voiceが書き出す音声の形式。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ExportFormat {
+    pub sampling_rate: u32,
+    pub bit_depth: ExportBitDepth,
+}
+
+impl Default for ExportFormat {
+    fn default() -> Self {
+        Self {
+            sampling_rate: 48000,
+            bit_depth: ExportBitDepth::default(),
+        }
+    }
+}
+
+/// 設定ファイルに書き込む値のうち、変更できるもの。
+#[derive(Debug, Clone, Default)]
+pub struct EditorSettings {
+    /// 文頭の無音（ミリ秒）。VOICEVOX側で`prePhonemeLength`を足すので既定は0。
+    pub begin_pause: u32,
+    /// 文末の無音（ミリ秒）。VOICEVOX側で`postPhonemeLength`を足すので既定は0。
+    pub term_pause: u32,
+    pub export: ExportFormat,
+}
+
+/// 設定ファイルを指定しない場合に使うプロファイル。
+const DEFAULT_PROFILE: &str = "Standard";
+
+/// インストールされているA.I.Voiceの設定ファイル。
+#[derive(Debug, Clone)]
+pub struct SettingsFile {
+    /// 設定フォルダの名前（`1.0`など）。
+    pub version: String,
+    /// 拡張子を除いたファイル名（`Standard`など）。
+    pub profile: String,
+    pub path: PathBuf,
+}
 
 #[derive(Debug)]
-pub struct SettingsModifier {}
+pub struct SettingsModifier {
+    settings: EditorSettings,
+    settings_path: PathBuf,
+}
 
 impl SettingsModifier {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(settings: EditorSettings, settings_path: PathBuf) -> Self {
+        Self {
+            settings,
+            settings_path,
+        }
     }
 
-    pub fn aivoice_setting_dir() -> PathBuf {
-        Path::new(&env::var("LOCALAPPDATA").unwrap())
+    /// A.I.Voiceの設定フォルダ。この下にバージョンごとのフォルダがある。
+    pub fn aivoice_setting_dir() -> Result<PathBuf> {
+        let local_app_data = env::var_os("LOCALAPPDATA")
+            .ok_or_else(|| Error::SettingsNotFound("%LOCALAPPDATA%".to_string()))?;
+        Ok(Path::new(&local_app_data)
             .join("AI")
-            .join("A.I.VOICE Editor")
-            .join("1.0")
+            .join("A.I.VOICE Editor"))
+    }
+
+    /// インストールされているA.I.Voiceの設定ファイル。新しいバージョンのものから順に並べる。
+    pub fn discover_settings_files() -> Result<Vec<SettingsFile>> {
+        discover_settings_files_in(&SettingsModifier::aivoice_setting_dir()?)
     }
-    pub fn aivoice_setting_path() -> PathBuf {
-        SettingsModifier::aivoice_setting_dir().join("Standard.settings")
+
+    /// 使う設定ファイル。`specified`が無ければ、一番新しいバージョンの`Standard.settings`を使う。
+    pub fn find_settings_path(specified: Option<&Path>) -> Result<PathBuf> {
+        if let Some(path) = specified {
+            if !path.is_file() {
+                return Err(Error::SettingsNotFound(path.display().to_string()));
+            }
+            info!("A.I.Voice settings (specified): {}", path.display());
+            return Ok(path.to_path_buf());
+        }
+
+        find_settings_path_in(&SettingsModifier::aivoice_setting_dir()?)
+    }
+
+    /// A.I.Voiceが読み込むフレーズ辞書のパスを設定ファイルから読む。
+    pub fn phrase_dictionary_path(settings_path: &Path) -> Result<PathBuf> {
+        let settings = std::fs::read_to_string(settings_path)
+            .map_err(|e| Error::SettingsParseFailed(e.into()))?;
+        let document = SettingsDocument::parse(settings)?;
+        if document.text("PhraseDic/IsEnabled")? != "true" {
+            return Err(Error::PhraseDictionaryDisabled);
+        }
+
+        let partial_path = document.text("PhraseDic/FilePath/PartialPath")?;
+        if document.text("PhraseDic/FilePath/IsSpecialFolderEnabled")? != "true" {
+            return Ok(PathBuf::from(partial_path));
+        }
+
+        let env_path = |name: &str| {
+            env::var(name)
+                .map(PathBuf::from)
+                .map_err(|e| Error::SettingsParseFailed(e.into()))
+        };
+        // .NETのEnvironment.SpecialFolderの名前
+        let special_folder = match document.text("PhraseDic/FilePath/SpecialFolder")?.as_str() {
+            "Personal" | "MyDocuments" => env_path("USERPROFILE")?.join("Documents"),
+            "Desktop" | "DesktopDirectory" => env_path("USERPROFILE")?.join("Desktop"),
+            "ApplicationData" => env_path("APPDATA")?,
+            "LocalApplicationData" => env_path("LOCALAPPDATA")?,
+            folder => {
+                return Err(Error::SettingsParseFailed(anyhow!(
+                    "Unsupported special folder: {}",
+                    folder
+                )))
+            }
+        };
+        Ok(special_folder.join(partial_path))
     }
-    pub fn aivoice_backup_setting_path() -> PathBuf {
-        SettingsModifier::aivoice_setting_dir().join("Standard.settings.bak")
+
+    /// `settings`に起動時の書き換えを適用する。書き込みはしない。
+    pub fn apply(
+        &self,
+        settings: String,
+        temporary_phrase_dict_path: &Path,
+    ) -> Result<SettingsDocument> {
+        let mut document = SettingsDocument::parse(settings)?;
+
+        document.set_text("PhraseDic/FilePath/IsSpecialFolderEnabled", "false")?;
+        document.set_text(
+            "PhraseDic/FilePath/PartialPath",
+            &temporary_phrase_dict_path.display().to_string(),
+        )?;
+        document.set_text("PhraseDic/IsEnabled", "true")?;
+        document.set_text("BeginPause", &self.settings.begin_pause.to_string())?;
+        document.set_text("TermPause", &self.settings.term_pause.to_string())?;
+        document.set_text("BitDepth", self.settings.export.bit_depth.setting_value())?;
+        document.set_text(
+            "SamplesPerSec",
+            &self.settings.export.sampling_rate.to_string(),
+        )?;
+        document.set_text("PcmAudioType", "Linear")?;
+        document.set_text("FilePathSelectionMode", "FileSaveDialog")?;
+        document.set_text("IsTextFileCreated", "false")?;
+        document.set_text("SplitCondition", "None")?;
+
+        Ok(document)
     }
 
     pub async fn modify(&mut self, temporary_phrase_dict_path: &Path) -> Result<()> {
-        info!(
-            "A.I.Voice setting path: {}",
-            &SettingsModifier::aivoice_setting_path().display()
-        );
+        let settings_path = &self.settings_path;
+        info!("Modifying A.I.Voice settings: {}", settings_path.display());
+
+        // 前回の書き換えが残っていれば、先に元に戻してから書き換える
+        SettingsModifier::restore(settings_path)?;
 
-        let mut settings = tokio::fs::read_to_string(&SettingsModifier::aivoice_setting_path())
+        let original = tokio::fs::read_to_string(settings_path)
             .await
             .map_err(|e| Error::SettingsParseFailed(e.into()))?;
+        let document = self.apply(original.clone(), temporary_phrase_dict_path)?;
+        for change in document.changes() {
+            info!(
+                "Setting {}: {:?} -> {:?}",
+                change.path, change.old, change.new
+            );
+        }
+        let modified = document.render();
 
-        let modify_setting = |settings: &str, key: &str, value: &str| -> String {
-            let key_start = settings
-                .find(format!("<{}>", key).as_str())
-                .unwrap_or_else(|| panic!("key_start not found, key: {}", key))
-                + key.len()
-                + 2;
-            let key_end = settings[key_start..]
-                .find(format!("</{}>", key).as_str())
-                .unwrap_or_else(|| panic!("key_end not found, key: {}", key))
-                + key_start;
-
-            format!(
-                "{}{}{}",
-                &settings[..key_start],
-                value,
-                &settings[key_end..]
-            )
-        };
+        write_atomically(&backup_path(settings_path), original.as_bytes())
+            .map_err(|e| Error::WriteSettingsFailed(e.into()))?;
+        SettingsJournal::new(
+            original.as_bytes(),
+            modified.as_bytes(),
+            document.changes().cloned().collect(),
+        )
+        .save(&journal_path(settings_path))?;
+        write_atomically(settings_path, modified.as_bytes())
+            .map_err(|e| Error::WriteSettingsFailed(e.into()))?;
 
-        let new_phrase_dic = format!(
-            indoc! {
-            r#"
-              <FilePath>
-                <IsSpecialFolderEnabled>false</IsSpecialFolderEnabled>
-                <SpecialFolder>Personal</SpecialFolder>
-                <PartialPath>{}</PartialPath>
-              </FilePath>
-              <IsEnabled>true</IsEnabled>
-            "#
-            },
-            html_escape::encode_text(&temporary_phrase_dict_path.display().to_string())
+        Ok(())
+    }
+
+    pub async fn restore_settings(&self) -> Result<()> {
+        SettingsModifier::restore(&self.settings_path)?;
+        Ok(())
+    }
+
+    /// `modify`で書き換えた`settings_path`を元に戻す。戻すものが無かった場合は`false`を返す。
+    ///
+    /// 書き換えた後に設定ファイルが変わっていなければバックアップをそのまま戻す。
+    /// A.I.Voiceが設定を保存するなどして変わっていた場合は、書き換えた値のままの要素だけを元の値に戻す。
+    pub fn restore(settings_path: &Path) -> Result<bool> {
+        let backup_path = backup_path(settings_path);
+        let journal_path = journal_path(settings_path);
+        let Some(journal) = SettingsJournal::load(&journal_path)? else {
+            if !backup_path.exists() {
+                return Ok(false);
+            }
+            // 記録を書く前のバージョンが残したバックアップ。書き換えた後に残るので、そのまま戻す
+            warn!("Restoring A.I.Voice settings from a backup without journal");
+            fs_err::copy(&backup_path, settings_path)
+                .map_err(|e| Error::RestoreSettingsFailed(e.into()))?;
+            fs_err::remove_file(&backup_path)
+                .map_err(|e| Error::RestoreSettingsFailed(e.into()))?;
+            info!("A.I.Voice settings restored");
+            return Ok(true);
+        };
+        info!(
+            "Restoring A.I.Voice settings modified by process {} at {}",
+            journal.pid, journal.modified_at
         );
-        settings = modify_setting(&settings, "PhraseDic", &new_phrase_dic);
-        settings = modify_setting(&settings, "BeginPause", "0");
-        settings = modify_setting(&settings, "TermPause", "0");
-        settings = modify_setting(&settings, "BitDepth", "0");
-        settings = modify_setting(&settings, "SamplesPerSec", "48000");
-        settings = modify_setting(&settings, "PcmAudioType", "Linear");
-        settings = modify_setting(&settings, "FilePathSelectionMode", "FileSaveDialog");
-        settings = modify_setting(&settings, "IsTextFileCreated", "false");
-        settings = modify_setting(&settings, "SplitCondition", "None");
-
-        if tokio::fs::metadata(&SettingsModifier::aivoice_backup_setting_path())
-            .await
-            .is_ok()
-        {
-            warn!("A.I.Voice settings backup file already exists, skipping backup (perhaps the previous run was interrupted)");
+
+        let backup =
+            fs_err::read(&backup_path).map_err(|e| Error::RestoreSettingsFailed(e.into()))?;
+        if checksum(&backup) != journal.original_checksum {
+            return Err(Error::RestoreSettingsFailed(anyhow!(
+                "Checksum mismatch: {}",
+                backup_path.display()
+            )));
+        }
+        let current =
+            fs_err::read(settings_path).map_err(|e| Error::RestoreSettingsFailed(e.into()))?;
+        let restored = if checksum(&current) == journal.modified_checksum {
+            backup
         } else {
-            tokio::fs::copy(
-                &SettingsModifier::aivoice_setting_path(),
-                &SettingsModifier::aivoice_backup_setting_path(),
-            )
-            .await
-            .map_err(|e| Error::SettingsParseFailed(e.into()))?;
+            warn!("A.I.Voice settings were changed after modification, reverting only the modified elements");
+            revert(current, &journal.changes)?
+        };
+
+        write_atomically(settings_path, &restored)
+            .map_err(|e| Error::RestoreSettingsFailed(e.into()))?;
+        let written =
+            fs_err::read(settings_path).map_err(|e| Error::RestoreSettingsFailed(e.into()))?;
+        if checksum(&written) != checksum(&restored) {
+            return Err(Error::RestoreSettingsFailed(anyhow!(
+                "Checksum mismatch: {}",
+                settings_path.display()
+            )));
         }
 
-        tokio::fs::write(&SettingsModifier::aivoice_setting_path(), settings)
-            .await
-            .map_err(|e| Error::SettingsParseFailed(e.into()))?;
+        // 記録より先にバックアップを消すと、次に起動したときに戻せなくなる
+        fs_err::remove_file(&journal_path).map_err(|e| Error::RestoreSettingsFailed(e.into()))?;
+        fs_err::remove_file(&backup_path).map_err(|e| Error::RestoreSettingsFailed(e.into()))?;
+        info!("A.I.Voice settings restored");
+        Ok(true)
+    }
 
-        Ok(())
+    /// パニックで終了するときに設定ファイルを元に戻す。
+    ///
+    /// tokioのタスクやホストのスレッドでのパニックは捕まえられて処理が続くので、メインスレッドでのパニックだけを対象にする。
+    pub fn install_panic_hook(settings_path: PathBuf) {
+        static INSTALL: Once = Once::new();
+        INSTALL.call_once(|| {
+            let default_hook = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                default_hook(info);
+                if thread::current().name() != Some("main") {
+                    return;
+                }
+                if let Err(e) = SettingsModifier::restore(&settings_path) {
+                    error!("Failed to restore A.I.Voice settings: {}", e);
+                }
+            }));
+        });
+    }
+}
+
+/// `setting_dir`の下のバージョンごとのフォルダにある設定ファイル。新しいバージョンのものから順に並べる。
+fn discover_settings_files_in(setting_dir: &Path) -> Result<Vec<SettingsFile>> {
+    let not_found = || Error::SettingsNotFound(setting_dir.display().to_string());
+
+    let mut files = Vec::new();
+    for entry in fs_err::read_dir(setting_dir).map_err(|_| not_found())? {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        let Some(version) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        let Some(version_number) = parse_version(&version) else {
+            continue;
+        };
+        let Ok(version_entries) = fs_err::read_dir(entry.path()) else {
+            continue;
+        };
+        for file in version_entries.flatten() {
+            let path = file.path();
+            if path.extension() != Some("settings".as_ref()) {
+                continue;
+            }
+            let Some(profile) = path.file_stem().and_then(|x| x.to_str()) else {
+                continue;
+            };
+            files.push((
+                version_number.clone(),
+                SettingsFile {
+                    version: version.clone(),
+                    profile: profile.to_string(),
+                    path: path.clone(),
+                },
+            ));
+        }
+    }
+    files.sort_by(|(a, a_file), (b, b_file)| {
+        b.cmp(a).then_with(|| {
+            (b_file.profile == DEFAULT_PROFILE)
+                .cmp(&(a_file.profile == DEFAULT_PROFILE))
+                .then_with(|| a_file.profile.cmp(&b_file.profile))
+        })
+    });
+    Ok(files.into_iter().map(|(_, file)| file).collect())
+}
+
+/// `setting_dir`にある一番新しいバージョンの`Standard.settings`。
+fn find_settings_path_in(setting_dir: &Path) -> Result<PathBuf> {
+    let files = discover_settings_files_in(setting_dir)?;
+    for file in &files {
+        info!(
+            "Found A.I.Voice settings: {} ({}, {})",
+            file.path.display(),
+            file.version,
+            file.profile
+        );
+    }
+    let file = files
+        .into_iter()
+        .find(|file| file.profile == DEFAULT_PROFILE)
+        .ok_or_else(|| Error::SettingsNotFound(format!("{}.settings", DEFAULT_PROFILE)))?;
+    info!("A.I.Voice settings: {}", file.path.display());
+    Ok(file.path)
+}
+
+/// `1.0`のようなフォルダ名を比較できる形にする。
+fn parse_version(name: &str) -> Option<Vec<u32>> {
+    name.split('.').map(|part| part.parse().ok()).collect()
+}
+
+fn backup_path(settings_path: &Path) -> PathBuf {
+    let mut path = settings_path.as_os_str().to_owned();
+    path.push(".bak");
+    path.into()
+}
+
+fn journal_path(settings_path: &Path) -> PathBuf {
+    let mut path = settings_path.as_os_str().to_owned();
+    path.push(".journal");
+    path.into()
+}
+
+/// `settings`のうち、書き換えた値のままの要素を元の値に戻す。
+fn revert(settings: Vec<u8>, changes: &[SettingsChange]) -> Result<Vec<u8>> {
+    let settings =
+        String::from_utf8(settings).map_err(|e| Error::RestoreSettingsFailed(e.into()))?;
+    let mut document = SettingsDocument::parse(settings)?;
+    for change in changes {
+        match document.text(&change.path) {
+            Ok(text) if text == change.new => document.set_text(&change.path, &change.old)?,
+            Ok(_) => info!("Keeping {} changed after modification", change.path),
+            Err(e) => warn!("Failed to revert {}: {}", change.path, e),
+        }
     }
+    Ok(document.render().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    pub async fn restore_settings(&self) -> std::result::Result<(), std::io::Error> {
-        info!("Restoring A.I.Voice settings...");
-        tokio::fs::copy(
-            SettingsModifier::aivoice_backup_setting_path(),
-            SettingsModifier::aivoice_setting_path(),
+    const FIXTURE: &str = include_str!("../fixtures/Standard.settings");
+
+    fn modifier() -> SettingsModifier {
+        SettingsModifier::new(
+            EditorSettings {
+                begin_pause: 0,
+                term_pause: 0,
+                export: ExportFormat {
+                    sampling_rate: 24000,
+                    bit_depth: ExportBitDepth::Float32,
+                },
+            },
+            PathBuf::from("Standard.settings"),
         )
-        .await?;
-        tokio::fs::remove_file(SettingsModifier::aivoice_backup_setting_path()).await?;
-        info!("A.I.Voice settings restored");
-        Ok(())
+    }
+
+    #[test]
+    fn applies_editor_settings() {
+        let dictionary = Path::new(r"C:\aivoice-vox\temporary_phrase_dict.pdic");
+        let document = modifier().apply(FIXTURE.to_string(), dictionary).unwrap();
+        let rendered = SettingsDocument::parse(document.render()).unwrap();
+
+        for (path, value) in [
+            ("PhraseDic/FilePath/IsSpecialFolderEnabled", "false"),
+            (
+                "PhraseDic/FilePath/PartialPath",
+                dictionary.to_str().unwrap(),
+            ),
+            ("PhraseDic/IsEnabled", "true"),
+            ("BeginPause", "0"),
+            ("TermPause", "0"),
+            ("BitDepth", "2"),
+            ("SamplesPerSec", "24000"),
+            ("PcmAudioType", "Linear"),
+            ("FilePathSelectionMode", "FileSaveDialog"),
+            ("IsTextFileCreated", "false"),
+            ("SplitCondition", "None"),
+        ] {
+            assert_eq!(rendered.text(path).unwrap(), value, "{}", path);
+        }
+        // 書き換えない要素はそのまま
+        assert_eq!(
+            rendered
+                .text("UserDic/FilePath/IsSpecialFolderEnabled")
+                .unwrap(),
+            "true"
+        );
+        assert_eq!(
+            rendered
+                .text("Item/PhraseDic/FilePath/PartialPath")
+                .unwrap(),
+            r"C:\Users\user\Documents\old.pdic"
+        );
+        assert!(document
+            .render()
+            .contains("<SplitCondition>None</SplitCondition>"));
+
+        // 2回目は何も変わらない
+        let reapplied = modifier().apply(document.render(), dictionary).unwrap();
+        assert!(!reapplied.is_changed());
+    }
+
+    #[test]
+    fn diffs_changed_elements() {
+        let dictionary = Path::new(r"C:\aivoice-vox\temporary_phrase_dict.pdic");
+        let document = modifier().apply(FIXTURE.to_string(), dictionary).unwrap();
+        assert_eq!(
+            document.diff(),
+            concat!(
+                "PhraseDic/FilePath/IsSpecialFolderEnabled\n- true\n+ false\n",
+                "PhraseDic/FilePath/PartialPath\n- AI\\A.I.VOICE Editor\\user.pdic\n+ C:\\aivoice-vox\\temporary_phrase_dict.pdic\n",
+                "PhraseDic/IsEnabled\n- false\n+ true\n",
+                "BeginPause\n- 150\n+ 0\n",
+                "TermPause\n- 800\n+ 0\n",
+                "BitDepth\n- 1\n+ 2\n",
+                "SamplesPerSec\n- 44100\n+ 24000\n",
+                "FilePathSelectionMode\n- FileNameRule\n+ FileSaveDialog\n",
+                "IsTextFileCreated\n- true\n+ false\n",
+                "SplitCondition\n- \n+ None\n",
+            )
+        );
+    }
+
+    /// `setting_dir`の下に`version/profile.settings`を作る。
+    fn create_settings(setting_dir: &Path, version: &str, profile: &str) -> PathBuf {
+        let directory = setting_dir.join(version);
+        fs_err::create_dir_all(&directory).unwrap();
+        let path = directory.join(format!("{}.settings", profile));
+        fs_err::write(&path, FIXTURE).unwrap();
+        path
+    }
+
+    #[test]
+    fn discovers_newest_version_first() {
+        let setting_dir = tempfile::tempdir().unwrap();
+        let old = create_settings(setting_dir.path(), "1.9", DEFAULT_PROFILE);
+        let new = create_settings(setting_dir.path(), "1.10", DEFAULT_PROFILE);
+        let other = create_settings(setting_dir.path(), "1.10", "Other");
+        create_settings(setting_dir.path(), "backup", DEFAULT_PROFILE);
+        create_settings(setting_dir.path(), "2.0-beta", DEFAULT_PROFILE);
+        fs_err::write(setting_dir.path().join("1.10").join("notes.txt"), "").unwrap();
+
+        let files = discover_settings_files_in(setting_dir.path()).unwrap();
+        let found: Vec<_> = files
+            .iter()
+            .map(|file| (file.version.as_str(), file.profile.as_str(), &file.path))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("1.10", DEFAULT_PROFILE, &new),
+                ("1.10", "Other", &other),
+                ("1.9", DEFAULT_PROFILE, &old),
+            ]
+        );
+        assert_eq!(find_settings_path_in(setting_dir.path()).unwrap(), new);
+    }
+
+    #[test]
+    fn specified_settings_win() {
+        let directory = tempfile::tempdir().unwrap();
+        create_settings(directory.path(), "1.10", DEFAULT_PROFILE);
+        let specified = create_settings(directory.path(), "custom", "Mine");
+
+        assert_eq!(
+            SettingsModifier::find_settings_path(Some(&specified)).unwrap(),
+            specified
+        );
+        assert!(matches!(
+            SettingsModifier::find_settings_path(Some(&directory.path().join("missing.settings"))),
+            Err(Error::SettingsNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn reports_missing_settings() {
+        let setting_dir = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            discover_settings_files_in(&setting_dir.path().join("missing")),
+            Err(Error::SettingsNotFound(_))
+        ));
+        assert!(matches!(
+            find_settings_path_in(setting_dir.path()),
+            Err(Error::SettingsNotFound(_))
+        ));
+
+        create_settings(setting_dir.path(), "1.10", "Other");
+        create_settings(setting_dir.path(), "latest", DEFAULT_PROFILE);
+        assert!(matches!(
+            find_settings_path_in(setting_dir.path()),
+            Err(Error::SettingsNotFound(name)) if name == "Standard.settings"
+        ));
     }
 }
@@ -0,0 +1,153 @@
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// ホストの状態。`/host_state`で公開する。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum HostState {
+    /// ホストを起動していない。合成などのリクエストが来たら起動する。
+    Standby,
+    /// 起動中。
+    Starting,
+    /// 合成を受け付けられる。
+    Ready,
+    /// 落ちたホストを再起動している。
+    Recovering { attempt: u32 },
+    /// 再起動を諦めた。次のリクエストで再度試す。
+    Failed { error: String },
+    /// 終了した。
+    Stopped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SupervisorStatus {
+    #[serde(flatten)]
+    pub state: HostState,
+    /// すぐに合成を受け付けられるかどうか。`state`が`ready`のときだけ`true`。
+    pub ready: bool,
+    /// これまでに再起動に成功した回数。
+    pub restarts: u32,
+    /// 最後にホストが落ちているのを検出した時刻。
+    pub last_crash: Option<String>,
+}
+
+/// 再起動の間隔。`initial`から倍々に増え、`max`で頭打ちになる。
+#[derive(Debug, Clone)]
+pub struct BackoffPolicy {
+    pub initial: Duration,
+    pub max: Duration,
+    pub max_attempts: u32,
+}
+
+impl Default for BackoffPolicy {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(30),
+            max_attempts: 5,
+        }
+    }
+}
+
+impl BackoffPolicy {
+    /// `attempt`回目（0始まり）の再起動に失敗した後に待つ時間。
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.initial
+            .checked_mul(2u32.saturating_pow(attempt))
+            .unwrap_or(self.max)
+            .min(self.max)
+    }
+}
+
+#[derive(Debug)]
+pub struct Supervisor {
+    status: Mutex<SupervisorStatus>,
+    policy: BackoffPolicy,
+}
+
+impl Supervisor {
+    pub fn new(policy: BackoffPolicy) -> Self {
+        Self {
+            status: Mutex::new(SupervisorStatus {
+                state: HostState::Standby,
+                ready: false,
+                restarts: 0,
+                last_crash: None,
+            }),
+            policy,
+        }
+    }
+
+    pub fn policy(&self) -> &BackoffPolicy {
+        &self.policy
+    }
+
+    pub fn status(&self) -> SupervisorStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub fn state(&self) -> HostState {
+        self.status.lock().unwrap().state.clone()
+    }
+
+    pub fn set_state(&self, state: HostState) {
+        let mut status = self.status.lock().unwrap();
+        status.ready = state == HostState::Ready;
+        status.state = state;
+    }
+
+    pub fn crashed(&self) {
+        let mut status = self.status.lock().unwrap();
+        status.last_crash = Some(chrono::Local::now().to_rfc3339());
+    }
+
+    pub fn recovered(&self) {
+        let mut status = self.status.lock().unwrap();
+        status.state = HostState::Ready;
+        status.ready = true;
+        status.restarts += 1;
+    }
+}
+
+pub static SUPERVISOR: Lazy<Arc<Supervisor>> =
+    Lazy::new(|| Arc::new(Supervisor::new(BackoffPolicy::default())));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doubles_delay_up_to_max() {
+        let policy = BackoffPolicy::default();
+        let delays = (0..7).map(|attempt| policy.delay(attempt).as_secs());
+        assert_eq!(delays.collect::<Vec<_>>(), [1, 2, 4, 8, 16, 30, 30]);
+        assert_eq!(policy.delay(40), policy.max);
+        assert_eq!(policy.delay(u32::MAX), policy.max);
+    }
+
+    #[test]
+    fn tracks_restarts() {
+        let supervisor = Supervisor::new(BackoffPolicy::default());
+        assert_eq!(supervisor.state(), HostState::Standby);
+        assert!(!supervisor.status().ready);
+
+        supervisor.set_state(HostState::Ready);
+        assert!(supervisor.status().ready);
+
+        supervisor.crashed();
+        supervisor.set_state(HostState::Recovering { attempt: 0 });
+        let status = supervisor.status();
+        assert!(!status.ready);
+        assert!(status.last_crash.is_some());
+
+        supervisor.recovered();
+        let status = supervisor.status();
+        assert_eq!(status.state, HostState::Ready);
+        assert!(status.ready);
+        assert_eq!(status.restarts, 1);
+    }
+}
@@ -0,0 +1,245 @@
+//! 合成結果のキャッシュ。
+//!
+//! 合成に使うボイスプリセット、読み、出力形式などから作ったキーごとに、最終的なWAVをファイルに保存する。
+//! 合計サイズが上限を超えたら、一番長く使われていないものから消す。
+
+use crate::error::{Error, Result};
+
+use indexmap::IndexMap;
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::SystemTime,
+};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+#[derive(Debug)]
+pub struct SynthesisCache {
+    directory: PathBuf,
+    max_bytes: u64,
+    /// キーとファイルサイズ。最後に使ったものが末尾。
+    entries: Mutex<IndexMap<String, u64>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// `/synthesis_cache`で公開するキャッシュの状態。
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheStatus {
+    pub directory: PathBuf,
+    pub entries: usize,
+    pub bytes: u64,
+    pub max_bytes: u64,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl SynthesisCache {
+    /// `directory`にあるキャッシュを読み込む。前回までの使用順は更新日時から決める。
+    pub fn open(directory: &Path, max_bytes: u64) -> Result<Self> {
+        fs_err::create_dir_all(directory).map_err(|e| Error::CacheFailed(e.into()))?;
+
+        let mut files = Vec::new();
+        for entry in fs_err::read_dir(directory).map_err(|e| Error::CacheFailed(e.into()))? {
+            let entry = entry.map_err(|e| Error::CacheFailed(e.into()))?;
+            let path = entry.path();
+            if path.extension() != Some("wav".as_ref()) {
+                continue;
+            }
+            let Some(key) = path.file_stem().and_then(|x| x.to_str()) else {
+                continue;
+            };
+            let metadata = entry.metadata().map_err(|e| Error::CacheFailed(e.into()))?;
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            files.push((modified, key.to_string(), metadata.len()));
+        }
+        files.sort();
+
+        let cache = Self {
+            directory: directory.to_path_buf(),
+            max_bytes,
+            entries: Mutex::new(
+                files
+                    .into_iter()
+                    .map(|(_, key, size)| (key, size))
+                    .collect(),
+            ),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        };
+        for key in cache.evict() {
+            if let Err(e) = fs_err::remove_file(cache.path(&key)) {
+                warn!("Failed to remove cached audio {}: {}", key, e);
+            }
+        }
+        info!(
+            "Synthesis cache: {} ({} entries)",
+            directory.display(),
+            cache.entries.lock().unwrap().len()
+        );
+        Ok(cache)
+    }
+
+    /// `key`から決まるキャッシュのキー。
+    pub fn key(key: &impl Serialize) -> String {
+        let key = serde_json::to_vec(key).expect("cache keys are serializable");
+        Uuid::new_v5(&Uuid::NAMESPACE_OID, &key)
+            .simple()
+            .to_string()
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.directory.join(format!("{}.wav", key))
+    }
+
+    pub async fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let found = {
+            let mut entries = self.entries.lock().unwrap();
+            match entries.shift_remove(key) {
+                Some(size) => {
+                    entries.insert(key.to_string(), size);
+                    true
+                }
+                None => false,
+            }
+        };
+        if !found {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        let path = self.path(key);
+        match tokio::fs::read(&path).await {
+            Ok(wav) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                // 次に起動したときも使用順が分かるように、更新日時を使った時刻にする
+                if let Err(e) = std::fs::File::options()
+                    .write(true)
+                    .open(&path)
+                    .and_then(|file| file.set_modified(SystemTime::now()))
+                {
+                    warn!("Failed to touch {}: {}", path.display(), e);
+                }
+                Some(wav)
+            }
+            Err(e) => {
+                warn!("Failed to read cached audio {}: {}", path.display(), e);
+                self.entries.lock().unwrap().shift_remove(key);
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    pub async fn put(&self, key: &str, wav: &[u8]) -> Result<()> {
+        if wav.len() as u64 > self.max_bytes {
+            return Ok(());
+        }
+        let path = self.path(key);
+        // 書き込み中のファイルを読まないように、別のファイルに書いてから置き換える
+        let temporary_path = path.with_extension("wav.tmp");
+        tokio::fs::write(&temporary_path, wav)
+            .await
+            .map_err(|e| Error::CacheFailed(e.into()))?;
+        tokio::fs::rename(&temporary_path, &path)
+            .await
+            .map_err(|e| Error::CacheFailed(e.into()))?;
+
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), wav.len() as u64);
+        for key in self.evict() {
+            if let Err(e) = tokio::fs::remove_file(self.path(&key)).await {
+                warn!("Failed to remove cached audio {}: {}", key, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// 合計サイズが上限を超えないように、古いものから一覧から外してそのキーを返す。
+    ///
+    /// ファイルを消すのはロックを放してから呼び出し側で行う。
+    fn evict(&self) -> Vec<String> {
+        let mut entries = self.entries.lock().unwrap();
+        let mut bytes: u64 = entries.values().sum();
+        let mut evicted = Vec::new();
+        while bytes > self.max_bytes {
+            let Some((key, size)) = entries.shift_remove_index(0) else {
+                break;
+            };
+            bytes -= size;
+            evicted.push(key);
+        }
+        evicted
+    }
+
+    pub async fn clear(&self) -> Result<()> {
+        let keys: Vec<String> = self
+            .entries
+            .lock()
+            .unwrap()
+            .drain(..)
+            .map(|x| x.0)
+            .collect();
+        info!("Clearing {} cached audios", keys.len());
+        for key in keys {
+            match tokio::fs::remove_file(self.path(&key)).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(Error::CacheFailed(e.into())),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn status(&self) -> CacheStatus {
+        let entries = self.entries.lock().unwrap();
+        CacheStatus {
+            directory: self.directory.clone(),
+            entries: entries.len(),
+            bytes: entries.values().sum(),
+            max_bytes: self.max_bytes,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+pub fn default_directory() -> PathBuf {
+    process_path::get_executable_path()
+        .unwrap()
+        .parent()
+        .unwrap()
+        .join("synthesis_cache")
+}
+
+/// キャッシュが無効な場合は`None`のまま。
+pub static SYNTHESIS_CACHE: OnceCell<SynthesisCache> = OnceCell::new();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn evicts_least_recently_used_audio() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache = SynthesisCache::open(directory.path(), 8).unwrap();
+        cache.put("a", b"aaaa").await.unwrap();
+        cache.put("b", b"bbbb").await.unwrap();
+        assert!(cache.get("a").await.is_some());
+
+        cache.put("c", b"cccc").await.unwrap();
+        assert!(directory.path().join("a.wav").exists());
+        assert!(!directory.path().join("b.wav").exists());
+        assert!(cache.get("b").await.is_none());
+        assert_eq!(cache.get("c").await.unwrap(), b"cccc");
+        assert_eq!(cache.status().bytes, 8);
+    }
+}
@@ -0,0 +1,101 @@
+use std::time::Duration;
+use tracing::warn;
+
+/// ブリッジ呼び出しの制限時間と、ホストを再起動するまでに許すハングの回数。
+#[derive(Debug, Clone)]
+pub struct WatchdogOptions {
+    /// 通常のAPI呼び出しの制限時間。
+    pub call_timeout: Duration,
+    /// 音声の書き出し（`SaveAudioToFile`とファイルの書き込み待ち）の制限時間。
+    pub synthesis_timeout: Duration,
+    /// ホストの起動を待つ時間。
+    pub start_timeout: Duration,
+    /// 続けてこの回数タイムアウトしたらホストを終了させる。
+    pub hang_limit: u32,
+}
+
+impl Default for WatchdogOptions {
+    fn default() -> Self {
+        Self {
+            call_timeout: Duration::from_secs(30),
+            synthesis_timeout: Duration::from_secs(120),
+            start_timeout: Duration::from_secs(60),
+            hang_limit: 2,
+        }
+    }
+}
+
+/// 続けて起きたタイムアウトを数える。
+#[derive(Debug)]
+pub struct Watchdog {
+    options: WatchdogOptions,
+    consecutive_hangs: u32,
+}
+
+impl Watchdog {
+    pub fn new(options: WatchdogOptions) -> Self {
+        Self {
+            options,
+            consecutive_hangs: 0,
+        }
+    }
+
+    pub fn options(&self) -> &WatchdogOptions {
+        &self.options
+    }
+
+    pub fn record_success(&mut self) {
+        self.consecutive_hangs = 0;
+    }
+
+    /// タイムアウトを記録する。ホストを終了させるべきなら`true`を返す。
+    pub fn record_hang(&mut self, api: &str) -> bool {
+        self.consecutive_hangs += 1;
+        warn!(
+            "{} timed out ({}/{} consecutive hangs)",
+            api, self.consecutive_hangs, self.options.hang_limit
+        );
+        if self.consecutive_hangs >= self.options.hang_limit {
+            self.consecutive_hangs = 0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watchdog(hang_limit: u32) -> Watchdog {
+        Watchdog::new(WatchdogOptions {
+            hang_limit,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn escalates_after_consecutive_hangs() {
+        let mut watchdog = watchdog(3);
+        assert!(!watchdog.record_hang("Text="));
+        assert!(!watchdog.record_hang("Text="));
+        assert!(watchdog.record_hang("Text="));
+
+        // 終了させた後は数え直す
+        assert!(!watchdog.record_hang("Text="));
+        assert!(!watchdog.record_hang("Text="));
+        assert!(watchdog.record_hang("Text="));
+    }
+
+    #[test]
+    fn resets_after_success() {
+        let mut watchdog = watchdog(2);
+        assert!(!watchdog.record_hang("Text="));
+        watchdog.record_success();
+        assert!(!watchdog.record_hang("Text="));
+        watchdog.record_success();
+        assert!(!watchdog.record_hang("Text="));
+        assert!(watchdog.record_hang("Text="));
+    }
+}
@@ -0,0 +1,344 @@
+//! A.I.Voiceが書き出したWAVの読み書き。
+//!
+//! 書き出した周波数のまま返す場合にサンプルが変わらないように、PCMのバイト列のまま扱う。
+//! 周波数を変える場合だけ`f32`にして補間し、元の形式に戻す。
+
+use crate::error::{Error, Result};
+
+use anyhow::anyhow;
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// サンプルの形式。A.I.Voiceが書き出せる形式と、32ビット整数。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleFormat {
+    Int16,
+    Int24,
+    Int32,
+    Float32,
+}
+
+impl SampleFormat {
+    fn new(tag: u16, bits_per_sample: u16) -> Option<Self> {
+        match (tag, bits_per_sample) {
+            (WAVE_FORMAT_PCM, 16) => Some(SampleFormat::Int16),
+            (WAVE_FORMAT_PCM, 24) => Some(SampleFormat::Int24),
+            (WAVE_FORMAT_PCM, 32) => Some(SampleFormat::Int32),
+            (WAVE_FORMAT_IEEE_FLOAT, 32) => Some(SampleFormat::Float32),
+            _ => None,
+        }
+    }
+
+    fn tag(self) -> u16 {
+        match self {
+            SampleFormat::Float32 => WAVE_FORMAT_IEEE_FLOAT,
+            _ => WAVE_FORMAT_PCM,
+        }
+    }
+
+    /// 1サンプルのバイト数。
+    fn size(self) -> usize {
+        match self {
+            SampleFormat::Int16 => 2,
+            SampleFormat::Int24 => 3,
+            SampleFormat::Int32 | SampleFormat::Float32 => 4,
+        }
+    }
+
+    fn decode(self, sample: &[u8]) -> f32 {
+        match self {
+            SampleFormat::Int16 => i16::from_le_bytes([sample[0], sample[1]]) as f32 / 32768.0,
+            SampleFormat::Int24 => {
+                (i32::from_le_bytes([0, sample[0], sample[1], sample[2]]) >> 8) as f32 / 8388608.0
+            }
+            SampleFormat::Int32 => {
+                (i32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]) as f64
+                    / 2147483648.0) as f32
+            }
+            SampleFormat::Float32 => {
+                f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]])
+            }
+        }
+    }
+
+    fn encode(self, sample: f32, data: &mut Vec<u8>) {
+        match self {
+            SampleFormat::Int16 => {
+                data.extend_from_slice(&((sample.clamp(-1.0, 1.0) * 32767.0) as i16).to_le_bytes())
+            }
+            SampleFormat::Int24 => data.extend_from_slice(
+                &((sample.clamp(-1.0, 1.0) * 8388607.0) as i32).to_le_bytes()[0..3],
+            ),
+            SampleFormat::Int32 => data.extend_from_slice(
+                &((sample.clamp(-1.0, 1.0) as f64 * 2147483647.0) as i32).to_le_bytes(),
+            ),
+            SampleFormat::Float32 => data.extend_from_slice(&sample.to_le_bytes()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Wav {
+    pub format: SampleFormat,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// チャンネルごとに交互に並べたサンプル。
+    pub data: Vec<u8>,
+}
+
+impl Wav {
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let invalid = |reason: &str| Error::SynthesisFailed(anyhow!("Invalid WAV: {}", reason));
+        if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            return Err(invalid("not a RIFF WAVE file"));
+        }
+
+        let mut format = None;
+        let mut position = 12;
+        while position + 8 <= bytes.len() {
+            let size = u32_at(bytes, position + 4) as usize;
+            let start = position + 8;
+            let body = &bytes[start..start.saturating_add(size).min(bytes.len())];
+            match &bytes[position..position + 4] {
+                b"fmt " => {
+                    if body.len() < 16 {
+                        return Err(invalid("fmt chunk is too short"));
+                    }
+                    let mut tag = u16_at(body, 0);
+                    if tag == WAVE_FORMAT_EXTENSIBLE {
+                        // 拡張形式では、SubFormatのGUIDの先頭2バイトが形式
+                        if body.len() < 26 {
+                            return Err(invalid("fmt chunk is too short"));
+                        }
+                        tag = u16_at(body, 24);
+                    }
+                    let bits_per_sample = u16_at(body, 14);
+                    let sample_format =
+                        SampleFormat::new(tag, bits_per_sample).ok_or_else(|| {
+                            Error::SynthesisFailed(anyhow!(
+                                "Unsupported WAV format: tag {}, {} bits",
+                                tag,
+                                bits_per_sample
+                            ))
+                        })?;
+                    let channels = u16_at(body, 2);
+                    if channels == 0 {
+                        return Err(invalid("no channels"));
+                    }
+                    format = Some((sample_format, channels, u32_at(body, 4)));
+                }
+                b"data" => {
+                    let (format, channels, sample_rate) =
+                        format.ok_or_else(|| invalid("data chunk before fmt chunk"))?;
+                    let frame_size = format.size() * channels as usize;
+                    return Ok(Self {
+                        format,
+                        sample_rate,
+                        channels,
+                        data: body[..body.len() / frame_size * frame_size].to_vec(),
+                    });
+                }
+                _ => {}
+            }
+            // チャンクは2バイト境界に揃えられている
+            position = start.saturating_add(size + size % 2);
+        }
+        Err(invalid("no data chunk"))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let block_align = self.channels * self.format.size() as u16;
+        let mut bytes = Vec::with_capacity(44 + self.data.len());
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + self.data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&self.format.tag().to_le_bytes());
+        bytes.extend_from_slice(&self.channels.to_le_bytes());
+        bytes.extend_from_slice(&self.sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&(self.sample_rate * block_align as u32).to_le_bytes());
+        bytes.extend_from_slice(&block_align.to_le_bytes());
+        bytes.extend_from_slice(&(self.format.size() as u16 * 8).to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    fn frame_size(&self) -> usize {
+        self.format.size() * self.channels as usize
+    }
+
+    /// 前後に無音（秒）を足す。整数でも浮動小数点数でも無音は0なので、元のサンプルは変わらない。
+    pub fn pad_silence(&mut self, before: f32, after: f32) {
+        let silence = |seconds: f32| {
+            vec![0u8; (self.sample_rate as f32 * seconds) as usize * self.frame_size()]
+        };
+        let mut data = silence(before);
+        data.extend_from_slice(&self.data);
+        data.extend(silence(after));
+        self.data = data;
+    }
+
+    /// 周波数を変える。サンプルを`f32`にして補間し、元の形式に戻す。
+    pub fn resample(&mut self, sample_rate: u32) {
+        if self.sample_rate == sample_rate {
+            return;
+        }
+        let samples = self
+            .data
+            .chunks_exact(self.format.size())
+            .map(|sample| self.format.decode(sample))
+            .collect();
+        let samples =
+            wav_io::resample::linear(samples, self.channels, self.sample_rate, sample_rate);
+        let mut data = Vec::with_capacity(samples.len() * self.format.size());
+        for sample in samples {
+            self.format.encode(sample, &mut data);
+        }
+        self.data = data;
+        self.sample_rate = sample_rate;
+    }
+
+    /// モノラルをステレオにする。サンプルは左右に複製するだけなので変わらない。
+    pub fn mono_to_stereo(&mut self) {
+        if self.channels != 1 {
+            return;
+        }
+        let size = self.format.size();
+        let mut data = Vec::with_capacity(self.data.len() * 2);
+        for sample in self.data.chunks_exact(size) {
+            data.extend_from_slice(sample);
+            data.extend_from_slice(sample);
+        }
+        self.data = data;
+        self.channels = 2;
+    }
+}
+
+fn u16_at(bytes: &[u8], position: usize) -> u16 {
+    u16::from_le_bytes([bytes[position], bytes[position + 1]])
+}
+
+fn u32_at(bytes: &[u8], position: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[position],
+        bytes[position + 1],
+        bytes[position + 2],
+        bytes[position + 3],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wav(tag: u16, bits_per_sample: u16, sample_rate: u32, data: &[u8]) -> Vec<u8> {
+        Wav {
+            format: SampleFormat::new(tag, bits_per_sample).unwrap(),
+            sample_rate,
+            channels: 1,
+            data: data.to_vec(),
+        }
+        .to_bytes()
+    }
+
+    /// 無音を足してステレオにしても、元のサンプルのバイト列がそのまま残ること。
+    fn assert_passthrough(bytes: &[u8], sample_size: usize) {
+        let mut wav = Wav::parse(bytes).unwrap();
+        let original = wav.data.clone();
+        wav.pad_silence(0.001, 0.002);
+        wav.resample(8000);
+        wav.mono_to_stereo();
+
+        let output = Wav::parse(&wav.to_bytes()).unwrap();
+        let frame_size = sample_size * 2;
+        let (before, rest) = output.data.split_at(8 * frame_size);
+        let (samples, after) = rest.split_at(original.len() * 2);
+        assert!(before.iter().all(|x| *x == 0));
+        assert!(after.iter().all(|x| *x == 0));
+        assert_eq!(after.len(), 16 * frame_size);
+        let left: Vec<u8> = samples
+            .chunks_exact(frame_size)
+            .flat_map(|frame| frame[..sample_size].to_vec())
+            .collect();
+        let right: Vec<u8> = samples
+            .chunks_exact(frame_size)
+            .flat_map(|frame| frame[sample_size..].to_vec())
+            .collect();
+        assert_eq!(left, original);
+        assert_eq!(right, original);
+    }
+
+    #[test]
+    fn passes_int24_samples_through() {
+        let data = [
+            0x01, 0x02, 0x03, 0xFF, 0xFF, 0x7F, 0x00, 0x00, 0x80, 0x55, 0xAA, 0x55,
+        ];
+        let bytes = wav(WAVE_FORMAT_PCM, 24, 8000, &data);
+        assert_eq!(Wav::parse(&bytes).unwrap().format, SampleFormat::Int24);
+        assert_passthrough(&bytes, 3);
+    }
+
+    #[test]
+    fn passes_float32_samples_through() {
+        // 範囲外の値も変換しないのでそのまま残る
+        let data: Vec<u8> = [0.123_456_79f32, -1.5, 1e-30, 0.0]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect();
+        let bytes = wav(WAVE_FORMAT_IEEE_FLOAT, 32, 8000, &data);
+        assert_eq!(Wav::parse(&bytes).unwrap().format, SampleFormat::Float32);
+        assert_passthrough(&bytes, 4);
+    }
+
+    #[test]
+    fn parses_extensible_format() {
+        let mut bytes = b"RIFF\0\0\0\0WAVEfmt ".to_vec();
+        bytes.extend_from_slice(&40u32.to_le_bytes());
+        bytes.extend_from_slice(&WAVE_FORMAT_EXTENSIBLE.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&44100u32.to_le_bytes());
+        bytes.extend_from_slice(&(44100u32 * 3).to_le_bytes());
+        bytes.extend_from_slice(&3u16.to_le_bytes());
+        bytes.extend_from_slice(&24u16.to_le_bytes());
+        bytes.extend_from_slice(&22u16.to_le_bytes());
+        bytes.extend_from_slice(&24u16.to_le_bytes());
+        bytes.extend_from_slice(&4u32.to_le_bytes());
+        bytes.extend_from_slice(&WAVE_FORMAT_PCM.to_le_bytes());
+        bytes.extend_from_slice(&[0; 14]);
+        bytes.extend_from_slice(b"LIST");
+        bytes.extend_from_slice(&3u32.to_le_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&6u32.to_le_bytes());
+        bytes.extend_from_slice(&[1, 2, 3, 4, 5, 6]);
+
+        let wav = Wav::parse(&bytes).unwrap();
+        assert_eq!(wav.format, SampleFormat::Int24);
+        assert_eq!(wav.sample_rate, 44100);
+        assert_eq!(wav.channels, 1);
+        assert_eq!(wav.data, [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn resamples_in_the_same_format() {
+        let data: Vec<u8> = (0..80i16).flat_map(|x| (x * 100).to_le_bytes()).collect();
+        let mut wav = Wav::parse(&wav(WAVE_FORMAT_PCM, 16, 8000, &data)).unwrap();
+        wav.resample(16000);
+        assert_eq!(wav.format, SampleFormat::Int16);
+        assert_eq!(wav.sample_rate, 16000);
+        assert_eq!(wav.data.len(), data.len() * 2);
+    }
+
+    #[test]
+    fn rejects_unsupported_wav() {
+        let mut bytes = wav(WAVE_FORMAT_PCM, 16, 8000, &[0, 0]);
+        bytes[34] = 8;
+        assert!(Wav::parse(&bytes).is_err());
+        assert!(Wav::parse(b"RIFF\0\0\0\0WAVE").is_err());
+        assert!(Wav::parse(b"not a wav file").is_err());
+    }
+}
@@ -0,0 +1,224 @@
+use crate::aivoice::{AiVoice, AiVoiceOptions};
+use crate::backend::{
+    protocol::{self, Call, Outcome, Reply, Request, Response, RpcError},
+    BackendKind,
+};
+use crate::error::{Error, Result};
+
+use anyhow::anyhow;
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{Ipv4Addr, SocketAddr, TcpListener, ToSocketAddrs},
+    sync::{mpsc, Mutex},
+    thread,
+};
+use tracing::{info, warn};
+
+/// `reader`から1行ずつリクエストを読み、`dispatch`で処理した結果を`writer`に書く。
+///
+/// リクエストは順番に処理するが、`TerminateHost`だけは前のリクエストを待たずに別のスレッドで処理する。
+/// ハングしたA.I.Voiceを終了させるためのもので、前のリクエストは終了させるまで応答を返さない。
+/// `reader`が閉じられるまで続ける。
+pub fn serve(
+    dispatch: &(impl Fn(Call) -> Result<Reply> + Sync),
+    reader: impl BufRead,
+    writer: impl Write + Send,
+) -> Result<()> {
+    let writer = Mutex::new(writer);
+    let writer = &writer;
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::channel::<Request>();
+        let worker = scope.spawn(move || {
+            for request in receiver {
+                respond(writer, handle(dispatch, request))?;
+            }
+            Ok(())
+        });
+
+        for line in reader.lines() {
+            let line = line.map_err(|e| Error::WorkerFailed(e.into()))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<Request>(&line) {
+                Ok(request) if matches!(request.call, Call::TerminateHost) => {
+                    scope.spawn(move || {
+                        if let Err(e) = respond(writer, handle(dispatch, request)) {
+                            warn!("Failed to respond to TerminateHost: {}", e);
+                        }
+                    });
+                }
+                Ok(request) => {
+                    // 受け取る側が止まっているのは書き込みに失敗した場合なので、その結果を返す
+                    if sender.send(request).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => respond(
+                    writer,
+                    Response::new(None, Outcome::Error(RpcError::parse_error(e))),
+                )?,
+            }
+        }
+
+        drop(sender);
+        worker.join().unwrap()
+    })
+}
+
+fn handle(dispatch: &impl Fn(Call) -> Result<Reply>, request: Request) -> Response {
+    Response::new(
+        Some(request.id),
+        match dispatch(request.call) {
+            Ok(reply) => Outcome::Result(reply),
+            Err(e) => {
+                warn!("Request {} failed: {}", request.id, e);
+                Outcome::Error(RpcError::from(&e))
+            }
+        },
+    )
+}
+
+fn respond(writer: &Mutex<impl Write>, response: Response) -> Result<()> {
+    let mut response =
+        serde_json::to_string(&response).map_err(|e| Error::WorkerFailed(e.into()))?;
+    response.push('\n');
+    let mut writer = writer.lock().unwrap();
+    writer
+        .write_all(response.as_bytes())
+        .and_then(|_| writer.flush())
+        .map_err(|e| Error::WorkerFailed(e.into()))
+}
+
+/// `--listen`の値から待ち受けるアドレスを決める。ポート番号だけの場合は127.0.0.1で待ち受ける。
+///
+/// ワーカーは認証しないので、接続できれば誰でもA.I.Voiceを終了させたりフレーズ辞書を書き換えたりできる。
+/// そのため、ループバック以外のアドレスは`allow_remote`の場合だけ許す。
+pub fn listen_address(listen: &str, allow_remote: bool) -> Result<SocketAddr> {
+    let address = match listen.parse::<u16>() {
+        Ok(port) => SocketAddr::from((Ipv4Addr::LOCALHOST, port)),
+        Err(_) => listen
+            .to_socket_addrs()
+            .map_err(|e| Error::WorkerFailed(e.into()))?
+            .next()
+            .ok_or_else(|| Error::WorkerFailed(anyhow!("アドレスを解決できません：{}", listen)))?,
+    };
+    if !address.ip().is_loopback() {
+        if !allow_remote {
+            return Err(Error::WorkerFailed(anyhow!(
+                "{} は他のマシンから接続できるアドレスです。ワーカーは認証しないので、許可する場合は --allow-remote を指定してください",
+                address
+            )));
+        }
+        warn!(
+            "Worker is listening on {} without authentication, anyone who can connect can control A.I.Voice",
+            address
+        );
+    }
+    Ok(address)
+}
+
+/// `address`で待ち受け、接続ごとに順番に`serve`する。
+pub fn serve_tcp(
+    dispatch: &(impl Fn(Call) -> Result<Reply> + Sync),
+    address: SocketAddr,
+) -> Result<()> {
+    let listener = TcpListener::bind(address).map_err(|e| Error::WorkerFailed(e.into()))?;
+    info!("Worker listening on {}", address);
+    for stream in listener.incoming() {
+        let stream = stream.map_err(|e| Error::WorkerFailed(e.into()))?;
+        let peer = stream
+            .peer_addr()
+            .map_err(|e| Error::WorkerFailed(e.into()))?;
+        info!("Worker client connected: {}", peer);
+        let reader = BufReader::new(
+            stream
+                .try_clone()
+                .map_err(|e| Error::WorkerFailed(e.into()))?,
+        );
+        if let Err(e) = serve(dispatch, reader, stream) {
+            warn!("Worker client {} disconnected: {}", peer, e);
+        } else {
+            info!("Worker client disconnected: {}", peer);
+        }
+    }
+
+    Ok(())
+}
+
+/// ワーカーとして起動する。`listen`が`None`の場合は標準入出力で通信する。
+pub async fn run(
+    options: AiVoiceOptions,
+    listen: Option<String>,
+    allow_remote: bool,
+) -> Result<()> {
+    if options.backend.kind == BackendKind::Remote {
+        return Err(Error::WorkerFailed(anyhow!(
+            "ワーカーのバックエンドに remote は指定できません"
+        )));
+    }
+    let listen = listen
+        .map(|listen| listen_address(&listen, allow_remote))
+        .transpose()?;
+    let mut aivoice = AiVoice::create(&options).await?;
+    if aivoice.host().editor_settings_path().is_some() {
+        aivoice.prepare_editor().await?;
+    }
+
+    // 読み書きは別のスレッドで行い、ホストの呼び出しはホストのスレッドに渡す
+    let host = aivoice.host().clone();
+    let host_thread = aivoice.host_thread().clone();
+    let save_audio_timeout = options.watchdog.synthesis_timeout;
+    let dispatch = move |call: Call| {
+        let host = host.clone();
+        // ホストのスレッドはハングした呼び出しで塞がっていることがあるので、プロセスの終了はこのスレッドで行う
+        if matches!(call, Call::TerminateHost) {
+            return protocol::dispatch(host.as_ref(), call, save_audio_timeout);
+        }
+        host_thread
+            .run(move || protocol::dispatch(host.as_ref(), call, save_audio_timeout))
+            .blocking_recv()
+            .unwrap_or_else(|_| Err(Error::ApiFailed("panicked".to_string())))
+    };
+    let worker = tokio::task::spawn_blocking(move || match listen {
+        Some(address) => serve_tcp(&dispatch, address),
+        None => serve(&dispatch, std::io::stdin().lock(), std::io::stdout()),
+    });
+
+    let result = tokio::select! {
+        result = worker => result.map_err(|e| Error::WorkerFailed(e.into()))?,
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    };
+
+    info!("Shutting down worker...");
+    aivoice.shutdown().await?;
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listens_on_loopback_by_default() {
+        assert_eq!(
+            listen_address("50202", false).unwrap(),
+            "127.0.0.1:50202".parse().unwrap()
+        );
+        assert!(listen_address("127.0.0.1:50202", false).is_ok());
+        assert!(listen_address("[::1]:50202", false).is_ok());
+    }
+
+    #[test]
+    fn requires_allow_remote_for_other_addresses() {
+        assert!(matches!(
+            listen_address("0.0.0.0:50202", false),
+            Err(Error::WorkerFailed(_))
+        ));
+        assert_eq!(
+            listen_address("0.0.0.0:50202", true).unwrap(),
+            "0.0.0.0:50202".parse().unwrap()
+        );
+    }
+}